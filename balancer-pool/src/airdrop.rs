@@ -0,0 +1,198 @@
+//! Airdrops to liquidity providers: the controller distributes a token pro-rata to pool share balances
+//! at the time the airdrop is registered, and every LP claims its part.
+//! Instead of copying all balances, every account checkpoints its balance before the first change
+//! after a new airdrop, so the balance at any airdrop can be looked up later.
+
+use near_lib::math::mul_div;
+
+use crate::*;
+
+/// Airdrop of `total_amount` of `token` to the holders of `snapshot_supply` pool shares.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Airdrop {
+    token: AccountId,
+    total_amount: Balance,
+    /// Pool shares outside of this contract at the time of the airdrop.
+    snapshot_supply: Balance,
+    claimed_amount: Balance,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AirdropView {
+    pub token: AccountId,
+    pub total_amount: U128,
+    pub snapshot_supply: U128,
+    pub claimed_amount: U128,
+}
+
+impl From<Airdrop> for AirdropView {
+    fn from(airdrop: Airdrop) -> Self {
+        Self {
+            token: airdrop.token,
+            total_amount: airdrop.total_amount.into(),
+            snapshot_supply: airdrop.snapshot_supply.into(),
+            claimed_amount: airdrop.claimed_amount.into(),
+        }
+    }
+}
+
+#[ext_contract(ext_airdrop_self)]
+pub trait AirdropCallbacks {
+    fn on_airdrop_claim(&mut self, airdrop_id: u64, account_id: AccountId, amount: U128) -> bool;
+}
+
+#[near_bindgen]
+impl BPool {
+    pub fn get_number_of_airdrops(&self) -> u64 {
+        self.airdrops.len()
+    }
+
+    pub fn get_airdrop(&self, airdrop_id: u64) -> Option<AirdropView> {
+        self.airdrops.get(airdrop_id).map(|airdrop| airdrop.into())
+    }
+
+    /// Returns how much of the airdrop given account can claim, zero if it was already claimed.
+    pub fn get_airdrop_claimable(&self, airdrop_id: u64, account_id: AccountId) -> U128 {
+        if self
+            .airdrop_claims
+            .contains(&(airdrop_id, account_id.clone()))
+        {
+            return 0.into();
+        }
+        let airdrop = self.airdrops.get(airdrop_id).expect("ERR_NO_AIRDROP");
+        self.internal_airdrop_amount(&airdrop, airdrop_id, &account_id)
+            .into()
+    }
+
+    /// Registers airdrop of `total_amount` of `token` to current holders of pool shares, taking
+    /// the amount from the controller's deposit. Returns id of the airdrop.
+    pub fn register_airdrop(&mut self, token: AccountId, total_amount: U128) -> u64 {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        let total_amount: Balance = total_amount.into();
+        assert!(total_amount > 0, "ERR_ZERO_AMOUNT");
+        // Shares held by the pool itself (collected exit fees) don't receive the airdrop.
+        let snapshot_supply = self.token.total_supply
+            - self
+                .token
+                .accounts
+                .get(&env::current_account_id())
+                .unwrap_or(0);
+        assert!(snapshot_supply > 0, "ERR_NO_SHARES");
+        let airdrop_id = self.airdrops.len();
+        self.airdrops.push(&Airdrop {
+            token: token.clone(),
+            total_amount,
+            snapshot_supply,
+            claimed_amount: 0,
+        });
+        self.pull_underlying(&token, &self.controller.clone(), total_amount);
+        airdrop_id
+    }
+
+    /// Sends the sender's part of the airdrop, pro-rata to its pool shares at the time of the airdrop.
+    pub fn claim_airdrop(&mut self, airdrop_id: u64) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let mut airdrop = self.airdrops.get(airdrop_id).expect("ERR_NO_AIRDROP");
+        assert!(
+            self.airdrop_claims
+                .insert(&(airdrop_id, account_id.clone())),
+            "ERR_AIRDROP_CLAIMED"
+        );
+        let amount = self.internal_airdrop_amount(&airdrop, airdrop_id, &account_id);
+        assert!(amount > 0, "ERR_NOTHING_TO_CLAIM");
+        airdrop.claimed_amount += amount;
+        self.airdrops.replace(airdrop_id, &airdrop);
+        ext_fungible_token::ft_transfer(
+            account_id.clone(),
+            amount.into(),
+            None,
+            &airdrop.token,
+            ONE_YOCTO,
+            gas::FT_TRANSFER,
+        )
+        .then(ext_airdrop_self::on_airdrop_claim(
+            airdrop_id,
+            account_id,
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            gas::ON_PUSH_CALLBACK,
+        ));
+        amount.into()
+    }
+
+    /// Callback after sending the claimed airdrop. If it failed, the claim is reverted so it can be retried.
+    pub fn on_airdrop_claim(
+        &mut self,
+        airdrop_id: u64,
+        account_id: AccountId,
+        amount: U128,
+    ) -> bool {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        if is_promise_success() {
+            return true;
+        }
+        let mut airdrop = self.airdrops.get(airdrop_id).unwrap();
+        airdrop.claimed_amount -= amount.0;
+        self.airdrops.replace(airdrop_id, &airdrop);
+        self.airdrop_claims.remove(&(airdrop_id, account_id));
+        false
+    }
+}
+
+impl BPool {
+    /// Records balance of pool shares of the account if it is about to change for the first time
+    /// since the last airdrop. Must be called before every change of the balance.
+    pub(crate) fn internal_checkpoint_shares(&mut self, account_id: &AccountId) {
+        let next_airdrop_id = self.airdrops.len();
+        if next_airdrop_id == 0 || account_id == &env::current_account_id() {
+            return;
+        }
+        let mut checkpoints = self.share_checkpoints.get(account_id).unwrap_or_default();
+        if checkpoints
+            .last()
+            .map(|(id, _)| *id < next_airdrop_id)
+            .unwrap_or(true)
+        {
+            let balance = self.token.accounts.get(account_id).unwrap_or(0);
+            checkpoints.push((next_airdrop_id, balance));
+            self.share_checkpoints.insert(account_id, &checkpoints);
+        }
+    }
+
+    /// Balance of pool shares of the account at the time of given airdrop.
+    fn internal_share_balance_at(&self, account_id: &AccountId, airdrop_id: u64) -> Balance {
+        if account_id == &env::current_account_id() {
+            return 0;
+        }
+        // The first checkpoint after the airdrop holds the balance the account had during it.
+        self.share_checkpoints
+            .get(account_id)
+            .and_then(|checkpoints| {
+                checkpoints
+                    .into_iter()
+                    .find(|(id, _)| *id > airdrop_id)
+                    .map(|(_, balance)| balance)
+            })
+            .unwrap_or_else(|| self.token.accounts.get(account_id).unwrap_or(0))
+    }
+
+    fn internal_airdrop_amount(
+        &self,
+        airdrop: &Airdrop,
+        airdrop_id: u64,
+        account_id: &AccountId,
+    ) -> Balance {
+        let shares = self.internal_share_balance_at(account_id, airdrop_id);
+        mul_div(airdrop.total_amount, shares, airdrop.snapshot_supply)
+    }
+}
@@ -3,6 +3,8 @@ use near_sdk::Balance;
 pub type Weight = u128;
 
 pub const BONE: Balance = 1_000_000_000_000_000_000_000_000;
+/// Number of decimals in `BONE`. Balances of all tokens are normalized to it in the pool math.
+pub const BONE_DECIMALS: u8 = 24;
 
 pub const MIN_BOUND_TOKENS: usize = 2;
 pub const MAX_BOUND_TOKENS: usize = 8;
@@ -10,10 +12,15 @@ pub const MAX_BOUND_TOKENS: usize = 8;
 pub const MIN_FEE: Balance = BONE / 1_000_000;
 pub const MAX_FEE: Balance = BONE / 10;
 pub const EXIT_FEE: Balance = 0;
+pub const MAX_EXIT_FEE: Balance = BONE / 100;
+/// Maximum share of the swap fee that goes to the factory.
+pub const MAX_PROTOCOL_FEE: Balance = BONE / 2;
 
 pub const MIN_WEIGHT: Weight = BONE;
 pub const MAX_WEIGHT: Weight = BONE * 50;
 pub const MAX_TOTAL_WEIGHT: Weight = BONE * 50;
+/// Weight of each token in the pools created via `new_simple`.
+pub const SIMPLE_POOL_WEIGHT: Weight = MAX_TOTAL_WEIGHT / 2;
 pub const MIN_BALANCE: Balance = BONE / 1_000_000_000_000;
 
 pub const INIT_POOL_SUPPLY: Balance = BONE * 100;
@@ -25,15 +32,31 @@ pub const MAX_IN_RATIO: Balance = BONE / 2;
 pub const MAX_OUT_RATIO: Balance = BONE / 3 + 1;
 
 pub const NO_DEPOSIT: Balance = 0;
+/// Attached to `ft_transfer` of the bound tokens, as NEP-141 requires.
+pub const ONE_YOCTO: Balance = 1;
+/// Price per 1 byte of storage, that the controller stakes for bound tokens.
+pub const STORAGE_PRICE_PER_BYTE: Balance = 10_000_000_000_000_000_000;
+/// Time between staging new code of the pool and deploying it: 1 day.
+pub const UPGRADE_STAGING_DURATION: u64 = 24 * 60 * 60 * 1_000_000_000;
 
 pub mod gas {
     pub const BASE_GAS: u64 = 20_000_000_000_000;
 
-    pub const NEP21_TRANSFER: u64 = BASE_GAS;
+    pub const FT_TRANSFER: u64 = BASE_GAS;
 
-    pub const ON_PULL_CALLBACK: u64 = BASE_GAS;
+    pub const ON_PUSH_CALLBACK: u64 = BASE_GAS;
 
-    pub const NEP21_TRANSFER_FROM: u64 = BASE_GAS;
+    pub const FT_METADATA: u64 = BASE_GAS;
 
-    pub const ON_PUSH_CALLBACK: u64 = BASE_GAS;
+    pub const ON_METADATA_CALLBACK: u64 = BASE_GAS;
+
+    pub const MIGRATE: u64 = 2 * BASE_GAS;
+
+    pub const FLASH_SWAP_RECEIVER: u64 = 2 * BASE_GAS;
+
+    pub const ON_FLASH_SWAP_REPAY_CALLBACK: u64 = BASE_GAS;
+
+    /// Covers the receiver call and the final callback.
+    pub const ON_FLASH_SWAP_PUSH_CALLBACK: u64 =
+        BASE_GAS + FLASH_SWAP_RECEIVER + ON_FLASH_SWAP_REPAY_CALLBACK;
 }
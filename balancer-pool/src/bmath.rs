@@ -1,6 +1,7 @@
 use near_sdk::Balance;
 
 use crate::bconst::{Weight, BONE};
+use crate::bnum::{bdiv, bmul, bpow};
 
 /**********************************************************************************************
 // calcSpotPrice                                                                             //
@@ -18,9 +19,194 @@ pub fn calc_spot_price(
     weight_out: Weight,
     swap_fee: Balance,
 ) -> Balance {
-    let numer = balance_in / weight_in;
-    let denom = balace_out / weight_out;
-    let ratio = numer / denom;
-    let scale = BONE / (BONE - swap_fee);
-    ratio * scale
+    let numer = bdiv(balance_in, weight_in);
+    let denom = bdiv(balace_out, weight_out);
+    let ratio = bdiv(numer, denom);
+    let scale = bdiv(BONE, BONE - swap_fee);
+    bmul(ratio, scale)
+}
+
+/**********************************************************************************************
+// calcOutGivenIn                                                                            //
+// aO = tokenAmountOut                                                                       //
+// bO = tokenBalanceOut                                                                      //
+// bI = tokenBalanceIn              /      /            bI             \    (wI / wO) \      //
+// aI = tokenAmountIn    aO = bO * |  1 - | --------------------------  | ^            |     //
+// wI = tokenWeightIn               \      \ ( bI + ( aI * ( 1 - sF )) /              /      //
+// wO = tokenWeightOut                                                                       //
+// sF = swapFee                                                                              //
+**********************************************************************************************/
+pub fn calc_out_given_in(
+    balance_in: Balance,
+    weight_in: Weight,
+    balance_out: Balance,
+    weight_out: Weight,
+    amount_in: Balance,
+    swap_fee: Balance,
+) -> Balance {
+    let weight_ratio = bdiv(weight_in, weight_out);
+    let adjusted_in = bmul(amount_in, BONE - swap_fee);
+    let y = bdiv(balance_in, balance_in + adjusted_in);
+    let foo = bpow(y, weight_ratio);
+    let bar = BONE - foo;
+    bmul(balance_out, bar)
+}
+
+/**********************************************************************************************
+// calcInGivenOut                                                                            //
+// aI = tokenAmountIn                                                                        //
+// bO = tokenBalanceOut               /  /     bO      \    (wO / wI)      \                 //
+// bI = tokenBalanceIn          bI * |  | ------------  | ^            - 1  |                //
+// aO = tokenAmountOut    aI =        \  \ ( bO - aO ) /                   /                 //
+// wI = tokenWeightIn           --------------------------------------------                 //
+// wO = tokenWeightOut                          ( 1 - sF )                                   //
+// sF = swapFee                                                                              //
+**********************************************************************************************/
+pub fn calc_in_given_out(
+    balance_in: Balance,
+    weight_in: Weight,
+    balance_out: Balance,
+    weight_out: Weight,
+    amount_out: Balance,
+    swap_fee: Balance,
+) -> Balance {
+    let weight_ratio = bdiv(weight_out, weight_in);
+    let diff = balance_out - amount_out;
+    let y = bdiv(balance_out, diff);
+    let foo = bpow(y, weight_ratio) - BONE;
+    bdiv(bmul(balance_in, foo), BONE - swap_fee)
+}
+
+/**********************************************************************************************
+// calcPoolOutGivenSingleIn                                                                  //
+// pAo = poolAmountOut         /                                              \              //
+// tAi = tokenAmountIn        ///      /     //    wI \      \\       \     wI \             //
+// wI = tokenWeightIn        //| tAi *| 1 - || 1 - --  | * sF || + tBi \    --  \            //
+// tW = totalWeight     pAo=||  \      \     \\    tW /      //         | ^ tW   | * pS - pS //
+// tBi = tokenBalanceIn      \\  ------------------------------------- /        /            //
+// pS = poolSupply            \\                    tBi               /        /             //
+// sF = swapFee                \                                              /              //
+**********************************************************************************************/
+pub fn calc_pool_out_given_single_in(
+    balance_in: Balance,
+    weight_in: Weight,
+    pool_supply: Balance,
+    total_weight: Weight,
+    amount_in: Balance,
+    swap_fee: Balance,
+) -> Balance {
+    // Charge the trading fee for the proportion of tokenAi
+    // which is implicitly traded to the other pool tokens.
+    let normalized_weight = bdiv(weight_in, total_weight);
+    let zaz = bmul(BONE - normalized_weight, swap_fee);
+    let amount_in_after_fee = bmul(amount_in, BONE - zaz);
+    let new_balance_in = balance_in + amount_in_after_fee;
+    let token_in_ratio = bdiv(new_balance_in, balance_in);
+    let pool_ratio = bpow(token_in_ratio, normalized_weight);
+    let new_pool_supply = bmul(pool_ratio, pool_supply);
+    new_pool_supply - pool_supply
+}
+
+/**********************************************************************************************
+// calcSingleInGivenPoolOut                                                                  //
+// tAi = tokenAmountIn              //(pS + pAo)\     /    1    \\                           //
+// pS = poolSupply                 || ---------  | ^ | --------- || * bI - bI                //
+// pAo = poolAmountOut              \\    pS    /     \(wI / tW)//                           //
+// bI = balanceIn          tAi =  --------------------------------------------               //
+// wI = weightIn                              /      wI  \                                   //
+// tW = totalWeight                          |  1 - ----  |  * sF                            //
+// sF = swapFee                               \      tW  /                                   //
+**********************************************************************************************/
+pub fn calc_single_in_given_pool_out(
+    balance_in: Balance,
+    weight_in: Weight,
+    pool_supply: Balance,
+    total_weight: Weight,
+    pool_amount_out: Balance,
+    swap_fee: Balance,
+) -> Balance {
+    let normalized_weight = bdiv(weight_in, total_weight);
+    let new_pool_supply = pool_supply + pool_amount_out;
+    let pool_ratio = bdiv(new_pool_supply, pool_supply);
+    let boo = bdiv(BONE, normalized_weight);
+    let token_in_ratio = bpow(pool_ratio, boo);
+    let new_balance_in = bmul(token_in_ratio, balance_in);
+    let amount_in_after_fee = new_balance_in - balance_in;
+    // Do reverse order of fees charged in joinswap_ExternAmountIn, this way
+    //     ``` pAo == joinswap_ExternAmountIn(Ti, joinswap_PoolAmountOut(pAo, Ti)) ```
+    let zar = bmul(BONE - normalized_weight, swap_fee);
+    bdiv(amount_in_after_fee, BONE - zar)
+}
+
+/**********************************************************************************************
+// calcSingleOutGivenPoolIn                                                                  //
+// tAo = tokenAmountOut            /      /                                             \\   //
+// bO = tokenBalanceOut           /      // pS - (pAi * (1 - eF)) \     /    1    \      \\  //
+// pAi = poolAmountIn            | bO - || ----------------------- | ^ | --------- | * b0 || //
+// ps = poolSupply                \      \\          pS           /     \(wO / tW)/      //  //
+// wI = tokenWeightIn      tAo =   \      \                                             //   //
+// tW = totalWeight                    /     /      wO \       \                             //
+// sF = swapFee                    *  | 1 - |  1 - ---- | * sF  |                            //
+// eF = exitFee                        \     \      tW /       /                             //
+**********************************************************************************************/
+pub fn calc_single_out_given_pool_in(
+    balance_out: Balance,
+    weight_out: Weight,
+    pool_supply: Balance,
+    total_weight: Weight,
+    pool_amount_in: Balance,
+    swap_fee: Balance,
+    exit_fee: Balance,
+) -> Balance {
+    let normalized_weight = bdiv(weight_out, total_weight);
+    // Charge exit fee on the pool token side
+    // pAiAfterExitFee = pAi*(1-exitFee)
+    let pool_amount_in_after_exit_fee = bmul(pool_amount_in, BONE - exit_fee);
+    let new_pool_supply = pool_supply - pool_amount_in_after_exit_fee;
+    let pool_ratio = bdiv(new_pool_supply, pool_supply);
+    // newBalTo = poolRatio^(1/weightTo) * balTo;
+    let token_out_ratio = bpow(pool_ratio, bdiv(BONE, normalized_weight));
+    let new_balance_out = bmul(token_out_ratio, balance_out);
+    let amount_out_before_swap_fee = balance_out - new_balance_out;
+    // charge swap fee on the output token side
+    // tAo = tAoBeforeSwapFee * (1 - (1-weightTo) * swapFee)
+    let zaz = bmul(BONE - normalized_weight, swap_fee);
+    bmul(amount_out_before_swap_fee, BONE - zaz)
+}
+
+/**********************************************************************************************
+// calcPoolInGivenSingleOut                                                                  //
+// pAi = poolAmountIn               // /               tAo             \\     / wO \     \   //
+// bO = tokenBalanceOut            // | bO - -------------------------- |\   | ---- |     \  //
+// tAo = tokenAmountOut      pS - ||   \     1 - ((1 - (tO / tW)) * sF)/  | ^ \ tW /  * pS | //
+// ps = poolSupply                 \\ -----------------------------------/                /  //
+// wO = tokenWeightOut  pAi =       \\               bO                 /                /   //
+// tW = totalWeight           -------------------------------------------------------------  //
+// sF = swapFee                                        ( 1 - eF )                            //
+// eF = exitFee                                                                              //
+**********************************************************************************************/
+pub fn calc_pool_in_given_single_out(
+    balance_out: Balance,
+    weight_out: Weight,
+    pool_supply: Balance,
+    total_weight: Weight,
+    amount_out: Balance,
+    swap_fee: Balance,
+    exit_fee: Balance,
+) -> Balance {
+    // charge swap fee on the output token side
+    let normalized_weight = bdiv(weight_out, total_weight);
+    // tAoBeforeSwapFee = tAo / (1 - (1-weightTo) * swapFee) ;
+    let zoo = BONE - normalized_weight;
+    let zar = bmul(zoo, swap_fee);
+    let amount_out_before_swap_fee = bdiv(amount_out, BONE - zar);
+    let new_balance_out = balance_out - amount_out_before_swap_fee;
+    let token_out_ratio = bdiv(new_balance_out, balance_out);
+    // newPoolSupply = (ratioTo ^ weightTo) * poolSupply;
+    let pool_ratio = bpow(token_out_ratio, normalized_weight);
+    let new_pool_supply = bmul(pool_ratio, pool_supply);
+    let pool_amount_in_after_exit_fee = pool_supply - new_pool_supply;
+    // charge exit fee on the pool token side
+    // pAi = pAiAfterExitFee/(1-exitFee)
+    bdiv(pool_amount_in_after_exit_fee, BONE - exit_fee)
 }
@@ -0,0 +1,105 @@
+use near_lib::math::U256;
+use near_sdk::Balance;
+
+use crate::bconst::{BONE, BONE_DECIMALS, BPOW_PRECISION, MAX_BPOW_BASE, MIN_BPOW_BASE};
+
+/// Multiplies two BONE-scaled numbers, rounding half up.
+pub fn bmul(a: Balance, b: Balance) -> Balance {
+    ((U256::from(a) * U256::from(b) + U256::from(BONE / 2)) / U256::from(BONE)).as_u128()
+}
+
+/// Divides two BONE-scaled numbers, rounding half up.
+pub fn bdiv(a: Balance, b: Balance) -> Balance {
+    assert_ne!(b, 0, "ERR_DIV_ZERO");
+    ((U256::from(a) * U256::from(BONE) + U256::from(b / 2)) / U256::from(b)).as_u128()
+}
+
+fn btoi(a: Balance) -> Balance {
+    a / BONE
+}
+
+fn bfloor(a: Balance) -> Balance {
+    btoi(a) * BONE
+}
+
+/// Returns `|a - b|` and whether `a < b`.
+fn bsub_sign(a: Balance, b: Balance) -> (Balance, bool) {
+    if a >= b {
+        (a - b, false)
+    } else {
+        (b - a, true)
+    }
+}
+
+/// Raises BONE-scaled `a` to the integer power `n`.
+fn bpowi(mut a: Balance, mut n: u128) -> Balance {
+    let mut z = if n % 2 != 0 { a } else { BONE };
+    n /= 2;
+    while n != 0 {
+        a = bmul(a, a);
+        if n % 2 != 0 {
+            z = bmul(z, a);
+        }
+        n /= 2;
+    }
+    z
+}
+
+/// Raises BONE-scaled `base` to the BONE-scaled power `exp`.
+/// Whole part of the exponent is computed exactly, the fraction via `bpow_approx`.
+pub fn bpow(base: Balance, exp: Balance) -> Balance {
+    assert!(base >= MIN_BPOW_BASE, "ERR_BPOW_BASE_TOO_LOW");
+    assert!(base <= MAX_BPOW_BASE, "ERR_BPOW_BASE_TOO_HIGH");
+    let whole = bfloor(exp);
+    let remain = exp - whole;
+    let whole_pow = bpowi(base, btoi(whole));
+    if remain == 0 {
+        return whole_pow;
+    }
+    let partial_result = bpow_approx(base, remain, BPOW_PRECISION);
+    bmul(whole_pow, partial_result)
+}
+
+/// Binomial series of `base^exp` for `exp < 1`, summed until the term is below `precision`.
+fn bpow_approx(base: Balance, exp: Balance, precision: Balance) -> Balance {
+    let a = exp;
+    let (x, xneg) = bsub_sign(base, BONE);
+    let mut term = BONE;
+    let mut sum = term;
+    let mut negative = false;
+
+    // term(k) = numer / denom
+    //         = (product(a - i + 1, i=1-->k) * x^k) / (k!)
+    // each iteration, multiply previous term by (a-(k-1)) * x / k
+    let mut i = 1;
+    while term >= precision {
+        let big_k = i * BONE;
+        let (c, cneg) = bsub_sign(a, big_k - BONE);
+        term = bdiv(bmul(term, bmul(c, x)), big_k);
+        if term == 0 {
+            break;
+        }
+        if xneg {
+            negative = !negative;
+        }
+        if cneg {
+            negative = !negative;
+        }
+        if negative {
+            sum -= term;
+        } else {
+            sum += term;
+        }
+        i += 1;
+    }
+    sum
+}
+
+/// Scales amount of a token with given decimals to `BONE` precision.
+pub fn normalize(amount: Balance, decimals: u8) -> Balance {
+    if decimals <= BONE_DECIMALS {
+        amount * 10u128.pow((BONE_DECIMALS - decimals) as u32)
+    } else {
+        amount / 10u128.pow((decimals - BONE_DECIMALS) as u32)
+    }
+}
@@ -0,0 +1,48 @@
+//! Structured event logs following NEP-297, so the pool activity can be indexed without replaying receipts.
+
+use near_lib::events::NearEvent;
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::AccountId;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Bind {
+        token: &'a AccountId,
+        balance: U128,
+        denorm: U128,
+    },
+    Rebind {
+        token: &'a AccountId,
+        balance: U128,
+        denorm: U128,
+    },
+    Unbind {
+        token: &'a AccountId,
+        balance: U128,
+    },
+    Join {
+        account_id: &'a AccountId,
+        pool_amount_out: U128,
+        amounts_in: Vec<U128>,
+    },
+    Exit {
+        account_id: &'a AccountId,
+        pool_amount_in: U128,
+        amounts_out: Vec<U128>,
+    },
+    Swap {
+        account_id: &'a AccountId,
+        token_in: &'a AccountId,
+        token_out: &'a AccountId,
+        amount_in: U128,
+        amount_out: U128,
+    },
+}
+
+impl NearEvent for Event<'_> {
+    const STANDARD: &'static str = "bpool";
+    const VERSION: &'static str = "1.0.0";
+}
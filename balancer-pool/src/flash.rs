@@ -0,0 +1,214 @@
+//! Flash swaps: `token_out` is pushed to the receiver before `token_in` is taken from its deposit,
+//! so the receiver can use it in between (e.g. for arbitrage against other venues).
+//! Transfers are asynchronous and can't be rolled back once the receiver got the tokens,
+//! so only receivers approved by the controller can flash swap.
+
+use near_sdk::PromiseOrValue;
+
+use crate::*;
+
+/// Interface of the flash swap receiver.
+#[ext_contract(ext_flash_receiver)]
+pub trait FlashSwapReceiver {
+    /// Called after `token_amount_out` of `token_out` was transferred to the receiver.
+    /// The receiver must deposit `token_amount_in` of `token_in` to the pool via `ft_transfer_call` by the end of this call.
+    fn on_flash_swap(
+        &mut self,
+        token_in: AccountId,
+        token_amount_in: U128,
+        token_out: AccountId,
+        token_amount_out: U128,
+        msg: String,
+    );
+}
+
+#[ext_contract(ext_flash_self)]
+pub trait FlashSwapCallbacks {
+    fn on_flash_swap_push(
+        &mut self,
+        receiver_id: AccountId,
+        token_in: AccountId,
+        token_amount_in: U128,
+        protocol_fee_amount: U128,
+        token_out: AccountId,
+        token_amount_out: U128,
+        msg: String,
+    ) -> PromiseOrValue<bool>;
+
+    fn on_flash_swap_repay(
+        &mut self,
+        receiver_id: AccountId,
+        token_in: AccountId,
+        token_amount_in: U128,
+        protocol_fee_amount: U128,
+        token_out: AccountId,
+        token_amount_out: U128,
+    ) -> bool;
+}
+
+#[near_bindgen]
+impl BPool {
+    pub fn is_flash_swap_receiver(&self, account_id: AccountId) -> bool {
+        self.flash_swap_receivers.contains(&account_id)
+    }
+
+    /// Approves or revokes the receiver of flash swaps.
+    pub fn set_flash_swap_receiver(&mut self, account_id: AccountId, allowed: bool) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        if allowed {
+            self.flash_swap_receivers.insert(&account_id);
+        } else {
+            self.flash_swap_receivers.remove(&account_id);
+        }
+    }
+
+    /// Same as `swap_exact_amount_out`, but pushes `token_out` to the sender first and calls `on_flash_swap`
+    /// on it with `msg`, then takes `token_in` from its deposit. Records are updated optimistically and reverted if
+    /// the transfer of `token_out` fails. If `token_in` is not repaid, the pool is paused.
+    /// Both tokens are locked until the flash swap resolves.
+    pub fn flash_swap_exact_amount_out(
+        &mut self,
+        token_in: AccountId,
+        max_amount_in: U128,
+        token_out: AccountId,
+        token_amount_out: U128,
+        max_price: U128,
+        msg: String,
+    ) -> Promise {
+        let receiver_id = env::predecessor_account_id();
+        assert!(
+            self.flash_swap_receivers.contains(&receiver_id),
+            "ERR_NOT_FLASH_SWAP_RECEIVER"
+        );
+        let token_amount_out: Balance = token_amount_out.into();
+        let prev_in_balance = self.records.get(&token_in).expect("ERR_NOT_BOUND").balance;
+        let (in_record, out_record, token_amount_in) = self.internal_swap_exact_amount_out(
+            &token_in,
+            max_amount_in.into(),
+            &token_out,
+            token_amount_out,
+            max_price.into(),
+        );
+        let protocol_fee_amount = token_amount_in - (in_record.balance - prev_in_balance);
+        self.records.insert(&token_in, &in_record);
+        self.records.insert(&token_out, &out_record);
+        self.lock(&token_in);
+        self.lock(&token_out);
+
+        ext_fungible_token::ft_transfer(
+            receiver_id.clone(),
+            token_amount_out.into(),
+            None,
+            &token_out,
+            ONE_YOCTO,
+            gas::FT_TRANSFER,
+        )
+        .then(ext_flash_self::on_flash_swap_push(
+            receiver_id,
+            token_in,
+            token_amount_in.into(),
+            protocol_fee_amount.into(),
+            token_out,
+            token_amount_out.into(),
+            msg,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            gas::ON_FLASH_SWAP_PUSH_CALLBACK,
+        ))
+    }
+
+    /// Callback after pushing `token_out` to the flash swap receiver.
+    /// Calls the receiver if the push succeeded, otherwise reverts the swap.
+    pub fn on_flash_swap_push(
+        &mut self,
+        receiver_id: AccountId,
+        token_in: AccountId,
+        token_amount_in: U128,
+        protocol_fee_amount: U128,
+        token_out: AccountId,
+        token_amount_out: U128,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        if !is_promise_success() {
+            self.internal_revert_swap_in(&token_in, token_amount_in.0, protocol_fee_amount.0);
+            let mut out_record = self.records.get(&token_out).unwrap();
+            out_record.balance += token_amount_out.0;
+            self.records.insert(&token_out, &out_record);
+            self.unlock(&token_in);
+            self.unlock(&token_out);
+            return PromiseOrValue::Value(false);
+        }
+        ext_flash_receiver::on_flash_swap(
+            token_in.clone(),
+            token_amount_in,
+            token_out.clone(),
+            token_amount_out,
+            msg,
+            &receiver_id,
+            NO_DEPOSIT,
+            gas::FLASH_SWAP_RECEIVER,
+        )
+        .then(ext_flash_self::on_flash_swap_repay(
+            receiver_id,
+            token_in,
+            token_amount_in,
+            protocol_fee_amount,
+            token_out,
+            token_amount_out,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            gas::ON_FLASH_SWAP_REPAY_CALLBACK,
+        ))
+        .into()
+    }
+
+    /// Callback after calling the flash swap receiver. Takes `token_in` from the receiver's deposit.
+    /// If it wasn't repaid, `token_out` is already gone: the swap is reverted on `token_in` side only
+    /// and the pool is paused until the controller resumes it.
+    pub fn on_flash_swap_repay(
+        &mut self,
+        receiver_id: AccountId,
+        token_in: AccountId,
+        token_amount_in: U128,
+        protocol_fee_amount: U128,
+        token_out: AccountId,
+        token_amount_out: U128,
+    ) -> bool {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        self.unlock(&token_in);
+        self.unlock(&token_out);
+        let deposit = self
+            .deposits
+            .get(&(receiver_id.clone(), token_in.clone()))
+            .unwrap_or(0);
+        if deposit < token_amount_in.0 {
+            self.internal_revert_swap_in(&token_in, token_amount_in.0, protocol_fee_amount.0);
+            self.paused = true;
+            env::log(format!("Flash swap by {} was not repaid, pausing", receiver_id).as_bytes());
+            return false;
+        }
+        self.pull_underlying(&token_in, &receiver_id, token_amount_in.0);
+        Event::Swap {
+            account_id: &receiver_id,
+            token_in: &token_in,
+            token_out: &token_out,
+            amount_in: token_amount_in,
+            amount_out: token_amount_out,
+        }
+        .emit();
+        true
+    }
+}
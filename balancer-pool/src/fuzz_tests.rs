@@ -0,0 +1,378 @@
+//! Randomized differential tests of the fixed-point math against exact decimal computations.
+//! Inputs are drawn from the ranges the pool allows, so that precision regressions are caught early.
+//! Fractional powers in the references are computed in `f64`, which is far more precise than `bpow`.
+
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use near_sdk::Balance;
+use near_test::fuzz::gen_amount;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::bconst::*;
+use crate::bmath::{
+    calc_in_given_out, calc_out_given_in, calc_pool_out_given_single_in,
+    calc_single_out_given_pool_in, calc_spot_price,
+};
+use crate::bnum::{bdiv, bmul, bpow, normalize};
+
+const ITERATIONS: usize = 10_000;
+
+fn big(value: Balance) -> BigDecimal {
+    BigDecimal::from_str(&value.to_string()).unwrap()
+}
+
+/// BONE-scaled number as a fraction.
+fn frac(value: Balance) -> BigDecimal {
+    big(value) / big(BONE)
+}
+
+/// `(numerator / denominator) ^ exp`.
+fn pow(numerator: &BigDecimal, denominator: &BigDecimal, exp: &BigDecimal) -> BigDecimal {
+    let to_f64 = |value: &BigDecimal| f64::from_str(&value.to_string()).unwrap();
+    let result = (to_f64(numerator) / to_f64(denominator)).powf(to_f64(exp));
+    BigDecimal::from_str(&format!("{:.30}", result)).unwrap()
+}
+
+/// Asserts that `actual` is within `abs_error` units or `rel_error` of `expected`.
+fn assert_close(
+    actual: Balance,
+    expected: &BigDecimal,
+    abs_error: Balance,
+    rel_error: &str,
+    inputs: &str,
+) {
+    let diff = (big(actual) - expected).abs();
+    let bound = big(abs_error) + expected * BigDecimal::from_str(rel_error).unwrap();
+    assert!(
+        diff <= bound,
+        "{}: got {}, expected {}, error {}",
+        inputs,
+        actual,
+        expected,
+        diff
+    );
+}
+
+#[test]
+fn fuzz_bmul() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..ITERATIONS {
+        let a = gen_amount(&mut rng, 1, 10u128.pow(31));
+        let b = gen_amount(&mut rng, 1, 10u128.pow(31));
+        let expected = big(a) * big(b) / big(BONE);
+        assert_close(
+            bmul(a, b),
+            &expected,
+            1,
+            "0",
+            &format!("bmul({}, {})", a, b),
+        );
+    }
+}
+
+#[test]
+fn fuzz_bdiv() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..ITERATIONS {
+        let a = gen_amount(&mut rng, 1, 10u128.pow(30));
+        let b = gen_amount(&mut rng, 10u128.pow(16), 10u128.pow(38));
+        let expected = big(a) * big(BONE) / big(b);
+        assert_close(
+            bdiv(a, b),
+            &expected,
+            1,
+            "0",
+            &format!("bdiv({}, {})", a, b),
+        );
+    }
+}
+
+#[test]
+fn fuzz_normalize() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for _ in 0..ITERATIONS {
+        let decimals: u8 = rng.gen_range(0, 33);
+        let amount = gen_amount(&mut rng, 1, 10u128.pow(12 + decimals as u32 / 2));
+        let expected = big(amount) * big(BONE) / big(10u128.pow(decimals as u32));
+        assert_close(
+            normalize(amount, decimals),
+            &expected,
+            1,
+            "0",
+            &format!("normalize({}, {})", amount, decimals),
+        );
+    }
+}
+
+#[test]
+fn fuzz_bpow() {
+    let mut rng = StdRng::seed_from_u64(4);
+    for _ in 0..ITERATIONS {
+        let base = rng.gen_range(BONE / 2, BONE * 3 / 2);
+        let exp = rng.gen_range(0, 2 * BONE);
+        // `f64` has enough precision here, as `bpow` itself only approximates up to `BPOW_PRECISION`.
+        let expected = (base as f64 / BONE as f64).powf(exp as f64 / BONE as f64) * BONE as f64;
+        assert_close(
+            bpow(base, exp),
+            &BigDecimal::from_str(&format!("{:.0}", expected)).unwrap(),
+            0,
+            "0.00000001",
+            &format!("bpow({}, {})", base, exp),
+        );
+    }
+}
+
+#[test]
+fn fuzz_calc_spot_price() {
+    let mut rng = StdRng::seed_from_u64(3);
+    // Wider ranges make the BONE-scaled ratio of balances overflow `Balance`, same as in Balancer.
+    let (min_balance, max_balance) = (BONE / 1_000_000, BONE * 1_000_000);
+    for _ in 0..ITERATIONS {
+        let balance_in = gen_amount(&mut rng, min_balance, max_balance);
+        let weight_in = rng.gen_range(MIN_WEIGHT, MAX_WEIGHT + 1);
+        let balance_out = gen_amount(&mut rng, min_balance, max_balance);
+        let weight_out = rng.gen_range(MIN_WEIGHT, MAX_WEIGHT + 1);
+        let swap_fee = rng.gen_range(MIN_FEE, MAX_FEE + 1);
+        let expected = (big(balance_in) / big(weight_in)) / (big(balance_out) / big(weight_out))
+            * big(BONE)
+            / (big(BONE - swap_fee) / big(BONE));
+        // Each of the intermediate divisions rounds, which is amplified by the following ones.
+        assert_close(
+            calc_spot_price(balance_in, weight_in, balance_out, weight_out, swap_fee),
+            &expected,
+            4,
+            "0.000000001",
+            &format!(
+                "calc_spot_price({}, {}, {}, {}, {})",
+                balance_in, weight_in, balance_out, weight_out, swap_fee
+            ),
+        );
+    }
+}
+
+/// Pool weights and fee from the allowed ranges.
+fn gen_weights_and_fee(rng: &mut StdRng) -> (Balance, Balance, Balance) {
+    (
+        rng.gen_range(MIN_WEIGHT, MAX_WEIGHT + 1),
+        rng.gen_range(MIN_WEIGHT, MAX_WEIGHT + 1),
+        rng.gen_range(MIN_FEE, MAX_FEE + 1),
+    )
+}
+
+#[test]
+fn fuzz_calc_out_given_in() {
+    let mut rng = StdRng::seed_from_u64(5);
+    for _ in 0..ITERATIONS {
+        let balance_in = gen_amount(&mut rng, BONE / 1_000_000, BONE * 1_000_000);
+        let balance_out = gen_amount(&mut rng, BONE / 1_000_000, BONE * 1_000_000);
+        let (weight_in, weight_out, swap_fee) = gen_weights_and_fee(&mut rng);
+        let amount_in = gen_amount(
+            &mut rng,
+            balance_in / 1_000_000,
+            bmul(balance_in, MAX_IN_RATIO),
+        );
+        let y = pow(
+            &big(balance_in),
+            &(big(balance_in) + big(amount_in) * (BigDecimal::from(1) - frac(swap_fee))),
+            &(big(weight_in) / big(weight_out)),
+        );
+        let expected = big(balance_out) * (BigDecimal::from(1) - y);
+        // `bpow` is precise up to `BPOW_PRECISION`, which is scaled by the balance.
+        assert_close(
+            calc_out_given_in(
+                balance_in,
+                weight_in,
+                balance_out,
+                weight_out,
+                amount_in,
+                swap_fee,
+            ),
+            &expected,
+            balance_out / 1_000_000_000,
+            "0",
+            &format!(
+                "calc_out_given_in({}, {}, {}, {}, {}, {})",
+                balance_in, weight_in, balance_out, weight_out, amount_in, swap_fee
+            ),
+        );
+    }
+}
+
+#[test]
+fn fuzz_calc_in_given_out() {
+    let mut rng = StdRng::seed_from_u64(6);
+    for _ in 0..ITERATIONS {
+        let balance_in = gen_amount(&mut rng, BONE / 1_000_000, BONE * 1_000_000);
+        let balance_out = gen_amount(&mut rng, BONE / 1_000_000, BONE * 1_000_000);
+        let (weight_in, weight_out, swap_fee) = gen_weights_and_fee(&mut rng);
+        let amount_out = gen_amount(
+            &mut rng,
+            balance_out / 1_000_000,
+            bmul(balance_out, MAX_OUT_RATIO),
+        );
+        let y = pow(
+            &big(balance_out),
+            &big(balance_out - amount_out),
+            &(big(weight_out) / big(weight_in)),
+        );
+        let expected =
+            big(balance_in) * (y - BigDecimal::from(1)) / (BigDecimal::from(1) - frac(swap_fee));
+        // The whole part of the power is computed by repeated `bmul`, which accumulates rounding.
+        assert_close(
+            calc_in_given_out(
+                balance_in,
+                weight_in,
+                balance_out,
+                weight_out,
+                amount_out,
+                swap_fee,
+            ),
+            &expected,
+            balance_in / 10_000_000_000,
+            "0.00000001",
+            &format!(
+                "calc_in_given_out({}, {}, {}, {}, {}, {})",
+                balance_in, weight_in, balance_out, weight_out, amount_out, swap_fee
+            ),
+        );
+    }
+}
+
+#[test]
+fn fuzz_calc_pool_out_given_single_in() {
+    let mut rng = StdRng::seed_from_u64(7);
+    for _ in 0..ITERATIONS {
+        let balance_in = gen_amount(&mut rng, BONE / 1_000_000, BONE * 1_000_000);
+        let pool_supply = gen_amount(&mut rng, BONE / 1_000, BONE * 1_000_000_000);
+        let total_weight = rng.gen_range(2 * MIN_WEIGHT, MAX_TOTAL_WEIGHT + 1);
+        let weight_in = rng.gen_range(MIN_WEIGHT, total_weight - MIN_WEIGHT + 1);
+        let swap_fee = rng.gen_range(MIN_FEE, MAX_FEE + 1);
+        let amount_in = gen_amount(
+            &mut rng,
+            balance_in / 1_000_000,
+            bmul(balance_in, MAX_IN_RATIO),
+        );
+        let normalized_weight = big(weight_in) / big(total_weight);
+        let amount_in_after_fee = big(amount_in)
+            * (BigDecimal::from(1) - (BigDecimal::from(1) - &normalized_weight) * frac(swap_fee));
+        let pool_ratio = pow(
+            &(big(balance_in) + amount_in_after_fee),
+            &big(balance_in),
+            &normalized_weight,
+        );
+        let expected = big(pool_supply) * (pool_ratio - BigDecimal::from(1));
+        assert_close(
+            calc_pool_out_given_single_in(
+                balance_in,
+                weight_in,
+                pool_supply,
+                total_weight,
+                amount_in,
+                swap_fee,
+            ),
+            &expected,
+            pool_supply / 1_000_000_000,
+            "0",
+            &format!(
+                "calc_pool_out_given_single_in({}, {}, {}, {}, {}, {})",
+                balance_in, weight_in, pool_supply, total_weight, amount_in, swap_fee
+            ),
+        );
+    }
+}
+
+#[test]
+fn fuzz_calc_single_out_given_pool_in() {
+    let mut rng = StdRng::seed_from_u64(8);
+    for _ in 0..ITERATIONS {
+        let balance_out = gen_amount(&mut rng, BONE / 1_000_000, BONE * 1_000_000);
+        let pool_supply = gen_amount(&mut rng, BONE / 1_000, BONE * 1_000_000_000);
+        let total_weight = rng.gen_range(2 * MIN_WEIGHT, MAX_TOTAL_WEIGHT + 1);
+        let weight_out = rng.gen_range(MIN_WEIGHT, total_weight - MIN_WEIGHT + 1);
+        let swap_fee = rng.gen_range(MIN_FEE, MAX_FEE + 1);
+        let exit_fee = rng.gen_range(0, MAX_EXIT_FEE + 1);
+        let pool_amount_in = gen_amount(&mut rng, pool_supply / 1_000_000, pool_supply / 3);
+        let normalized_weight = big(weight_out) / big(total_weight);
+        let token_out_ratio = pow(
+            &(big(pool_supply) - big(pool_amount_in) * (BigDecimal::from(1) - frac(exit_fee))),
+            &big(pool_supply),
+            &(BigDecimal::from(1) / &normalized_weight),
+        );
+        let expected = big(balance_out)
+            * (BigDecimal::from(1) - token_out_ratio)
+            * (BigDecimal::from(1) - (BigDecimal::from(1) - &normalized_weight) * frac(swap_fee));
+        assert_close(
+            calc_single_out_given_pool_in(
+                balance_out,
+                weight_out,
+                pool_supply,
+                total_weight,
+                pool_amount_in,
+                swap_fee,
+                exit_fee,
+            ),
+            &expected,
+            balance_out / 1_000_000_000,
+            "0",
+            &format!(
+                "calc_single_out_given_pool_in({}, {}, {}, {}, {}, {}, {})",
+                balance_out,
+                weight_out,
+                pool_supply,
+                total_weight,
+                pool_amount_in,
+                swap_fee,
+                exit_fee
+            ),
+        );
+    }
+}
+
+/// Swapping and swapping the proceeds back never returns more than was put in:
+/// the swap fee covers the rounding of both swaps.
+#[test]
+fn fuzz_swap_round_trip() {
+    let mut rng = StdRng::seed_from_u64(9);
+    for _ in 0..ITERATIONS {
+        let balance_in = gen_amount(&mut rng, BONE / 1_000_000, BONE * 1_000_000);
+        let balance_out = gen_amount(&mut rng, BONE / 1_000_000, BONE * 1_000_000);
+        let (weight_in, weight_out, swap_fee) = gen_weights_and_fee(&mut rng);
+        let amount_in = gen_amount(
+            &mut rng,
+            balance_in / 1_000_000,
+            bmul(balance_in, MAX_IN_RATIO),
+        );
+        let amount_out = calc_out_given_in(
+            balance_in,
+            weight_in,
+            balance_out,
+            weight_out,
+            amount_in,
+            swap_fee,
+        );
+        if amount_out == 0 || amount_out > bmul(balance_out - amount_out, MAX_IN_RATIO) {
+            continue;
+        }
+        let amount_back = calc_out_given_in(
+            balance_out - amount_out,
+            weight_out,
+            balance_in + amount_in,
+            weight_in,
+            amount_out,
+            swap_fee,
+        );
+        assert!(
+            amount_back <= amount_in,
+            "round trip of {} returned {}: balances {} and {}, weights {} and {}, fee {}",
+            amount_in,
+            amount_back,
+            balance_in,
+            balance_out,
+            weight_in,
+            weight_out,
+            swap_fee
+        );
+    }
+}
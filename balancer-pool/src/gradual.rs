@@ -0,0 +1,40 @@
+use near_lib::math::mul_div;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+
+use crate::bconst::Weight;
+
+/// Scheduled linear change of token weights, used for liquidity bootstrapping launches.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct GradualUpdate {
+    pub start_time: u64,
+    pub end_time: u64,
+    /// Weights at `start_time`, in the order of the pool's tokens.
+    pub start_weights: Vec<Weight>,
+    /// Weights at `end_time`, in the order of the pool's tokens.
+    pub end_weights: Vec<Weight>,
+}
+
+impl GradualUpdate {
+    /// Returns weights interpolated linearly for the given timestamp.
+    pub fn weights_at(&self, timestamp: u64) -> Vec<Weight> {
+        if timestamp <= self.start_time {
+            return self.start_weights.clone();
+        }
+        if timestamp >= self.end_time {
+            return self.end_weights.clone();
+        }
+        let elapsed = (timestamp - self.start_time) as u128;
+        let duration = (self.end_time - self.start_time) as u128;
+        self.start_weights
+            .iter()
+            .zip(self.end_weights.iter())
+            .map(|(&start, &end)| {
+                if end >= start {
+                    start + mul_div(end - start, elapsed, duration)
+                } else {
+                    start - mul_div(start - end, elapsed, duration)
+                }
+            })
+            .collect()
+    }
+}
@@ -1,14 +1,39 @@
-use borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
-use near_sdk::json_types::U128;
-use near_sdk::{env, ext_contract, near_bindgen, AccountId, Balance, Promise};
-
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider,
+};
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
+use near_contract_standards::fungible_token::FungibleToken;
+use near_lib::events::NearEvent;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, ext_contract, near_bindgen, serde_json, AccountId, Balance, Promise, PromiseOrValue,
+    PromiseResult,
+};
+
+mod airdrop;
 mod bconst;
 mod bmath;
+mod bnum;
+mod events;
+mod flash;
+#[cfg(test)]
+mod fuzz_tests;
+mod gradual;
+mod upgrade;
 
+use airdrop::Airdrop;
 use bconst::*;
-use bmath::calc_spot_price;
-use near_lib::token::{ext_nep21, FungibleToken, Token};
+use bmath::{calc_in_given_out, calc_out_given_in, calc_spot_price};
+use bnum::{bdiv, bmul, normalize};
+use events::Event;
+use gradual::GradualUpdate;
+use upgrade::LegacyAccount;
+
+near_sdk::setup_alloc!();
 
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Record {
@@ -16,20 +41,89 @@ pub struct Record {
     index: u64,
     denorm: Weight,
     balance: Balance,
+    /// Decimals of the token, known once its metadata has been fetched.
+    decimals: Option<u8>,
+    /// NEAR staked by the controller for the storage of this token, refunded on unbind.
+    storage_stake: Balance,
+}
+
+impl Record {
+    /// Balance scaled to `BONE` precision, so that tokens with different decimals are comparable.
+    fn normalized_balance(&self) -> Balance {
+        normalize(self.balance, self.decimals.expect("ERR_DECIMALS_UNKNOWN"))
+    }
+}
+
+/// Part of the NEP-148 token metadata used by the pool.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenMetadata {
+    pub decimals: u8,
+}
+
+/// Everything needed to render the pool in a single view call.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolInfo {
+    pub tokens: Vec<AccountId>,
+    pub balances: Vec<U128>,
+    pub denorm_weights: Vec<U128>,
+    pub swap_fee: U128,
+    pub total_shares: U128,
+    pub finalized: bool,
+    pub public_swap: bool,
 }
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct BPool {
     controller: AccountId,
+    /// Account proposed to become the controller, until it accepts.
+    pending_controller: Option<AccountId>,
     factory: AccountId,
     swap_fee: Balance,
+    exit_fee: Balance,
     finalized: bool,
     public_swap: bool,
+    /// Blocks swaps, joins and binds while set. Exits are always allowed.
+    paused: bool,
+    /// Finalize as soon as all bound tokens have been deposited via `ft_on_transfer`.
+    auto_finalize: bool,
     records: UnorderedMap<AccountId, Record>,
     tokens: Vec<AccountId>,
     total_weight: Weight,
-    token: Token,
+    /// Pool shares.
+    token: FungibleToken,
+    /// Exit fees collected so far per token (pool shares are under this contract's account id).
+    collected_fees: UnorderedMap<AccountId, Balance>,
+    /// Share of the swap fee that accrues to the factory instead of the liquidity providers.
+    protocol_fee: Balance,
+    /// Protocol fees accrued so far per token, not part of the pool balances.
+    protocol_fees: UnorderedMap<AccountId, Balance>,
+    /// Only the controller and accounts in `lp_allowlist` can join the pool while set. Swaps are not affected.
+    lp_allowlist_enabled: bool,
+    lp_allowlist: UnorderedSet<AccountId>,
+    /// Accounts approved by the controller to receive flash swaps.
+    flash_swap_receivers: UnorderedSet<AccountId>,
+    airdrops: Vector<Airdrop>,
+    /// Pool share balances before their first change after each airdrop, as `(next airdrop id, balance)`.
+    share_checkpoints: LookupMap<AccountId, Vec<(u64, Balance)>>,
+    /// Airdrops claimed by each account.
+    airdrop_claims: LookupSet<(u64, AccountId)>,
+    /// Number of flash swaps in flight per token. Operations on a token are rejected until they resolve.
+    locks: UnorderedMap<AccountId, u32>,
+    /// Right of the controller to change weights after finalization via gradual updates.
+    can_change_weights: bool,
+    gradual_update: Option<GradualUpdate>,
+    /// Time after which the staged code can be deployed, if there is any.
+    staging_timestamp: Option<u64>,
+    /// Whether `deploy_code` calls `migrate` on the staged code.
+    staging_migration: bool,
+    /// Tokens deposited via `ft_transfer_call` or owed after a transfer out of the pool failed,
+    /// per `(account, token)`. Joins, swaps and binds take tokens from here, see `withdraw`.
+    deposits: LookupMap<(AccountId, AccountId), Balance>,
+    /// Pool shares of the first version of the pool that weren't claimed yet, by sha256 of the account id.
+    legacy_shares: UnorderedMap<Vec<u8>, LegacyAccount>,
 }
 
 impl Default for BPool {
@@ -42,82 +136,204 @@ impl Default for BPool {
 impl BPool {
     #[init]
     pub fn new() -> Self {
-        Self {
+        let mut pool = Self {
             controller: env::predecessor_account_id(),
+            pending_controller: None,
             factory: env::predecessor_account_id(),
             swap_fee: MIN_FEE,
+            exit_fee: EXIT_FEE,
             public_swap: false,
             finalized: false,
+            paused: false,
+            auto_finalize: false,
             records: UnorderedMap::new(b"r".to_vec()),
             tokens: Vec::new(),
             total_weight: 0,
-            token: Token::new(env::signer_account_id(), 0u128),
+            token: FungibleToken::new(b"t".to_vec()),
+            collected_fees: UnorderedMap::new(b"f".to_vec()),
+            protocol_fee: 0,
+            protocol_fees: UnorderedMap::new(b"p".to_vec()),
+            lp_allowlist_enabled: false,
+            lp_allowlist: UnorderedSet::new(b"q".to_vec()),
+            flash_swap_receivers: UnorderedSet::new(b"x".to_vec()),
+            airdrops: Vector::new(b"d".to_vec()),
+            share_checkpoints: LookupMap::new(b"c".to_vec()),
+            airdrop_claims: LookupSet::new(b"k".to_vec()),
+            locks: UnorderedMap::new(b"l".to_vec()),
+            can_change_weights: false,
+            gradual_update: None,
+            staging_timestamp: None,
+            staging_migration: false,
+            deposits: LookupMap::new(b"w".to_vec()),
+            legacy_shares: UnorderedMap::new(b"a".to_vec()),
+        };
+        pool.internal_register_if_missing(&env::current_account_id());
+        pool.internal_register_if_missing(&env::predecessor_account_id());
+        pool
+    }
+
+    /// Creates 50/50 pool of two given tokens in one call.
+    /// The signer of the transaction becomes the controller and funds the pool by `ft_transfer_call`
+    /// of both tokens to it. The pool is finalized when both deposits arrived.
+    #[init]
+    pub fn new_simple(token_a: AccountId, token_b: AccountId, swap_fee: U128) -> Self {
+        assert_ne!(token_a, token_b, "ERR_SAME_TOKEN");
+        let swap_fee: Balance = swap_fee.into();
+        assert!(swap_fee >= MIN_FEE, "ERR_MIN_FEE");
+        assert!(swap_fee <= MAX_FEE, "ERR_MAX_FEE");
+        let mut pool = Self::new();
+        pool.controller = env::signer_account_id();
+        pool.internal_register_if_missing(&env::signer_account_id());
+        pool.swap_fee = swap_fee;
+        pool.auto_finalize = true;
+        for token in [token_a, token_b].iter() {
+            pool.records.insert(
+                token,
+                &Record {
+                    bound: true,
+                    index: pool.tokens.len() as u64,
+                    denorm: SIMPLE_POOL_WEIGHT,
+                    balance: 0,
+                    decimals: None,
+                    storage_stake: 0,
+                },
+            );
+            pool.fetch_decimals(token);
+            pool.tokens.push(token.clone());
+            pool.total_weight += SIMPLE_POOL_WEIGHT;
+            Event::Bind {
+                token,
+                balance: 0.into(),
+                denorm: SIMPLE_POOL_WEIGHT.into(),
+            }
+            .emit();
         }
+        pool
     }
 
     // Getters
 
-    pub fn isPublicSwap(&self) -> bool {
+    pub fn is_public_swap(&self) -> bool {
         self.public_swap
     }
 
-    pub fn isFinalized(&self) -> bool {
+    pub fn is_finalized(&self) -> bool {
         self.finalized
     }
 
-    pub fn isBound(&self, token: AccountId) -> bool {
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_bound(&self, token: AccountId) -> bool {
         self.records
             .get(&token)
             .map(|record| record.bound)
             .unwrap_or(false)
     }
 
-    pub fn getNumTokens(&self) -> u64 {
+    pub fn get_num_tokens(&self) -> u64 {
         self.tokens.len() as u64
     }
 
-    pub fn getCurrentTokens(&self) -> Vec<AccountId> {
+    pub fn get_current_tokens(&self) -> Vec<AccountId> {
         self.tokens.clone()
     }
 
-    pub fn getFinalTokens(&self) -> Vec<AccountId> {
+    pub fn get_final_tokens(&self) -> Vec<AccountId> {
         assert!(self.finalized, "ERR_NOT_FINALIZED");
         self.tokens.clone()
     }
 
-    pub fn getDenormalizedWeight(&self, token: AccountId) -> U128 {
-        assert!(self.isBound(token.clone()), "ERR_NOT_BOUND");
+    pub fn get_denormalized_weight(&self, token: AccountId) -> U128 {
+        assert!(self.is_bound(token.clone()), "ERR_NOT_BOUND");
         self.records.get(&token).unwrap().denorm.into()
     }
 
-    pub fn getTotalDenormalizedWeight(&self) -> U128 {
+    pub fn get_total_denormalized_weight(&self) -> U128 {
         self.total_weight.into()
     }
 
-    pub fn getNormalizedWeight(&self, token: AccountId) -> U128 {
-        assert!(self.isBound(token.clone()), "ERR_NOT_BOUND");
+    /// Share of the token in the total weight, scaled by `BONE`.
+    pub fn get_normalized_weight(&self, token: AccountId) -> U128 {
+        assert!(self.is_bound(token.clone()), "ERR_NOT_BOUND");
         let denorm = self.records.get(&token).unwrap().denorm;
-        // TODO: this division is special?
-        (denorm / self.total_weight).into()
+        bdiv(denorm, self.total_weight).into()
     }
 
-    pub fn getBalance(&self, token: AccountId) -> U128 {
-        assert!(self.isBound(token.clone()), "ERR_NOT_BOUND");
+    pub fn get_token_balance(&self, token: AccountId) -> U128 {
+        assert!(self.is_bound(token.clone()), "ERR_NOT_BOUND");
         self.records.get(&token).unwrap().balance.into()
     }
 
-    pub fn getSwapFee(&self) -> U128 {
+    pub fn get_decimals(&self, token: AccountId) -> Option<u8> {
+        assert!(self.is_bound(token.clone()), "ERR_NOT_BOUND");
+        self.records.get(&token).unwrap().decimals
+    }
+
+    pub fn get_swap_fee(&self) -> U128 {
         self.swap_fee.into()
     }
 
-    pub fn getController(&self) -> AccountId {
+    pub fn get_exit_fee(&self) -> U128 {
+        self.exit_fee.into()
+    }
+
+    pub fn get_collected_fees(&self, token: AccountId) -> U128 {
+        self.collected_fees.get(&token).unwrap_or(0).into()
+    }
+
+    pub fn get_protocol_fee(&self) -> U128 {
+        self.protocol_fee.into()
+    }
+
+    pub fn get_accrued_protocol_fees(&self, token: AccountId) -> U128 {
+        self.protocol_fees.get(&token).unwrap_or(0).into()
+    }
+
+    pub fn is_lp_allowlist_enabled(&self) -> bool {
+        self.lp_allowlist_enabled
+    }
+
+    /// Returns whether given account can join the pool.
+    pub fn is_lp_allowed(&self, account_id: AccountId) -> bool {
+        !self.lp_allowlist_enabled
+            || account_id == self.controller
+            || self.lp_allowlist.contains(&account_id)
+    }
+
+    pub fn get_lp_allowlist(&self, from_index: u64, limit: u64) -> Vec<AccountId> {
+        let accounts = self.lp_allowlist.as_vector();
+        (from_index..std::cmp::min(from_index + limit, accounts.len()))
+            .map(|index| accounts.get(index).unwrap())
+            .collect()
+    }
+
+    pub fn get_controller(&self) -> AccountId {
         self.controller.clone()
     }
 
+    pub fn get_pool_info(&self) -> PoolInfo {
+        let records: Vec<Record> = self
+            .tokens
+            .iter()
+            .map(|token| self.records.get(token).unwrap())
+            .collect();
+        PoolInfo {
+            tokens: self.tokens.clone(),
+            balances: records.iter().map(|record| record.balance.into()).collect(),
+            denorm_weights: records.iter().map(|record| record.denorm.into()).collect(),
+            swap_fee: self.swap_fee.into(),
+            total_shares: self.token.total_supply.into(),
+            finalized: self.finalized,
+            public_swap: self.public_swap,
+        }
+    }
+
     // Setters.
 
-    pub fn setSwapFee(&mut self, swapFee: U128) {
-        let swap_fee = swapFee.into();
+    pub fn set_swap_fee(&mut self, swap_fee: U128) {
+        let swap_fee: Balance = swap_fee.into();
         assert!(!self.finalized, "ERR_IS_FINALIZED");
         assert_eq!(
             env::predecessor_account_id(),
@@ -129,16 +345,56 @@ impl BPool {
         self.swap_fee = swap_fee;
     }
 
-    pub fn setController(&mut self, controller: AccountId) {
+    pub fn set_exit_fee(&mut self, exit_fee: U128) {
+        let exit_fee: Balance = exit_fee.into();
+        assert!(!self.finalized, "ERR_IS_FINALIZED");
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        assert!(exit_fee <= MAX_EXIT_FEE, "ERR_MAX_EXIT_FEE");
+        self.exit_fee = exit_fee;
+    }
+
+    /// Sets share of the swap fee that accrues to the factory. Can be changed at any time.
+    pub fn set_protocol_fee(&mut self, protocol_fee: U128) {
+        let protocol_fee: Balance = protocol_fee.into();
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.factory,
+            "ERR_NOT_FACTORY"
+        );
+        assert!(protocol_fee <= MAX_PROTOCOL_FEE, "ERR_MAX_PROTOCOL_FEE");
+        self.protocol_fee = protocol_fee;
+    }
+
+    /// Proposes new controller, who has to call `accept_controller` to take over.
+    /// Proposing again replaces the previous proposal.
+    pub fn propose_controller(&mut self, controller: AccountId) {
         assert_eq!(
             env::predecessor_account_id(),
             self.controller,
             "ERR_NOT_CONTROLLER"
         );
-        self.controller = controller;
+        self.pending_controller = Some(controller);
+    }
+
+    /// Makes the caller the controller, if it was proposed by the current controller.
+    pub fn accept_controller(&mut self) {
+        assert_eq!(
+            Some(env::predecessor_account_id()),
+            self.pending_controller,
+            "ERR_NOT_PENDING_CONTROLLER"
+        );
+        self.controller = self.pending_controller.take().unwrap();
+    }
+
+    pub fn get_pending_controller(&self) -> Option<AccountId> {
+        self.pending_controller.clone()
     }
 
-    pub fn setPublicSwap(&mut self, public: bool) {
+    pub fn set_public_swap(&mut self, public: bool) {
         assert!(!self.finalized, "ERR_IS_FINALIZED");
         assert_eq!(
             env::predecessor_account_id(),
@@ -148,6 +404,137 @@ impl BPool {
         self.public_swap = public;
     }
 
+    /// Restricts joining the pool to the controller and the allowlisted accounts.
+    /// Can be changed at any time, accounts that already hold pool shares can always exit.
+    pub fn set_lp_allowlist_enabled(&mut self, enabled: bool) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        self.lp_allowlist_enabled = enabled;
+    }
+
+    pub fn add_to_lp_allowlist(&mut self, account_ids: Vec<AccountId>) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        for account_id in account_ids.iter() {
+            self.lp_allowlist.insert(account_id);
+        }
+    }
+
+    pub fn remove_from_lp_allowlist(&mut self, account_ids: Vec<AccountId>) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        for account_id in account_ids.iter() {
+            self.lp_allowlist.remove(account_id);
+        }
+    }
+
+    /// Freezes the pool in case one of the bound tokens is compromised.
+    /// Liquidity providers can still exit while the pool is paused.
+    pub fn pause(&mut self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        assert!(!self.paused, "ERR_IS_PAUSED");
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        assert!(self.paused, "ERR_NOT_PAUSED");
+        self.paused = false;
+    }
+
+    /// Allows the controller to schedule gradual weight updates, including after finalization.
+    pub fn set_can_change_weights(&mut self, can_change_weights: bool) {
+        assert!(!self.finalized, "ERR_IS_FINALIZED");
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        self.can_change_weights = can_change_weights;
+    }
+
+    /// Schedules linear change of weights from the current ones to `new_weights`
+    /// between `start_time` and `end_time` (nanoseconds). Weights are applied by `poke_weights`.
+    pub fn update_weights_gradually(
+        &mut self,
+        new_weights: Vec<U128>,
+        start_time: U64,
+        end_time: U64,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        assert!(self.can_change_weights, "ERR_NOT_CONFIGURABLE_WEIGHTS");
+        assert_eq!(
+            new_weights.len(),
+            self.tokens.len(),
+            "ERR_START_WEIGHTS_MISMATCH"
+        );
+        let start_time = std::cmp::max(env::block_timestamp(), start_time.into());
+        let end_time: u64 = end_time.into();
+        assert!(end_time > start_time, "ERR_GRADUAL_UPDATE_TIME_TRAVEL");
+
+        let end_weights: Vec<Weight> = new_weights.into_iter().map(|w| w.into()).collect();
+        let mut total_weight = 0;
+        for &weight in end_weights.iter() {
+            assert!(weight >= MIN_WEIGHT, "ERR_MIN_WEIGHT");
+            assert!(weight <= MAX_WEIGHT, "ERR_MAX_WEIGHT");
+            total_weight += weight;
+        }
+        assert!(total_weight <= MAX_TOTAL_WEIGHT, "ERR_MAX_TOTAL_WEIGHT");
+
+        self.gradual_update = Some(GradualUpdate {
+            start_time,
+            end_time,
+            start_weights: self
+                .tokens
+                .iter()
+                .map(|token| self.records.get(token).unwrap().denorm)
+                .collect(),
+            end_weights,
+        });
+    }
+
+    /// Applies weights for the current block of the scheduled gradual update. Can be called by anyone.
+    pub fn poke_weights(&mut self) {
+        let gradual_update = self.gradual_update.as_ref().expect("ERR_NO_UPDATE");
+        let now = env::block_timestamp();
+        assert!(now >= gradual_update.start_time, "ERR_CANT_POKE_YET");
+        let weights = gradual_update.weights_at(now);
+        let is_done = now >= gradual_update.end_time;
+
+        let mut total_weight = 0;
+        for (token, weight) in self.tokens.iter().zip(weights.into_iter()) {
+            let mut record = self.records.get(token).unwrap();
+            record.denorm = weight;
+            self.records.insert(token, &record);
+            total_weight += weight;
+        }
+        self.total_weight = total_weight;
+        if is_done {
+            self.gradual_update = None;
+        }
+    }
+
     pub fn finalize(&mut self) {
         assert_eq!(
             env::predecessor_account_id(),
@@ -157,268 +544,1217 @@ impl BPool {
         assert!(!self.finalized, "ERR_IS_FINALIZED");
         assert!(self.tokens.len() >= MIN_BOUND_TOKENS, "ERR_MIN_TOKENS");
 
-        self.finalized = true;
-        self.public_swap = true;
+        self.internal_finalize();
+    }
 
-        self.mint_pool_share(INIT_POOL_SUPPLY);
-        self.push_pool_share(env::predecessor_account_id(), INIT_POOL_SUPPLY);
+    /// Receives tokens sent with `ft_transfer_call` into the sender's deposit, which joins, swaps and binds
+    /// take tokens from. The sender must be registered, see `storage_deposit`.
+    /// Before finalization of pools created with `new_simple`, tokens of the controller fund the pool directly
+    /// instead, and the pool is finalized once every token is funded.
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> U128 {
+        let token = env::predecessor_account_id();
+        assert!(msg.is_empty(), "ERR_MSG_INCORRECT");
+        assert_ne!(token, env::current_account_id(), "ERR_WRONG_TOKEN");
+        if !(self.auto_finalize && !self.finalized && sender_id == self.controller) {
+            assert!(
+                self.token.accounts.contains_key(&sender_id),
+                "ERR_NOT_REGISTERED"
+            );
+            self.internal_deposit(&sender_id, &token, amount.0);
+            return 0.into();
+        }
+        assert!(self.is_bound(token.clone()), "ERR_NOT_BOUND");
+        self.assert_not_paused();
+        self.assert_unlocked(&token);
+
+        let mut record = self.records.get(&token).unwrap();
+        record.balance += amount.0;
+        self.records.insert(&token, &record);
+        Event::Rebind {
+            token: &token,
+            balance: record.balance.into(),
+            denorm: record.denorm.into(),
+        }
+        .emit();
+
+        self.maybe_auto_finalize();
+        0.into()
     }
 
+    /// Binds new token to the pool. Attached deposit must cover the storage used by the token's record,
+    /// the rest is refunded.
+    #[payable]
     pub fn bind(&mut self, token: AccountId, balance: U128, denorm: U128) {
         assert_eq!(
             env::predecessor_account_id(),
             self.controller,
             "ERR_NOT_CONTROLLER"
         );
-        assert!(!self.isBound(token.clone()), "ERR_IS_BOUND");
+        assert!(!self.is_bound(token.clone()), "ERR_IS_BOUND");
         assert!(!self.finalized, "ERR_IS_FINALIZED");
+        // Pending gradual update is indexed by token position.
+        assert!(self.gradual_update.is_none(), "ERR_GRADUAL_UPDATE_PENDING");
         assert!(self.tokens.len() < MAX_BOUND_TOKENS, "ERR_MAX_TOKENS");
-
-        self.records.insert(
-            &token,
-            &Record {
-                bound: true,
-                index: self.tokens.len() as u64,
-                denorm: 0,
-                balance: 0,
-            },
-        );
+        self.assert_not_paused();
+        self.assert_unlocked(&token);
+
+        let initial_storage_usage = env::storage_usage();
+        let mut record = Record {
+            bound: true,
+            index: self.tokens.len() as u64,
+            denorm: 0,
+            balance: 0,
+            decimals: None,
+            storage_stake: 0,
+        };
+        self.records.insert(&token, &record);
         self.tokens.push(token.clone());
-        self.rebind(token, balance.into(), denorm.into());
+        // `tokens` is only written with the contract state at the end of the call, so add its growth here.
+        let storage_used = env::storage_usage() - initial_storage_usage + token.len() as u64 + 4;
+        record.storage_stake = storage_used as Balance * STORAGE_PRICE_PER_BYTE;
+        assert!(
+            env::attached_deposit() >= record.storage_stake,
+            "ERR_STORAGE_DEPOSIT"
+        );
+        self.records.insert(&token, &record);
+        let refund = env::attached_deposit() - record.storage_stake;
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        self.fetch_decimals(&token);
+        self.internal_rebind(&token, balance.into(), denorm.into());
+        Event::Bind {
+            token: &token,
+            balance,
+            denorm,
+        }
+        .emit();
     }
 
-    pub fn rebind(&mut self, token: AccountId, balance: Balance, denorm: Weight) {
+    pub fn rebind(&mut self, token: AccountId, balance: U128, denorm: U128) {
         assert_eq!(
             env::predecessor_account_id(),
             self.controller,
             "ERR_NOT_CONTROLLER"
         );
-        assert!(self.isBound(token.clone()), "ERR_NOT_BOUND");
+        assert!(self.is_bound(token.clone()), "ERR_NOT_BOUND");
         assert!(!self.finalized, "ERR_IS_FINALIZED");
+        // Pending gradual update is indexed by token position.
+        assert!(self.gradual_update.is_none(), "ERR_GRADUAL_UPDATE_PENDING");
+        self.assert_not_paused();
+        self.assert_unlocked(&token);
+
+        self.internal_rebind(&token, balance.into(), denorm.into());
+        Event::Rebind {
+            token: &token,
+            balance,
+            denorm,
+        }
+        .emit();
+    }
 
-        assert!(denorm >= MIN_WEIGHT, "ERR_MIN_WEIGHT");
-        assert!(denorm <= MAX_WEIGHT, "ERR_MAX_WEIGHT");
-        assert!(balance >= MIN_BALANCE, "ERR_MIN_BALANCE");
-
-        let mut record = self.records.get(&token).unwrap();
-        let old_weight = record.denorm;
-        record.denorm = denorm;
-        if denorm > old_weight {
-            self.total_weight = self.total_weight + (denorm - old_weight);
-        } else {
-            self.total_weight = self.total_weight - (old_weight - denorm);
+    pub fn unbind(&mut self, token: AccountId) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.controller,
+            "ERR_NOT_CONTROLLER"
+        );
+        assert!(self.is_bound(token.clone()), "ERR_NOT_BOUND");
+        assert!(!self.finalized, "ERR_IS_FINALIZED");
+        // Pending gradual update is indexed by token position.
+        assert!(self.gradual_update.is_none(), "ERR_GRADUAL_UPDATE_PENDING");
+        self.assert_unlocked(&token);
+
+        let record = self.records.get(&token).unwrap();
+        let token_balance = record.balance;
+        let token_exit_fee = bmul(token_balance, self.exit_fee);
+        self.total_weight -= record.denorm;
+
+        // Swap the token-to-unbind with the last token, then delete the last token.
+        let index = record.index as usize;
+        self.tokens.swap_remove(index);
+        if index < self.tokens.len() {
+            let mut moved_record = self.records.get(&self.tokens[index]).unwrap();
+            moved_record.index = index as u64;
+            self.records.insert(&self.tokens[index], &moved_record);
         }
+        self.records.remove(&token);
 
-        let old_balance = record.balance;
-        record.balance = balance;
-        if balance > old_balance {
-            self.pull_underlying(
-                &token,
-                &env::predecessor_account_id(),
-                balance - old_balance,
-            );
-        } else {
-            let token_balance_withdrawn = old_balance - balance;
-            let token_exit_fee = token_balance_withdrawn * EXIT_FEE;
-            self.push_underlying(
-                token.clone(),
-                env::predecessor_account_id(),
-                token_balance_withdrawn - token_exit_fee,
-            );
-            self.push_underlying(token.clone(), self.factory.clone(), token_exit_fee);
+        self.push_underlying(
+            token.clone(),
+            env::predecessor_account_id(),
+            token_balance - token_exit_fee,
+        );
+        self.record_fee(&token, token_exit_fee);
+        if record.storage_stake > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(record.storage_stake);
         }
-        // TODO: move this into the on_pull or else clause.
-        self.records.insert(&token, &record);
+        Event::Unbind {
+            token: &token,
+            balance: token_balance.into(),
+        }
+        .emit();
     }
 
-    pub fn unbind(&mut self, token: AccountId) {}
-
     /// Absorb any tokens that have been sent to this contract into the pool.
     pub fn gulp(&mut self, token: AccountId) {
-        // TODO: call ext_nep21.balance(env::current_account_id(), token.clone()).then()
+        // TODO: call ext_fungible_token::ft_balance_of(env::current_account_id(), token.clone()).then()
     }
 
-    pub fn getSpotPrice(&self, tokenIn: AccountId, tokenOut: AccountId) -> Balance {
-        assert!(self.isBound(tokenIn.clone()), "ERR_NOT_BOUND");
-        assert!(self.isBound(tokenOut.clone()), "ERR_NOT_BOUND");
-        let in_record = self.records.get(&tokenIn).unwrap();
-        let out_record = self.records.get(&tokenOut).unwrap();
+    pub fn get_spot_price(&self, token_in: AccountId, token_out: AccountId) -> U128 {
+        assert!(self.is_bound(token_in.clone()), "ERR_NOT_BOUND");
+        assert!(self.is_bound(token_out.clone()), "ERR_NOT_BOUND");
+        let in_record = self.records.get(&token_in).unwrap();
+        let out_record = self.records.get(&token_out).unwrap();
         calc_spot_price(
-            in_record.balance,
+            in_record.normalized_balance(),
             in_record.denorm,
-            out_record.balance,
+            out_record.normalized_balance(),
             out_record.denorm,
             self.swap_fee,
         )
+        .into()
     }
 
-    pub fn getSpotPriceSansFee(&self, tokenIn: AccountId, tokenOut: AccountId) -> Balance {
-        assert!(self.isBound(tokenIn.clone()), "ERR_NOT_BOUND");
-        assert!(self.isBound(tokenOut.clone()), "ERR_NOT_BOUND");
-        let in_record = self.records.get(&tokenIn).unwrap();
-        let out_record = self.records.get(&tokenOut).unwrap();
+    pub fn get_spot_price_sans_fee(&self, token_in: AccountId, token_out: AccountId) -> U128 {
+        assert!(self.is_bound(token_in.clone()), "ERR_NOT_BOUND");
+        assert!(self.is_bound(token_out.clone()), "ERR_NOT_BOUND");
+        let in_record = self.records.get(&token_in).unwrap();
+        let out_record = self.records.get(&token_out).unwrap();
         calc_spot_price(
+            in_record.normalized_balance(),
+            in_record.denorm,
+            out_record.normalized_balance(),
+            out_record.denorm,
+            0,
+        )
+        .into()
+    }
+
+    /// Maximum amount of the token that can be sent into the pool in a single swap or join.
+    pub fn get_max_amount_in(&self, token: AccountId) -> U128 {
+        let record = self.records.get(&token).expect("ERR_NOT_BOUND");
+        bmul(record.balance, MAX_IN_RATIO).into()
+    }
+
+    /// Maximum amount of the token that can be taken out of the pool in a single swap or exit.
+    pub fn get_max_amount_out(&self, token: AccountId) -> U128 {
+        let record = self.records.get(&token).expect("ERR_NOT_BOUND");
+        bmul(record.balance, MAX_OUT_RATIO).into()
+    }
+
+    /// Swaps exactly `token_amount_in` of `token_in` from the sender's deposit for `token_out`.
+    /// `max_price` limits the spot price of `token_out` after the swap. Returns amount sent to the sender.
+    pub fn swap_exact_amount_in(
+        &mut self,
+        token_in: AccountId,
+        token_amount_in: U128,
+        token_out: AccountId,
+        min_amount_out: U128,
+        max_price: U128,
+    ) -> U128 {
+        let token_amount_in: Balance = token_amount_in.into();
+        let max_price: Balance = max_price.into();
+        let (mut in_record, mut out_record) = self.internal_swap_records(&token_in, &token_out);
+        assert!(
+            token_amount_in <= bmul(in_record.balance, MAX_IN_RATIO),
+            "ERR_MAX_IN_RATIO"
+        );
+
+        let spot_price_before = self.internal_spot_price(&in_record, &out_record);
+        assert!(spot_price_before <= max_price, "ERR_BAD_LIMIT_PRICE");
+        let token_amount_out = calc_out_given_in(
             in_record.balance,
             in_record.denorm,
             out_record.balance,
             out_record.denorm,
-            0,
+            token_amount_in,
+            self.swap_fee,
+        );
+        assert!(token_amount_out >= min_amount_out.0, "ERR_LIMIT_OUT");
+
+        in_record.balance +=
+            token_amount_in - self.internal_accrue_protocol_fee(&token_in, token_amount_in);
+        out_record.balance -= token_amount_out;
+        self.internal_check_swap_prices(
+            &in_record,
+            &out_record,
+            spot_price_before,
+            max_price,
+            token_amount_in,
+            token_amount_out,
+        );
+        self.internal_swap(
+            &token_in,
+            &in_record,
+            token_amount_in,
+            &token_out,
+            &out_record,
+            token_amount_out,
+        );
+        token_amount_out.into()
+    }
+
+    /// Swaps `token_in` from the sender for exactly `token_amount_out` of `token_out`.
+    /// `max_price` limits the spot price of `token_out` after the swap. Returns amount taken from the sender's deposit.
+    pub fn swap_exact_amount_out(
+        &mut self,
+        token_in: AccountId,
+        max_amount_in: U128,
+        token_out: AccountId,
+        token_amount_out: U128,
+        max_price: U128,
+    ) -> U128 {
+        let token_amount_out: Balance = token_amount_out.into();
+        let (in_record, out_record, token_amount_in) = self.internal_swap_exact_amount_out(
+            &token_in,
+            max_amount_in.into(),
+            &token_out,
+            token_amount_out,
+            max_price.into(),
+        );
+        self.internal_swap(
+            &token_in,
+            &in_record,
+            token_amount_in,
+            &token_out,
+            &out_record,
+            token_amount_out,
+        );
+        token_amount_in.into()
+    }
+
+    /// Joins the pool with exactly `token_amount_in` of a single token.
+    /// Returns amount of pool shares minted to the sender.
+    pub fn join_swap_extern_amount_in(
+        &mut self,
+        token_in: AccountId,
+        token_amount_in: U128,
+        min_pool_amount_out: U128,
+    ) -> U128 {
+        let token_amount_in: Balance = token_amount_in.into();
+        let mut record = self.internal_single_asset_record(&token_in);
+        self.assert_not_paused();
+        self.assert_lp_allowed();
+        assert!(
+            token_amount_in <= bmul(record.balance, MAX_IN_RATIO),
+            "ERR_MAX_IN_RATIO"
+        );
+
+        let pool_amount_out = bmath::calc_pool_out_given_single_in(
+            record.balance,
+            record.denorm,
+            self.token.total_supply,
+            self.total_weight,
+            token_amount_in,
+            self.swap_fee,
+        );
+        assert!(pool_amount_out >= min_pool_amount_out.0, "ERR_LIMIT_OUT");
+
+        record.balance += token_amount_in;
+        self.internal_join_single(&token_in, &record, token_amount_in, pool_amount_out);
+        pool_amount_out.into()
+    }
+
+    /// Joins the pool for exactly `pool_amount_out` of pool shares, paying in a single token.
+    /// Returns amount of the token taken from the sender's deposit.
+    pub fn join_swap_pool_amount_out(
+        &mut self,
+        token_in: AccountId,
+        pool_amount_out: U128,
+        max_amount_in: U128,
+    ) -> U128 {
+        let pool_amount_out: Balance = pool_amount_out.into();
+        let mut record = self.internal_single_asset_record(&token_in);
+        self.assert_not_paused();
+        self.assert_lp_allowed();
+
+        let token_amount_in = bmath::calc_single_in_given_pool_out(
+            record.balance,
+            record.denorm,
+            self.token.total_supply,
+            self.total_weight,
+            pool_amount_out,
+            self.swap_fee,
+        );
+        assert_ne!(token_amount_in, 0, "ERR_MATH_APPROX");
+        assert!(token_amount_in <= max_amount_in.0, "ERR_LIMIT_IN");
+        assert!(
+            token_amount_in <= bmul(record.balance, MAX_IN_RATIO),
+            "ERR_MAX_IN_RATIO"
+        );
+
+        record.balance += token_amount_in;
+        self.internal_join_single(&token_in, &record, token_amount_in, pool_amount_out);
+        token_amount_in.into()
+    }
+
+    /// Exits the pool with exactly `pool_amount_in` of pool shares, receiving a single token.
+    /// Returns amount of the token pushed to the sender.
+    pub fn exit_swap_pool_amount_in(
+        &mut self,
+        token_out: AccountId,
+        pool_amount_in: U128,
+        min_amount_out: U128,
+    ) -> U128 {
+        let pool_amount_in: Balance = pool_amount_in.into();
+        let mut record = self.internal_single_asset_record(&token_out);
+
+        let token_amount_out = bmath::calc_single_out_given_pool_in(
+            record.balance,
+            record.denorm,
+            self.token.total_supply,
+            self.total_weight,
+            pool_amount_in,
+            self.swap_fee,
+            self.exit_fee,
+        );
+        assert!(token_amount_out >= min_amount_out.0, "ERR_LIMIT_OUT");
+        assert!(
+            token_amount_out <= bmul(record.balance, MAX_OUT_RATIO),
+            "ERR_MAX_OUT_RATIO"
+        );
+
+        record.balance -= token_amount_out;
+        self.internal_exit_single(&token_out, &record, token_amount_out, pool_amount_in);
+        token_amount_out.into()
+    }
+
+    /// Exits the pool for exactly `token_amount_out` of a single token, paying with pool shares.
+    /// Returns amount of pool shares taken from the sender.
+    pub fn exit_swap_extern_amount_out(
+        &mut self,
+        token_out: AccountId,
+        token_amount_out: U128,
+        max_pool_amount_in: U128,
+    ) -> U128 {
+        let token_amount_out: Balance = token_amount_out.into();
+        let mut record = self.internal_single_asset_record(&token_out);
+        assert!(
+            token_amount_out <= bmul(record.balance, MAX_OUT_RATIO),
+            "ERR_MAX_OUT_RATIO"
+        );
+
+        let pool_amount_in = bmath::calc_pool_in_given_single_out(
+            record.balance,
+            record.denorm,
+            self.token.total_supply,
+            self.total_weight,
+            token_amount_out,
+            self.swap_fee,
+            self.exit_fee,
+        );
+        assert_ne!(pool_amount_in, 0, "ERR_MATH_APPROX");
+        assert!(pool_amount_in <= max_pool_amount_in.0, "ERR_LIMIT_IN");
+
+        record.balance -= token_amount_out;
+        self.internal_exit_single(&token_out, &record, token_amount_out, pool_amount_in);
+        pool_amount_in.into()
+    }
+
+    /// Amounts of every bound token `join_pool` would take for `pool_amount_out` of pool shares.
+    pub fn get_join_pool_amounts_in(&self, pool_amount_out: U128) -> Vec<U128> {
+        self.internal_join_amounts_in(pool_amount_out.into())
+            .into_iter()
+            .map(U128)
+            .collect()
+    }
+
+    /// Amounts of every bound token `exit_pool` would push for `pool_amount_in` of pool shares.
+    pub fn get_exit_pool_amounts_out(&self, pool_amount_in: U128) -> Vec<U128> {
+        self.internal_exit_amounts_out(pool_amount_in.into())
+            .into_iter()
+            .map(U128)
+            .collect()
+    }
+
+    /// Pool shares `join_swap_extern_amount_in` would mint for `token_amount_in` of `token_in`.
+    pub fn calc_pool_out_given_single_in(
+        &self,
+        token_in: AccountId,
+        token_amount_in: U128,
+    ) -> U128 {
+        let record = self.records.get(&token_in).expect("ERR_NOT_BOUND");
+        bmath::calc_pool_out_given_single_in(
+            record.balance,
+            record.denorm,
+            self.token.total_supply,
+            self.total_weight,
+            token_amount_in.into(),
+            self.swap_fee,
         )
+        .into()
     }
 
-    pub fn joinPool(&mut self, poolAmountOut: Balance, maxAmountsIn: Vec<Balance>) {
-        assert!(self.finalized, "ERR_NOT_FINALIZED");
-        let pool_total = self.token.get_total_supply();
-        let ratio = poolAmountOut / pool_total;
-        assert_ne!(ratio, 0, "ERR_MAX_APPROX");
+    /// Amount of `token_in` `join_swap_pool_amount_out` would take for `pool_amount_out` of pool shares.
+    pub fn calc_single_in_given_pool_out(
+        &self,
+        token_in: AccountId,
+        pool_amount_out: U128,
+    ) -> U128 {
+        let record = self.records.get(&token_in).expect("ERR_NOT_BOUND");
+        bmath::calc_single_in_given_pool_out(
+            record.balance,
+            record.denorm,
+            self.token.total_supply,
+            self.total_weight,
+            pool_amount_out.into(),
+            self.swap_fee,
+        )
+        .into()
+    }
+
+    /// Amount of `token_out` `exit_swap_pool_amount_in` would push for `pool_amount_in` of pool shares.
+    pub fn calc_single_out_given_pool_in(
+        &self,
+        token_out: AccountId,
+        pool_amount_in: U128,
+    ) -> U128 {
+        let record = self.records.get(&token_out).expect("ERR_NOT_BOUND");
+        bmath::calc_single_out_given_pool_in(
+            record.balance,
+            record.denorm,
+            self.token.total_supply,
+            self.total_weight,
+            pool_amount_in.into(),
+            self.swap_fee,
+            self.exit_fee,
+        )
+        .into()
+    }
+
+    /// Pool shares `exit_swap_extern_amount_out` would take for `token_amount_out` of `token_out`.
+    pub fn calc_pool_in_given_single_out(
+        &self,
+        token_out: AccountId,
+        token_amount_out: U128,
+    ) -> U128 {
+        let record = self.records.get(&token_out).expect("ERR_NOT_BOUND");
+        bmath::calc_pool_in_given_single_out(
+            record.balance,
+            record.denorm,
+            self.token.total_supply,
+            self.total_weight,
+            token_amount_out.into(),
+            self.swap_fee,
+            self.exit_fee,
+        )
+        .into()
+    }
 
-        for i in 0..self.tokens.len() {
+    /// Mints `pool_amount_out` of pool shares to the sender in exchange for a proportional amount
+    /// of every bound token from the sender's deposits. Returns amounts of tokens taken.
+    pub fn join_pool(&mut self, pool_amount_out: U128, max_amounts_in: Vec<U128>) -> Vec<U128> {
+        let pool_amount_out: Balance = pool_amount_out.into();
+        assert!(self.finalized, "ERR_NOT_FINALIZED");
+        self.assert_not_paused();
+        self.assert_lp_allowed();
+        self.assert_all_unlocked();
+
+        let mut amounts_in: Vec<U128> = vec![];
+        for (i, token_amount_in) in self
+            .internal_join_amounts_in(pool_amount_out)
+            .into_iter()
+            .enumerate()
+        {
             let mut record = self.records.get(&self.tokens[i]).unwrap();
-            let token_amount_in = ratio * record.balance;
-            assert_ne!(token_amount_in, 0, "ERR_MATH_APPROX");
-            assert!(token_amount_in <= maxAmountsIn[i], "ERR_LIMIT_IN");
+            assert!(token_amount_in <= max_amounts_in[i].0, "ERR_LIMIT_IN");
             record.balance += token_amount_in;
+            self.records.insert(&self.tokens[i], &record);
             self.pull_underlying(
                 &self.tokens[i].clone(),
                 &env::predecessor_account_id(),
                 token_amount_in,
             );
-            // TODO: join all promises and only save records / mint shares on success.
+            amounts_in.push(token_amount_in.into());
+        }
+        self.mint_pool_share(pool_amount_out);
+        self.push_pool_share(env::predecessor_account_id(), pool_amount_out);
+        Event::Join {
+            account_id: &env::predecessor_account_id(),
+            pool_amount_out: pool_amount_out.into(),
+            amounts_in: amounts_in.clone(),
         }
-        self.mint_pool_share(poolAmountOut);
-        self.push_pool_share(env::predecessor_account_id(), poolAmountOut);
+        .emit();
+        amounts_in
     }
 
-    pub fn exitPool(&mut self, poolAmountIn: Balance, minAmountsOut: Vec<Balance>) {
+    /// Burns `pool_amount_in` of the sender's pool shares in exchange for a proportional amount
+    /// of every bound token. Returns amounts of tokens pushed to the sender.
+    pub fn exit_pool(&mut self, pool_amount_in: U128, min_amounts_out: Vec<U128>) -> Vec<U128> {
+        let pool_amount_in: Balance = pool_amount_in.into();
         assert!(self.finalized, "ERR_NOT_FINALIZED");
+        self.assert_all_unlocked();
 
-        let pool_total = self.token.get_total_supply();
-        let exit_fee = poolAmountIn * EXIT_FEE;
-        let p_ai_after_exit_fee = poolAmountIn - exit_fee;
-        let ratio = p_ai_after_exit_fee / pool_total;
-        assert_ne!(ratio, 0, "ERR_MATH_APPROX");
+        let exit_fee = bmul(pool_amount_in, self.exit_fee);
+        let token_amounts_out = self.internal_exit_amounts_out(pool_amount_in);
 
-        self.pull_pool_share(env::predecessor_account_id(), poolAmountIn);
-        self.push_pool_share(self.factory.clone(), exit_fee);
-        self.burn_pool_share(p_ai_after_exit_fee);
+        self.pull_pool_share(env::predecessor_account_id(), pool_amount_in);
+        self.record_fee(&env::current_account_id(), exit_fee);
+        self.burn_pool_share(pool_amount_in - exit_fee);
 
-        for i in 0..self.tokens.len() {
+        let mut amounts_out: Vec<U128> = vec![];
+        for (i, token_amount_out) in token_amounts_out.into_iter().enumerate() {
             let mut record = self.records.get(&self.tokens[i]).unwrap();
-            let token_amount_out = ratio * record.balance;
-            assert_ne!(token_amount_out, 0, "ERR_MATH_APPROX");
-            assert!(token_amount_out >= minAmountsOut[i], "ERR_LIMIT_OUT");
-            record.balance += token_amount_out;
+            assert!(token_amount_out >= min_amounts_out[i].0, "ERR_LIMIT_OUT");
+            record.balance -= token_amount_out;
+            self.records.insert(&self.tokens[i], &record);
             self.push_underlying(
                 self.tokens[i].clone(),
                 env::predecessor_account_id(),
                 token_amount_out,
             );
+            amounts_out.push(token_amount_out.into());
         }
+        Event::Exit {
+            account_id: &env::predecessor_account_id(),
+            pool_amount_in: pool_amount_in.into(),
+            amounts_out: amounts_out.clone(),
+        }
+        .emit();
+        amounts_out
     }
 
-    pub fn on_pull(&mut self) -> bool {
-        true
-    }
-
-    pub fn on_push(&mut self) -> bool {
-        true
+    /// Sends all collected exit fees in given token to the factory.
+    /// To collect fees paid in pool shares, pass this pool's account id as `token`.
+    pub fn collect(&mut self, token: AccountId) -> U128 {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.factory,
+            "ERR_NOT_FACTORY"
+        );
+        if token != env::current_account_id() {
+            self.assert_unlocked(&token);
+        }
+        let amount = self.collected_fees.remove(&token).expect("ERR_NO_FEES");
+        if token == env::current_account_id() {
+            self.push_pool_share(self.factory.clone(), amount);
+        } else {
+            self.push_underlying(token, self.factory.clone(), amount);
+        }
+        amount.into()
     }
-}
 
-#[near_bindgen]
-impl FungibleToken for BPool {
-    fn inc_allowance(&mut self, escrow_account_id: String, amount: U128) {
-        self.token.inc_allowance(escrow_account_id, amount.into());
+    /// Sends all accrued protocol fees in given token to the factory.
+    pub fn collect_protocol_fees(&mut self, token: AccountId) -> U128 {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.factory,
+            "ERR_NOT_FACTORY"
+        );
+        self.assert_unlocked(&token);
+        let amount = self.protocol_fees.remove(&token).expect("ERR_NO_FEES");
+        self.push_underlying(token, self.factory.clone(), amount);
+        amount.into()
     }
 
-    fn dec_allowance(&mut self, escrow_account_id: String, amount: U128) {
-        self.token.dec_allowance(escrow_account_id, amount.into());
+    /// Amount of `token` deposited by the account or owed to it after a transfer out of the pool failed.
+    pub fn get_deposit(&self, account_id: AccountId, token: AccountId) -> U128 {
+        self.deposits.get(&(account_id, token)).unwrap_or(0).into()
     }
 
-    fn transfer_from(&mut self, owner_id: String, new_owner_id: String, amount: U128) {
-        self.token
-            .transfer_from(owner_id, new_owner_id, amount.into());
+    /// Sends `amount` of `token` from the sender's deposit to the sender. If the transfer fails,
+    /// the amount is credited back.
+    pub fn withdraw(&mut self, token: AccountId, amount: U128) {
+        let account_id = env::predecessor_account_id();
+        let amount: Balance = amount.into();
+        assert!(amount > 0, "ERR_ZERO_AMOUNT");
+        self.internal_withdraw_deposit(&account_id, &token, amount);
+        self.push_underlying(token, account_id, amount);
     }
 
-    fn transfer(&mut self, new_owner_id: String, amount: U128) {
-        self.token.transfer(new_owner_id, amount.into());
+    /// Callback with metadata of the bound `token`. Tokens without metadata are assumed to have
+    /// `BONE_DECIMALS` decimals.
+    pub fn on_metadata(&mut self, token: AccountId) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        assert_eq!(env::promise_results_count(), 1, "ERR_NO_METADATA");
+        let decimals = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<TokenMetadata>(&value)
+                .map(|metadata| metadata.decimals)
+                .unwrap_or(BONE_DECIMALS),
+            _ => BONE_DECIMALS,
+        };
+        self.internal_set_decimals(&token, decimals);
+    }
+
+    /// Callback after pushing `amount` of `token` out of the pool to the account.
+    /// The pool records and shares were already updated, so if the transfer failed the amount is credited
+    /// to the account's deposit and can be withdrawn later.
+    pub fn on_push(&mut self, token: AccountId, account_id: AccountId, amount: U128) -> bool {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        if is_promise_success() {
+            return true;
+        }
+        env::log(format!("Failed to send {} of {} to {}", amount.0, token, account_id).as_bytes());
+        self.internal_deposit(&account_id, &token, amount.0);
+        false
+    }
+}
+
+/// Same as `impl_fungible_token_core!`, but checkpoints share balances for airdrops before every transfer.
+#[near_bindgen]
+impl FungibleTokenCore for BPool {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        self.internal_checkpoint_shares(&env::predecessor_account_id());
+        self.internal_checkpoint_shares(receiver_id.as_ref());
+        self.token.ft_transfer(receiver_id, amount, memo)
     }
 
-    fn get_total_supply(&self) -> U128 {
-        self.token.get_total_supply().into()
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.internal_checkpoint_shares(&env::predecessor_account_id());
+        self.internal_checkpoint_shares(receiver_id.as_ref());
+        self.token.ft_transfer_call(receiver_id, amount, memo, msg)
     }
 
-    fn get_balance(&self, owner_id: String) -> U128 {
-        self.token.get_balance(owner_id).into()
+    fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
     }
 
-    fn get_allowance(&self, owner_id: String, escrow_account_id: String) -> U128 {
-        self.token.get_allowance(owner_id, escrow_account_id).into()
+    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.token.ft_balance_of(account_id)
     }
 }
 
+#[near_bindgen]
+impl FungibleTokenResolver for BPool {
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        receiver_id: ValidAccountId,
+        amount: U128,
+    ) -> U128 {
+        self.internal_checkpoint_shares(sender_id.as_ref());
+        self.internal_checkpoint_shares(receiver_id.as_ref());
+        let (used_amount, _) =
+            self.token
+                .internal_ft_resolve_transfer(sender_id.as_ref(), receiver_id, amount);
+        used_amount.into()
+    }
+}
+
+near_contract_standards::impl_fungible_token_storage!(BPool, token);
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for BPool {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: "ft-1.0.0".to_string(),
+            name: "Balancer Pool Token".to_string(),
+            symbol: "BPT".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: BONE_DECIMALS,
+        }
+    }
+}
+
+/// Interface of the bound tokens, which follow NEP-141.
+#[ext_contract(ext_fungible_token)]
+pub trait Nep141 {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
 #[ext_contract(ext_self)]
 pub trait ExtSelf {
-    fn on_pull(&mut self) -> bool;
+    fn on_push(&mut self, token: AccountId, account_id: AccountId, amount: U128) -> bool;
 
-    fn on_push(&mut self) -> bool;
+    fn on_metadata(&mut self, token: AccountId);
+}
+
+#[ext_contract(ext_ft_metadata)]
+pub trait TokenMetadataProvider {
+    fn ft_metadata(&self) -> TokenMetadata;
+}
+
+/// Returns true if the promise this callback is attached to has succeeded.
+fn is_promise_success() -> bool {
+    assert_eq!(
+        env::promise_results_count(),
+        1,
+        "ERR_EXPECTED_ONE_PROMISE_RESULT"
+    );
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
 }
 
 impl BPool {
-    fn pull_underlying(&mut self, token: &AccountId, from: &AccountId, amount: Balance) -> Promise {
-        ext_nep21::transfer_from(
-            from.clone(),
-            env::current_account_id(),
-            amount.into(),
-            token,
-            NO_DEPOSIT,
-            gas::NEP21_TRANSFER_FROM,
+    fn assert_not_paused(&self) {
+        assert!(!self.paused, "ERR_IS_PAUSED");
+    }
+
+    fn assert_lp_allowed(&self) {
+        assert!(
+            self.is_lp_allowed(env::predecessor_account_id()),
+            "ERR_NOT_ALLOWED_LP"
+        );
+    }
+
+    /// Rejects operation on the token while transfers of it are in flight,
+    /// as records are not consistent until their callbacks resolve.
+    fn assert_unlocked(&self, token: &AccountId) {
+        assert!(self.locks.get(token).is_none(), "ERR_REENTRANCY");
+    }
+
+    fn assert_all_unlocked(&self) {
+        for token in self.tokens.iter() {
+            self.assert_unlocked(token);
+        }
+    }
+
+    fn lock(&mut self, token: &AccountId) {
+        let count = self.locks.get(token).unwrap_or(0);
+        self.locks.insert(token, &(count + 1));
+    }
+
+    fn unlock(&mut self, token: &AccountId) {
+        let count = self.locks.get(token).expect("ERR_NOT_LOCKED");
+        if count > 1 {
+            self.locks.insert(token, &(count - 1));
+        } else {
+            self.locks.remove(token);
+        }
+    }
+
+    /// Amounts of every bound token proportional to `pool_amount_out` of pool shares.
+    fn internal_join_amounts_in(&self, pool_amount_out: Balance) -> Vec<Balance> {
+        let ratio = bdiv(pool_amount_out, self.token.total_supply);
+        assert_ne!(ratio, 0, "ERR_MATH_APPROX");
+        self.tokens
+            .iter()
+            .map(|token| {
+                let token_amount_in = bmul(ratio, self.records.get(token).unwrap().balance);
+                assert_ne!(token_amount_in, 0, "ERR_MATH_APPROX");
+                token_amount_in
+            })
+            .collect()
+    }
+
+    /// Amounts of every bound token proportional to `pool_amount_in` of pool shares less the exit fee.
+    fn internal_exit_amounts_out(&self, pool_amount_in: Balance) -> Vec<Balance> {
+        let p_ai_after_exit_fee = pool_amount_in - bmul(pool_amount_in, self.exit_fee);
+        let ratio = bdiv(p_ai_after_exit_fee, self.token.total_supply);
+        assert_ne!(ratio, 0, "ERR_MATH_APPROX");
+        self.tokens
+            .iter()
+            .map(|token| {
+                let token_amount_out = bmul(ratio, self.records.get(token).unwrap().balance);
+                assert_ne!(token_amount_out, 0, "ERR_MATH_APPROX");
+                token_amount_out
+            })
+            .collect()
+    }
+
+    /// Returns records of both tokens of the swap, checking that swapping them is allowed.
+    fn internal_swap_records(
+        &self,
+        token_in: &AccountId,
+        token_out: &AccountId,
+    ) -> (Record, Record) {
+        let in_record = self.records.get(token_in).expect("ERR_NOT_BOUND");
+        let out_record = self.records.get(token_out).expect("ERR_NOT_BOUND");
+        assert!(self.public_swap, "ERR_SWAP_NOT_PUBLIC");
+        self.assert_not_paused();
+        self.assert_unlocked(token_in);
+        self.assert_unlocked(token_out);
+        (in_record, out_record)
+    }
+
+    fn internal_spot_price(&self, in_record: &Record, out_record: &Record) -> Balance {
+        calc_spot_price(
+            in_record.normalized_balance(),
+            in_record.denorm,
+            out_record.normalized_balance(),
+            out_record.denorm,
+            self.swap_fee,
         )
-        .then(ext_self::on_pull(
-            &env::current_account_id(),
-            NO_DEPOSIT,
-            gas::ON_PULL_CALLBACK,
-        ))
     }
 
+    /// Computes `token_amount_in` for exactly `token_amount_out` and returns updated records of both tokens,
+    /// which are not saved yet.
+    fn internal_swap_exact_amount_out(
+        &mut self,
+        token_in: &AccountId,
+        max_amount_in: Balance,
+        token_out: &AccountId,
+        token_amount_out: Balance,
+        max_price: Balance,
+    ) -> (Record, Record, Balance) {
+        let (mut in_record, mut out_record) = self.internal_swap_records(token_in, token_out);
+        assert!(
+            token_amount_out <= bmul(out_record.balance, MAX_OUT_RATIO),
+            "ERR_MAX_OUT_RATIO"
+        );
+
+        let spot_price_before = self.internal_spot_price(&in_record, &out_record);
+        assert!(spot_price_before <= max_price, "ERR_BAD_LIMIT_PRICE");
+        let token_amount_in = calc_in_given_out(
+            in_record.balance,
+            in_record.denorm,
+            out_record.balance,
+            out_record.denorm,
+            token_amount_out,
+            self.swap_fee,
+        );
+        assert!(token_amount_in <= max_amount_in, "ERR_LIMIT_IN");
+
+        in_record.balance +=
+            token_amount_in - self.internal_accrue_protocol_fee(token_in, token_amount_in);
+        out_record.balance -= token_amount_out;
+        self.internal_check_swap_prices(
+            &in_record,
+            &out_record,
+            spot_price_before,
+            max_price,
+            token_amount_in,
+            token_amount_out,
+        );
+        (in_record, out_record, token_amount_in)
+    }
+
+    /// Checks that the swap moved the price in the right direction, within the limit, and that
+    /// the effective price of the swap is not better than the spot price before it.
+    fn internal_check_swap_prices(
+        &self,
+        in_record: &Record,
+        out_record: &Record,
+        spot_price_before: Balance,
+        max_price: Balance,
+        token_amount_in: Balance,
+        token_amount_out: Balance,
+    ) {
+        let spot_price_after = self.internal_spot_price(in_record, out_record);
+        assert!(spot_price_after >= spot_price_before, "ERR_MATH_APPROX");
+        assert!(spot_price_after <= max_price, "ERR_LIMIT_PRICE");
+        assert!(
+            spot_price_before
+                <= bdiv(
+                    normalize(token_amount_in, in_record.decimals.unwrap()),
+                    normalize(token_amount_out, out_record.decimals.unwrap())
+                ),
+            "ERR_MATH_APPROX"
+        );
+    }
+
+    /// Takes `token_in` from the sender's deposit, saves records after the swap and pushes `token_out`
+    /// to the sender.
+    fn internal_swap(
+        &mut self,
+        token_in: &AccountId,
+        in_record: &Record,
+        token_amount_in: Balance,
+        token_out: &AccountId,
+        out_record: &Record,
+        token_amount_out: Balance,
+    ) {
+        self.pull_underlying(token_in, &env::predecessor_account_id(), token_amount_in);
+        self.records.insert(token_in, in_record);
+        self.records.insert(token_out, out_record);
+        self.push_underlying(
+            token_out.clone(),
+            env::predecessor_account_id(),
+            token_amount_out,
+        );
+        Event::Swap {
+            account_id: &env::predecessor_account_id(),
+            token_in,
+            token_out,
+            amount_in: token_amount_in.into(),
+            amount_out: token_amount_out.into(),
+        }
+        .emit();
+    }
+
+    /// Removes `token_amount_in` that was optimistically added to the pool balance and protocol fees.
+    fn internal_revert_swap_in(
+        &mut self,
+        token_in: &AccountId,
+        token_amount_in: Balance,
+        protocol_fee_amount: Balance,
+    ) {
+        // Token can't be unbound while it's locked.
+        let mut in_record = self.records.get(token_in).unwrap();
+        in_record.balance -= token_amount_in - protocol_fee_amount;
+        self.records.insert(token_in, &in_record);
+        if protocol_fee_amount > 0 {
+            let amount = self.protocol_fees.get(token_in).unwrap_or(0);
+            self.protocol_fees
+                .insert(token_in, &amount.saturating_sub(protocol_fee_amount));
+        }
+    }
+
+    /// Records protocol share of the swap fee paid on `token_amount_in`, which doesn't go into the pool balance.
+    fn internal_accrue_protocol_fee(
+        &mut self,
+        token_in: &AccountId,
+        token_amount_in: Balance,
+    ) -> Balance {
+        let amount = bmul(bmul(token_amount_in, self.swap_fee), self.protocol_fee);
+        if amount > 0 {
+            let prev_amount = self.protocol_fees.get(token_in).unwrap_or(0);
+            self.protocol_fees.insert(token_in, &(prev_amount + amount));
+        }
+        amount
+    }
+
+    /// Returns record of the token for single asset join or exit.
+    fn internal_single_asset_record(&self, token: &AccountId) -> Record {
+        assert!(self.finalized, "ERR_NOT_FINALIZED");
+        let record = self.records.get(token).expect("ERR_NOT_BOUND");
+        self.assert_unlocked(token);
+        record
+    }
+
+    /// Amounts of all bound tokens, with `amount` for the given token and zero for the rest.
+    fn single_asset_amounts(&self, token: &AccountId, amount: Balance) -> Vec<U128> {
+        self.tokens
+            .iter()
+            .map(|t| if t == token { amount } else { 0 }.into())
+            .collect()
+    }
+
+    /// Takes `token_in` from the sender's deposit, saves the record and mints pool shares to the sender.
+    fn internal_join_single(
+        &mut self,
+        token_in: &AccountId,
+        record: &Record,
+        token_amount_in: Balance,
+        pool_amount_out: Balance,
+    ) {
+        self.pull_underlying(token_in, &env::predecessor_account_id(), token_amount_in);
+        self.records.insert(token_in, record);
+        self.mint_pool_share(pool_amount_out);
+        self.push_pool_share(env::predecessor_account_id(), pool_amount_out);
+        Event::Join {
+            account_id: &env::predecessor_account_id(),
+            pool_amount_out: pool_amount_out.into(),
+            amounts_in: self.single_asset_amounts(token_in, token_amount_in),
+        }
+        .emit();
+    }
+
+    fn internal_exit_single(
+        &mut self,
+        token_out: &AccountId,
+        record: &Record,
+        token_amount_out: Balance,
+        pool_amount_in: Balance,
+    ) {
+        let exit_fee = bmul(pool_amount_in, self.exit_fee);
+        self.records.insert(token_out, record);
+        self.pull_pool_share(env::predecessor_account_id(), pool_amount_in);
+        self.record_fee(&env::current_account_id(), exit_fee);
+        self.burn_pool_share(pool_amount_in - exit_fee);
+        self.push_underlying(
+            token_out.clone(),
+            env::predecessor_account_id(),
+            token_amount_out,
+        );
+        Event::Exit {
+            account_id: &env::predecessor_account_id(),
+            pool_amount_in: pool_amount_in.into(),
+            amounts_out: self.single_asset_amounts(token_out, token_amount_out),
+        }
+        .emit();
+    }
+
+    /// Finalizes pool created with `auto_finalize` once every token is funded and its decimals are known.
+    fn maybe_auto_finalize(&mut self) {
+        if self.auto_finalize
+            && !self.finalized
+            && self.tokens.iter().all(|token| {
+                let record = self.records.get(token).unwrap();
+                record.balance >= MIN_BALANCE && record.decimals.is_some()
+            })
+        {
+            self.internal_finalize();
+        }
+    }
+
+    /// Registers account for pool shares, with the storage paid by the pool.
+    fn internal_register_if_missing(&mut self, account_id: &AccountId) {
+        if !self.token.accounts.contains_key(account_id) {
+            self.token.internal_register_account(account_id);
+        }
+    }
+
+    /// Makes the pool public and issues initial supply of pool shares to the controller.
+    fn internal_finalize(&mut self) {
+        for token in self.tokens.iter() {
+            assert!(
+                self.records.get(token).unwrap().decimals.is_some(),
+                "ERR_DECIMALS_UNKNOWN"
+            );
+        }
+        self.finalized = true;
+        self.public_swap = true;
+
+        // Controller could have been changed since the pool was created.
+        self.internal_register_if_missing(&self.controller.clone());
+        self.mint_pool_share(INIT_POOL_SUPPLY);
+        self.push_pool_share(self.controller.clone(), INIT_POOL_SUPPLY);
+    }
+
+    /// Updates weight and balance of the bound token. The difference in balance is taken from the sender's
+    /// deposit or pushed to the sender.
+    fn internal_rebind(&mut self, token: &AccountId, balance: Balance, denorm: Weight) {
+        assert!(denorm >= MIN_WEIGHT, "ERR_MIN_WEIGHT");
+        assert!(denorm <= MAX_WEIGHT, "ERR_MAX_WEIGHT");
+        assert!(balance >= MIN_BALANCE, "ERR_MIN_BALANCE");
+
+        let mut record = self.records.get(token).unwrap();
+        let old_weight = record.denorm;
+        record.denorm = denorm;
+        if denorm > old_weight {
+            self.total_weight = self.total_weight + (denorm - old_weight);
+        } else {
+            self.total_weight = self.total_weight - (old_weight - denorm);
+        }
+
+        let old_balance = record.balance;
+        record.balance = balance;
+        if balance > old_balance {
+            self.pull_underlying(token, &env::predecessor_account_id(), balance - old_balance);
+        } else {
+            let token_balance_withdrawn = old_balance - balance;
+            let token_exit_fee = bmul(token_balance_withdrawn, self.exit_fee);
+            self.push_underlying(
+                token.clone(),
+                env::predecessor_account_id(),
+                token_balance_withdrawn - token_exit_fee,
+            );
+            self.record_fee(token, token_exit_fee);
+        }
+        self.records.insert(token, &record);
+    }
+
+    /// Requests metadata of the token to learn its decimals.
+    fn fetch_decimals(&self, token: &AccountId) -> Promise {
+        ext_ft_metadata::ft_metadata(token, NO_DEPOSIT, gas::FT_METADATA).then(
+            ext_self::on_metadata(
+                token.clone(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                gas::ON_METADATA_CALLBACK,
+            ),
+        )
+    }
+
+    fn internal_set_decimals(&mut self, token: &AccountId, decimals: u8) {
+        // Token could have been unbound while metadata was in flight.
+        if let Some(mut record) = self.records.get(token) {
+            record.decimals = Some(decimals);
+            self.records.insert(token, &record);
+            self.maybe_auto_finalize();
+        }
+    }
+
+    /// Adds `amount` of `token` to the deposit of the account.
+    fn internal_deposit(&mut self, account_id: &AccountId, token: &AccountId, amount: Balance) {
+        let key = (account_id.clone(), token.clone());
+        let deposit = self.deposits.get(&key).unwrap_or(0);
+        self.deposits.insert(&key, &(deposit + amount));
+    }
+
+    /// Removes `amount` of `token` from the deposit of the account.
+    fn internal_withdraw_deposit(
+        &mut self,
+        account_id: &AccountId,
+        token: &AccountId,
+        amount: Balance,
+    ) {
+        let key = (account_id.clone(), token.clone());
+        let deposit = self.deposits.get(&key).unwrap_or(0);
+        assert!(deposit >= amount, "ERR_NOT_ENOUGH_DEPOSIT");
+        if deposit == amount {
+            self.deposits.remove(&key);
+        } else {
+            self.deposits.insert(&key, &(deposit - amount));
+        }
+    }
+
+    /// Takes `amount` of `token` from the deposit of `from` into the pool.
+    fn pull_underlying(&mut self, token: &AccountId, from: &AccountId, amount: Balance) {
+        self.internal_withdraw_deposit(from, token, amount);
+    }
+
+    /// Sends `amount` of `token` to `to`, crediting it to the deposit of `to` in `on_push` if the transfer fails.
     fn push_underlying(&mut self, token: AccountId, to: AccountId, amount: Balance) -> Promise {
-        ext_nep21::transfer(
+        ext_fungible_token::ft_transfer(
             to.clone(),
             amount.into(),
+            None,
             &token,
-            NO_DEPOSIT,
-            gas::NEP21_TRANSFER,
+            ONE_YOCTO,
+            gas::FT_TRANSFER,
         )
         .then(ext_self::on_push(
+            token,
+            to,
+            amount.into(),
             &env::current_account_id(),
             NO_DEPOSIT,
             gas::ON_PUSH_CALLBACK,
         ))
     }
 
+    /// Records exit fee that stays on this contract until the factory collects it.
+    fn record_fee(&mut self, token: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let prev_amount = self.collected_fees.get(token).unwrap_or(0);
+            self.collected_fees.insert(token, &(prev_amount + amount));
+        }
+    }
+
     fn mint_pool_share(&mut self, amount: Balance) {
-        self.token.mint(env::current_account_id(), amount)
+        self.token
+            .internal_deposit(&env::current_account_id(), amount)
     }
 
     fn burn_pool_share(&mut self, amount: Balance) {
-        self.token.burn(env::current_account_id(), amount)
+        self.token
+            .internal_withdraw(&env::current_account_id(), amount)
     }
 
+    /// Moves pool shares of `from` to the pool. Only called on behalf of `from` itself.
     fn pull_pool_share(&mut self, from: AccountId, amount: Balance) {
+        self.internal_checkpoint_shares(&from);
         self.token
-            .transfer_from(from, env::current_account_id(), amount)
+            .internal_transfer(&from, &env::current_account_id(), amount, None)
     }
 
+    /// Receiver must be registered, see `storage_deposit`.
     fn push_pool_share(&mut self, to: AccountId, amount: Balance) {
+        self.internal_checkpoint_shares(&to);
         self.token
-            .transfer_from(env::current_account_id(), to, amount)
+            .internal_transfer(&env::current_account_id(), &to, amount, None)
     }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
 mod tests {
+    use std::convert::TryInto;
+
     use super::*;
+    use near_lib::context::testing_env_with_promise_results;
+    use near_lib::upgrade::Upgradable;
     use near_sdk::{testing_env, MockedBlockchain, VMContext};
 
     fn pool_account() -> AccountId {
@@ -452,7 +1788,7 @@ mod tests {
             account_balance,
             account_locked_balance,
             storage_usage: 10u64.pow(6),
-            attached_deposit: 0,
+            attached_deposit: to_yocto(1),
             prepaid_gas: 10u64.pow(15),
             random_seed: vec![0, 1, 2],
             is_view,
@@ -464,12 +1800,80 @@ mod tests {
         amount * 10u128.pow(24)
     }
 
+    /// Deposits `amount` of `token` from the account via `ft_on_transfer`, then switches back to the account.
+    fn deposit(pool: &mut BPool, account_id: &AccountId, token: AccountId, amount: Balance) {
+        testing_env!(get_context(token, to_yocto(10), 0, false));
+        pool.ft_on_transfer(account_id.clone(), amount.into(), "".to_string());
+        testing_env!(get_context(account_id.clone(), to_yocto(10), 0, false));
+    }
+
     #[test]
     fn test_setup_pool() {
         let context = get_context(factory_account(), to_yocto(10), 0, false);
         testing_env!(context.clone());
         let mut pool = BPool::new();
-        assert_eq!(pool.getController(), factory_account());
+        assert_eq!(pool.get_controller(), factory_account());
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token1_account(),
+            to_yocto(50_000),
+        );
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token2_account(),
+            to_yocto(1_000_000),
+        );
+        pool.bind(
+            token1_account(),
+            to_yocto(50_000).into(),
+            to_yocto(10).into(),
+        );
+        pool.bind(
+            token2_account(),
+            to_yocto(1_000_000).into(),
+            to_yocto(10).into(),
+        );
+        pool.internal_set_decimals(&token1_account(), 24);
+        pool.internal_set_decimals(&token2_account(), 24);
+        pool.finalize();
+        assert_eq!(
+            pool.get_normalized_weight(token1_account()),
+            (BONE / 2).into()
+        );
+        assert_eq!(
+            pool.get_spot_price(token1_account(), token2_account()),
+            U128(50_000_050_000_050_000_050_000)
+        );
+        let info = pool.get_pool_info();
+        assert_eq!(info.tokens, vec![token1_account(), token2_account()]);
+        assert_eq!(info.total_shares, INIT_POOL_SUPPLY.into());
+        assert!(info.finalized && info.public_swap);
+        assert_eq!(
+            pool.ft_balance_of(factory_account().try_into().unwrap()),
+            INIT_POOL_SUPPLY.into()
+        );
+        assert_eq!(pool.ft_metadata().symbol, "BPT");
+    }
+
+    /// Finalized pool of token1 and token2 with `factory_account()` as the controller.
+    fn setup_finalized_pool() -> BPool {
+        let context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token1_account(),
+            to_yocto(50_000),
+        );
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token2_account(),
+            to_yocto(1_000_000),
+        );
         pool.bind(
             token1_account(),
             to_yocto(50_000).into(),
@@ -480,7 +1884,659 @@ mod tests {
             to_yocto(1_000_000).into(),
             to_yocto(10).into(),
         );
+        pool.internal_set_decimals(&token1_account(), 24);
+        pool.internal_set_decimals(&token2_account(), 24);
+        pool.finalize();
+        pool
+    }
+
+    /// Registers the user with `setup_finalized_pool` and deposits given amounts of token1 and token2.
+    fn setup_user(pool: &mut BPool, user: &AccountId, amount1: Balance, amount2: Balance) {
+        testing_env!(get_context(user.clone(), to_yocto(10), 0, false));
+        pool.internal_register_if_missing(user);
+        if amount1 > 0 {
+            deposit(pool, user, token1_account(), amount1);
+        }
+        if amount2 > 0 {
+            deposit(pool, user, token2_account(), amount2);
+        }
+    }
+
+    #[test]
+    fn test_join_exit() {
+        let mut pool = setup_finalized_pool();
+        let user = "user".to_string();
+        setup_user(&mut pool, &user, to_yocto(5_000), to_yocto(100_000));
+
+        // Join with 10% of the pool shares.
+        let expected_amounts_in = pool.get_join_pool_amounts_in(to_yocto(10).into());
+        let amounts_in = pool.join_pool(
+            to_yocto(10).into(),
+            vec![to_yocto(5_000).into(), to_yocto(100_000).into()],
+        );
+        assert_eq!(
+            amounts_in,
+            vec![to_yocto(5_000).into(), to_yocto(100_000).into()]
+        );
+        assert_eq!(amounts_in, expected_amounts_in);
+        assert_eq!(
+            pool.get_token_balance(token1_account()),
+            to_yocto(55_000).into()
+        );
+        assert_eq!(pool.get_deposit(user.clone(), token1_account()), 0.into());
+        assert_eq!(
+            pool.ft_balance_of(user.clone().try_into().unwrap()),
+            to_yocto(10).into()
+        );
+
+        let expected_amounts_out = pool.get_exit_pool_amounts_out(to_yocto(10).into());
+        let amounts_out = pool.exit_pool(to_yocto(10).into(), vec![1.into(), 1.into()]);
+        // Rounding of the ratio is at most few thousands yocto in favor of the pool.
+        assert!(amounts_out[0].0 <= to_yocto(5_000) && amounts_out[0].0 > to_yocto(5_000) - 10_000);
+        assert!(
+            amounts_out[1].0 <= to_yocto(100_000) && amounts_out[1].0 > to_yocto(100_000) - 10_000
+        );
+        assert_eq!(amounts_out, expected_amounts_out);
+        assert_eq!(pool.ft_balance_of(user.try_into().unwrap()), 0.into());
+        assert_eq!(pool.ft_total_supply(), INIT_POOL_SUPPLY.into());
+        assert_eq!(
+            pool.get_token_balance(token1_account()).0,
+            to_yocto(55_000) - amounts_out[0].0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_DEPOSIT")]
+    fn test_join_without_deposit() {
+        let mut pool = setup_finalized_pool();
+        let user = "user".to_string();
+        setup_user(&mut pool, &user, to_yocto(5_000), 0);
+        pool.join_pool(
+            to_yocto(10).into(),
+            vec![to_yocto(5_000).into(), to_yocto(100_000).into()],
+        );
+    }
+
+    #[test]
+    fn test_exit_push_failed() {
+        let mut pool = setup_finalized_pool();
+        let amounts_out = pool.exit_pool(to_yocto(10).into(), vec![1.into(), 1.into()]);
+        testing_env_with_promise_results(
+            get_context(pool_account(), to_yocto(10), 0, false),
+            PromiseResult::Failed,
+        );
+        assert!(!pool.on_push(token1_account(), factory_account(), amounts_out[0]));
+        assert_eq!(
+            pool.get_deposit(factory_account(), token1_account()),
+            amounts_out[0]
+        );
+
+        testing_env!(get_context(factory_account(), to_yocto(10), 0, false));
+        pool.withdraw(token1_account(), amounts_out[0]);
+        assert_eq!(
+            pool.get_deposit(factory_account(), token1_account()),
+            0.into()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_LIMIT_IN")]
+    fn test_join_limit_in() {
+        let mut pool = setup_finalized_pool();
+        let user = "user".to_string();
+        testing_env!(get_context(user.clone(), to_yocto(10), 0, false));
+        pool.internal_register_if_missing(&user);
+        pool.join_pool(
+            to_yocto(10).into(),
+            vec![to_yocto(4_999).into(), to_yocto(100_000).into()],
+        );
+    }
+
+    #[test]
+    fn test_new_simple() {
+        let mut context = get_context(factory_account(), to_yocto(10), 0, false);
+        context.signer_account_id = "owner".to_string();
+        testing_env!(context.clone());
+        let mut pool = BPool::new_simple(token1_account(), token2_account(), MIN_FEE.into());
+        assert_eq!(pool.get_controller(), "owner".to_string());
+        assert_eq!(pool.get_num_tokens(), 2);
+        pool.internal_set_decimals(&token1_account(), 24);
+        pool.internal_set_decimals(&token2_account(), 24);
+
+        context.predecessor_account_id = token1_account();
+        testing_env!(context.clone());
+        pool.ft_on_transfer("owner".to_string(), to_yocto(10).into(), "".to_string());
+        assert!(!pool.is_finalized());
+        context.predecessor_account_id = token2_account();
+        testing_env!(context.clone());
+        pool.ft_on_transfer("owner".to_string(), to_yocto(20).into(), "".to_string());
+        assert!(pool.is_finalized());
+        assert_eq!(
+            pool.get_token_balance(token2_account()),
+            to_yocto(20).into()
+        );
+
+        // Once finalized, transfers go to the deposit of the sender.
+        pool.ft_on_transfer("owner".to_string(), to_yocto(5).into(), "".to_string());
+        assert_eq!(
+            pool.get_token_balance(token2_account()),
+            to_yocto(20).into()
+        );
+        assert_eq!(
+            pool.get_deposit("owner".to_string(), token2_account()),
+            to_yocto(5).into()
+        );
+    }
+
+    #[test]
+    fn test_unbind() {
+        let context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token1_account(),
+            to_yocto(10),
+        );
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token2_account(),
+            to_yocto(10),
+        );
+        pool.bind(token1_account(), to_yocto(10).into(), to_yocto(10).into());
+        pool.bind(token2_account(), to_yocto(10).into(), to_yocto(20).into());
+        pool.unbind(token1_account());
+        assert_eq!(pool.get_current_tokens(), vec![token2_account()]);
+        assert_eq!(pool.get_total_denormalized_weight(), to_yocto(20).into());
+        assert!(!pool.is_bound(token1_account()));
+        assert_eq!(
+            pool.get_token_balance(token2_account()),
+            to_yocto(10).into()
+        );
+    }
+
+    #[test]
+    fn test_decimals_normalization() {
+        let context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token1_account(),
+            to_yocto(1_000),
+        );
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token2_account(),
+            2_000 * 10u128.pow(6),
+        );
+        // 1 token1 (24 decimals) is worth 2 token2 (6 decimals).
+        pool.bind(
+            token1_account(),
+            to_yocto(1_000).into(),
+            to_yocto(10).into(),
+        );
+        pool.bind(
+            token2_account(),
+            (2_000 * 10u128.pow(6)).into(),
+            to_yocto(10).into(),
+        );
+        pool.internal_set_decimals(&token1_account(), 24);
+        pool.internal_set_decimals(&token2_account(), 6);
+        assert_eq!(pool.get_decimals(token2_account()), Some(6));
+        assert_eq!(
+            pool.get_spot_price_sans_fee(token2_account(), token1_account()),
+            U128(2 * BONE)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DECIMALS_UNKNOWN")]
+    fn test_finalize_requires_decimals() {
+        let context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token1_account(),
+            to_yocto(10),
+        );
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token2_account(),
+            to_yocto(10),
+        );
+        pool.bind(token1_account(), to_yocto(10).into(), to_yocto(10).into());
+        pool.bind(token2_account(), to_yocto(10).into(), to_yocto(10).into());
         pool.finalize();
-        assert_eq!(pool.getSpotPrice(token1_account(), token2_account()), 1);
+    }
+
+    #[test]
+    fn test_gradual_weights() {
+        let mut context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token1_account(),
+            to_yocto(10),
+        );
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token2_account(),
+            to_yocto(10),
+        );
+        pool.bind(token1_account(), to_yocto(10).into(), to_yocto(10).into());
+        pool.bind(token2_account(), to_yocto(10).into(), to_yocto(10).into());
+        pool.set_can_change_weights(true);
+        pool.update_weights_gradually(
+            vec![to_yocto(10).into(), to_yocto(40).into()],
+            100.into(),
+            200.into(),
+        );
+
+        context.block_timestamp = 150;
+        testing_env!(context.clone());
+        pool.poke_weights();
+        assert_eq!(
+            pool.get_denormalized_weight(token2_account()),
+            to_yocto(25).into()
+        );
+        assert_eq!(pool.get_total_denormalized_weight(), to_yocto(35).into());
+
+        context.block_timestamp = 250;
+        testing_env!(context.clone());
+        pool.poke_weights();
+        assert_eq!(
+            pool.get_denormalized_weight(token2_account()),
+            to_yocto(40).into()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_GRADUAL_UPDATE_PENDING")]
+    fn test_unbind_during_gradual_update() {
+        let context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context);
+        let mut pool = BPool::new();
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token1_account(),
+            to_yocto(10),
+        );
+        deposit(
+            &mut pool,
+            &factory_account(),
+            token2_account(),
+            to_yocto(10),
+        );
+        pool.bind(token1_account(), to_yocto(10).into(), to_yocto(10).into());
+        pool.bind(token2_account(), to_yocto(10).into(), to_yocto(10).into());
+        pool.set_can_change_weights(true);
+        pool.update_weights_gradually(
+            vec![to_yocto(10).into(), to_yocto(40).into()],
+            100.into(),
+            200.into(),
+        );
+        pool.unbind(token1_account());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_REENTRANCY")]
+    fn test_swap_during_flash_swap() {
+        let mut pool = setup_finalized_pool();
+        let receiver = "receiver".to_string();
+        pool.set_flash_swap_receiver(receiver.clone(), true);
+        testing_env!(get_context(receiver, to_yocto(10), 0, false));
+        pool.flash_swap_exact_amount_out(
+            token1_account(),
+            to_yocto(1_000).into(),
+            token2_account(),
+            to_yocto(10_000).into(),
+            to_yocto(100).into(),
+            "".to_string(),
+        );
+        let user = "user".to_string();
+        setup_user(&mut pool, &user, to_yocto(1_000), 0);
+        pool.swap_exact_amount_in(
+            token1_account(),
+            to_yocto(1_000).into(),
+            token2_account(),
+            1.into(),
+            to_yocto(100).into(),
+        );
+    }
+
+    #[test]
+    fn test_controller_transfer() {
+        let mut context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        pool.propose_controller("owner".to_string());
+        assert_eq!(pool.get_controller(), factory_account());
+        assert_eq!(pool.get_pending_controller(), Some("owner".to_string()));
+
+        context.predecessor_account_id = "owner".to_string();
+        testing_env!(context.clone());
+        pool.accept_controller();
+        assert_eq!(pool.get_controller(), "owner".to_string());
+        assert_eq!(pool.get_pending_controller(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_PENDING_CONTROLLER")]
+    fn test_controller_transfer_wrong_account() {
+        let mut context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        pool.propose_controller("owner".to_string());
+
+        context.predecessor_account_id = "other".to_string();
+        testing_env!(context.clone());
+        pool.accept_controller();
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        pool.stage_code(vec![1, 2, 3], UPGRADE_STAGING_DURATION);
+        assert_eq!(
+            pool.get_staging_timestamp(),
+            Some(UPGRADE_STAGING_DURATION.into())
+        );
+
+        context.block_timestamp = UPGRADE_STAGING_DURATION;
+        testing_env!(context.clone());
+        pool.deploy_code();
+        assert_eq!(pool.get_staging_timestamp(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STAGING_NOT_OVER")]
+    fn test_upgrade_too_early() {
+        let mut context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        pool.stage_code(vec![1, 2, 3], UPGRADE_STAGING_DURATION);
+
+        context.block_timestamp = UPGRADE_STAGING_DURATION - 1;
+        testing_env!(context.clone());
+        pool.deploy_code();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_STORAGE_DEPOSIT")]
+    fn test_bind_requires_storage_deposit() {
+        let mut context = get_context(factory_account(), to_yocto(10), 0, false);
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        pool.bind(token1_account(), to_yocto(10).into(), to_yocto(10).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_IS_PAUSED")]
+    fn test_pause_blocks_bind() {
+        let context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        pool.pause();
+        assert!(pool.is_paused());
+        pool.bind(
+            token1_account(),
+            to_yocto(50_000).into(),
+            to_yocto(10).into(),
+        );
+    }
+
+    #[test]
+    fn test_swap_exact_amount_in() {
+        let mut pool = setup_finalized_pool();
+        let user = "user".to_string();
+        setup_user(&mut pool, &user, to_yocto(1_000), 0);
+        assert_eq!(
+            pool.get_max_amount_in(token1_account()),
+            to_yocto(25_000).into()
+        );
+        // 50/50 pool of 50k token1 and 1M token2: 1000 token1 buy 1M * 1000 / 51000 token2 less the fee.
+        let amount_out = pool.swap_exact_amount_in(
+            token1_account(),
+            to_yocto(1_000).into(),
+            token2_account(),
+            to_yocto(19_607).into(),
+            to_yocto(100).into(),
+        );
+        assert!(amount_out.0 > to_yocto(19_607) && amount_out.0 < to_yocto(19_608));
+        assert_eq!(
+            pool.get_token_balance(token1_account()),
+            to_yocto(51_000).into()
+        );
+        assert_eq!(
+            pool.get_token_balance(token2_account()).0,
+            to_yocto(1_000_000) - amount_out.0
+        );
+        assert_eq!(pool.get_deposit(user, token1_account()), 0.into());
+    }
+
+    #[test]
+    fn test_protocol_fee() {
+        let mut pool = setup_finalized_pool();
+        pool.swap_fee = MAX_FEE;
+        testing_env!(get_context(factory_account(), to_yocto(10), 0, false));
+        pool.set_protocol_fee(MAX_PROTOCOL_FEE.into());
+        setup_user(&mut pool, &"user".to_string(), to_yocto(1_000), 0);
+        pool.swap_exact_amount_in(
+            token1_account(),
+            to_yocto(1_000).into(),
+            token2_account(),
+            1.into(),
+            to_yocto(100).into(),
+        );
+        // Half of the 10% swap fee is kept outside of the pool balance.
+        assert_eq!(
+            pool.get_accrued_protocol_fees(token1_account()),
+            to_yocto(50).into()
+        );
+        assert_eq!(
+            pool.get_token_balance(token1_account()),
+            to_yocto(50_950).into()
+        );
+
+        testing_env!(get_context(factory_account(), to_yocto(10), 0, false));
+        assert_eq!(
+            pool.collect_protocol_fees(token1_account()),
+            to_yocto(50).into()
+        );
+        assert_eq!(pool.get_accrued_protocol_fees(token1_account()), 0.into());
+    }
+
+    #[test]
+    fn test_airdrop() {
+        let mut pool = setup_finalized_pool();
+        let user = "user".to_string();
+        setup_user(&mut pool, &user, to_yocto(5_000), to_yocto(100_000));
+        pool.join_pool(
+            to_yocto(10).into(),
+            vec![to_yocto(5_000).into(), to_yocto(100_000).into()],
+        );
+
+        deposit(
+            &mut pool,
+            &factory_account(),
+            "token3".to_string(),
+            to_yocto(1_100),
+        );
+        let airdrop_id = pool.register_airdrop("token3".to_string(), to_yocto(1_100).into());
+        assert_eq!(pool.get_number_of_airdrops(), 1);
+        assert_eq!(
+            pool.get_airdrop(airdrop_id).unwrap().snapshot_supply,
+            to_yocto(110).into()
+        );
+        assert_eq!(
+            pool.get_deposit(factory_account(), "token3".to_string()),
+            0.into()
+        );
+
+        // Shares moved after the airdrop don't change its distribution.
+        let mut context = get_context(user.clone(), to_yocto(10), 0, false);
+        context.attached_deposit = 1;
+        testing_env!(context);
+        pool.ft_transfer(
+            factory_account().try_into().unwrap(),
+            to_yocto(10).into(),
+            None,
+        );
+        assert_eq!(
+            pool.get_airdrop_claimable(airdrop_id, user.clone()),
+            to_yocto(100).into()
+        );
+        assert_eq!(
+            pool.get_airdrop_claimable(airdrop_id, factory_account()),
+            to_yocto(1_000).into()
+        );
+
+        assert_eq!(pool.claim_airdrop(airdrop_id), to_yocto(100).into());
+        assert_eq!(pool.get_airdrop_claimable(airdrop_id, user), 0.into());
+        assert_eq!(
+            pool.get_airdrop(airdrop_id).unwrap().claimed_amount,
+            to_yocto(100).into()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ALLOWED_LP")]
+    fn test_lp_allowlist() {
+        let mut pool = setup_finalized_pool();
+        pool.set_lp_allowlist_enabled(true);
+        pool.add_to_lp_allowlist(vec!["user".to_string()]);
+        assert_eq!(pool.get_lp_allowlist(0, 10), vec!["user".to_string()]);
+        assert!(pool.is_lp_allowed(factory_account()));
+        assert!(!pool.is_lp_allowed("other".to_string()));
+
+        let other = "other".to_string();
+        setup_user(&mut pool, &other, to_yocto(1_000), to_yocto(1_000));
+        // Swaps are still public.
+        pool.swap_exact_amount_in(
+            token1_account(),
+            to_yocto(1_000).into(),
+            token2_account(),
+            1.into(),
+            to_yocto(100).into(),
+        );
+        pool.join_swap_extern_amount_in(token2_account(), to_yocto(1_000).into(), 1.into());
+    }
+
+    #[test]
+    fn test_flash_swap() {
+        let mut pool = setup_finalized_pool();
+        let receiver = "receiver".to_string();
+        pool.set_flash_swap_receiver(receiver.clone(), true);
+        testing_env!(get_context(receiver.clone(), to_yocto(10), 0, false));
+        pool.flash_swap_exact_amount_out(
+            token1_account(),
+            to_yocto(1_000).into(),
+            token2_account(),
+            to_yocto(10_000).into(),
+            to_yocto(100).into(),
+            "".to_string(),
+        );
+        // Records are updated optimistically, both tokens are locked until the flash swap resolves.
+        assert_eq!(
+            pool.get_token_balance(token2_account()),
+            to_yocto(990_000).into()
+        );
+        assert!(pool.get_token_balance(token1_account()).0 > to_yocto(50_500));
+        assert_eq!(pool.locks.get(&token1_account()), Some(1));
+        assert_eq!(pool.locks.get(&token2_account()), Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_FLASH_SWAP_RECEIVER")]
+    fn test_flash_swap_not_approved() {
+        let mut pool = setup_finalized_pool();
+        testing_env!(get_context("receiver".to_string(), to_yocto(10), 0, false));
+        pool.flash_swap_exact_amount_out(
+            token1_account(),
+            to_yocto(1_000).into(),
+            token2_account(),
+            to_yocto(10_000).into(),
+            to_yocto(100).into(),
+            "".to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MAX_IN_RATIO")]
+    fn test_swap_max_in_ratio() {
+        let mut pool = setup_finalized_pool();
+        testing_env!(get_context("user".to_string(), to_yocto(10), 0, false));
+        pool.swap_exact_amount_in(
+            token1_account(),
+            (to_yocto(25_000) + 1).into(),
+            token2_account(),
+            1.into(),
+            to_yocto(100).into(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MAX_OUT_RATIO")]
+    fn test_exit_single_max_out_ratio() {
+        let mut pool = setup_finalized_pool();
+        testing_env!(get_context(factory_account(), to_yocto(10), 0, false));
+        pool.exit_swap_extern_amount_out(
+            token1_account(),
+            (pool.get_max_amount_out(token1_account()).0 + 1).into(),
+            INIT_POOL_SUPPLY.into(),
+        );
+    }
+
+    #[test]
+    fn test_join_exit_single_asset() {
+        let mut pool = setup_finalized_pool();
+        let user = "user".to_string();
+        setup_user(&mut pool, &user, to_yocto(1_000), 0);
+        // Adding 2% of token1 with half of the weight mints a bit less than 1% of pool shares.
+        let expected_pool_amount_out =
+            pool.calc_pool_out_given_single_in(token1_account(), to_yocto(1_000).into());
+        let pool_amount_out =
+            pool.join_swap_extern_amount_in(token1_account(), to_yocto(1_000).into(), 1.into());
+        assert!(pool_amount_out.0 > to_yocto(99) / 100 && pool_amount_out.0 < to_yocto(1));
+        assert_eq!(pool_amount_out, expected_pool_amount_out);
+        assert_eq!(
+            pool.ft_balance_of(user.clone().try_into().unwrap()),
+            pool_amount_out
+        );
+
+        let expected_amount_out =
+            pool.calc_single_out_given_pool_in(token1_account(), pool_amount_out);
+        let amount_out = pool.exit_swap_pool_amount_in(token1_account(), pool_amount_out, 1.into());
+        assert_eq!(amount_out, expected_amount_out);
+        // Swap fee is charged on the way in and out.
+        assert!(amount_out.0 < to_yocto(1_000) && amount_out.0 > to_yocto(999));
+        assert_eq!(pool.ft_balance_of(user.try_into().unwrap()), 0.into());
+        assert_eq!(pool.ft_total_supply(), INIT_POOL_SUPPLY.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MAX_EXIT_FEE")]
+    fn test_exit_fee_bounds() {
+        let context = get_context(factory_account(), to_yocto(10), 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        pool.set_exit_fee(MAX_EXIT_FEE.into());
+        assert_eq!(pool.get_exit_fee(), MAX_EXIT_FEE.into());
+        pool.set_exit_fee((MAX_EXIT_FEE + 1).into());
     }
 }
@@ -0,0 +1,285 @@
+//! Staged code upgrades via `near_lib::upgrade::Upgradable`, so that long-lived pools can receive fixes:
+//! the factory stages new code, which anyone can deploy after the staging duration has passed.
+//! Code that changes the `BPool` layout is staged with `stage_code_with_migration`, so that `deploy_code`
+//! calls `migrate` on it.
+
+use near_lib::types::{Timestamp, WrappedDuration};
+use near_lib::upgrade::Upgradable;
+use near_sdk::json_types::U64;
+use near_sdk::{env, near_bindgen, Promise};
+
+use crate::*;
+
+const UPGRADE_CODE_KEY: &[u8] = b"upgrade";
+
+#[near_bindgen]
+impl Upgradable for BPool {
+    /// Minimum time between staging the code and deploying it, in nanoseconds.
+    fn get_staging_duration(&self) -> WrappedDuration {
+        UPGRADE_STAGING_DURATION.into()
+    }
+
+    /// Stages code that keeps the `BPool` layout, to be deployed not earlier than `timestamp`.
+    /// Staging again replaces previously staged code.
+    fn stage_code(
+        &mut self,
+        #[serializer(borsh)] code: Vec<u8>,
+        #[serializer(borsh)] timestamp: Timestamp,
+    ) {
+        self.internal_stage_code(code, timestamp, false);
+    }
+
+    /// Deploys staged code, calling `migrate` on it if it was staged with `stage_code_with_migration`.
+    fn deploy_code(&mut self) -> Promise {
+        let timestamp = self.staging_timestamp.take().expect("ERR_NO_STAGED_CODE");
+        assert!(env::block_timestamp() >= timestamp, "ERR_STAGING_NOT_OVER");
+        let code = env::storage_read(UPGRADE_CODE_KEY).expect("ERR_NO_STAGED_CODE");
+        env::storage_remove(UPGRADE_CODE_KEY);
+        let promise = Promise::new(env::current_account_id()).deploy_contract(code);
+        if std::mem::take(&mut self.staging_migration) {
+            promise.function_call(b"migrate".to_vec(), vec![], NO_DEPOSIT, gas::MIGRATE)
+        } else {
+            promise
+        }
+    }
+}
+
+#[near_bindgen]
+impl BPool {
+    /// Returns time when the staged code can be deployed, if any code is staged.
+    pub fn get_staging_timestamp(&self) -> Option<U64> {
+        self.staging_timestamp.map(|timestamp| timestamp.into())
+    }
+
+    /// Whether the staged code is deployed together with a call to its `migrate`.
+    pub fn is_staging_migration(&self) -> bool {
+        self.staging_migration
+    }
+
+    /// Same as `stage_code`, for code that changes the `BPool` layout: `deploy_code` calls `migrate` on it.
+    pub fn stage_code_with_migration(
+        &mut self,
+        #[serializer(borsh)] code: Vec<u8>,
+        #[serializer(borsh)] timestamp: Timestamp,
+    ) {
+        self.internal_stage_code(code, timestamp, true);
+    }
+
+    /// Converts state of the previous version of the contract.
+    /// Must be updated together with any change to the `BPool` layout.
+    /// `Upgradable::migrate` is not used, as the previous state can't be read as `BPool`.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let mut prev: PrevBPool = env::state_read().expect("ERR_NOT_INITIALIZED");
+        let prev_records = prev.records.to_vec();
+        prev.records.clear();
+        let mut pool = Self {
+            controller: prev.controller,
+            pending_controller: None,
+            factory: prev.factory,
+            swap_fee: prev.swap_fee,
+            exit_fee: EXIT_FEE,
+            finalized: prev.finalized,
+            public_swap: prev.public_swap,
+            paused: false,
+            auto_finalize: false,
+            records: UnorderedMap::new(b"r".to_vec()),
+            tokens: prev.tokens,
+            total_weight: prev.total_weight,
+            token: FungibleToken::new(b"t".to_vec()),
+            collected_fees: UnorderedMap::new(b"f".to_vec()),
+            protocol_fee: 0,
+            protocol_fees: UnorderedMap::new(b"p".to_vec()),
+            lp_allowlist_enabled: false,
+            lp_allowlist: UnorderedSet::new(b"q".to_vec()),
+            flash_swap_receivers: UnorderedSet::new(b"x".to_vec()),
+            airdrops: Vector::new(b"d".to_vec()),
+            share_checkpoints: LookupMap::new(b"c".to_vec()),
+            airdrop_claims: LookupSet::new(b"k".to_vec()),
+            locks: UnorderedMap::new(b"l".to_vec()),
+            can_change_weights: false,
+            gradual_update: None,
+            staging_timestamp: None,
+            staging_migration: false,
+            deposits: LookupMap::new(b"w".to_vec()),
+            legacy_shares: prev.token.accounts,
+        };
+        // Shares of the previous version are moved to the new accounts by `claim_legacy_shares`,
+        // which doesn't change the supply.
+        pool.token.total_supply = prev.token.total_supply;
+        pool.internal_register_if_missing(&env::current_account_id());
+        pool.internal_register_if_missing(&pool.controller.clone());
+        for (token, record) in prev_records {
+            pool.records.insert(
+                &token,
+                &Record {
+                    bound: record.bound,
+                    index: record.index,
+                    denorm: record.denorm,
+                    balance: record.balance,
+                    decimals: None,
+                    storage_stake: 0,
+                },
+            );
+            pool.fetch_decimals(&token);
+        }
+        pool
+    }
+
+    /// Moves pool shares the sender held in the previous version of the contract, which were kept by
+    /// the hash of the account id, to its account. Returns the amount moved.
+    pub fn claim_legacy_shares(&mut self) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let mut account = self
+            .legacy_shares
+            .remove(&env::sha256(account_id.as_bytes()))
+            .expect("ERR_NO_LEGACY_SHARES");
+        account.allowances.clear();
+        self.internal_register_if_missing(&account_id);
+        self.internal_checkpoint_shares(&account_id);
+        let balance = self.token.accounts.get(&account_id).unwrap_or(0);
+        self.token
+            .accounts
+            .insert(&account_id, &(balance + account.balance));
+        account.balance.into()
+    }
+}
+
+impl BPool {
+    fn internal_stage_code(&mut self, code: Vec<u8>, timestamp: Timestamp, migration: bool) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.factory,
+            "ERR_NOT_FACTORY"
+        );
+        assert!(
+            timestamp >= env::block_timestamp() + UPGRADE_STAGING_DURATION,
+            "ERR_STAGING_TOO_SHORT"
+        );
+        // Written directly to avoid reading the code with the rest of the state.
+        env::storage_write(UPGRADE_CODE_KEY, &code);
+        self.staging_timestamp = Some(timestamp);
+        self.staging_migration = migration;
+    }
+}
+
+/// Pool share account of the first version of the pool, see `claim_legacy_shares`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyAccount {
+    balance: Balance,
+    /// NEP-21 allowances, which pool shares no longer have.
+    allowances: UnorderedMap<Vec<u8>, Balance>,
+}
+
+/// Layout of `Record` in the first version of the pool.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct PrevRecord {
+    bound: bool,
+    index: u64,
+    denorm: Weight,
+    balance: Balance,
+}
+
+/// Layout of the NEP-21 pool shares in the first version of the pool, keyed by sha256 of the account id.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct PrevToken {
+    accounts: UnorderedMap<Vec<u8>, LegacyAccount>,
+    total_supply: Balance,
+}
+
+/// Layout of `BPool` in the first version of the pool, which is what is deployed.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct PrevBPool {
+    controller: AccountId,
+    factory: AccountId,
+    swap_fee: Balance,
+    finalized: bool,
+    public_swap: bool,
+    records: UnorderedMap<AccountId, PrevRecord>,
+    tokens: Vec<AccountId>,
+    total_weight: Weight,
+    token: PrevToken,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+    use crate::tests::get_context;
+
+    #[test]
+    fn test_migrate_from_first_version() {
+        testing_env!(get_context("pool".to_string(), 10 * BONE, 0, false));
+        let mut records = UnorderedMap::new(b"r".to_vec());
+        for (index, token) in ["token1", "token2"].iter().enumerate() {
+            records.insert(
+                &token.to_string(),
+                &PrevRecord {
+                    bound: true,
+                    index: index as u64,
+                    denorm: 10 * BONE,
+                    balance: 100 * BONE,
+                },
+            );
+        }
+        let user_hash = env::sha256(b"user");
+        let mut accounts = UnorderedMap::new(b"a".to_vec());
+        accounts.insert(
+            &user_hash,
+            &LegacyAccount {
+                balance: 100 * BONE,
+                allowances: UnorderedMap::new(user_hash.clone()),
+            },
+        );
+        env::state_write(&PrevBPool {
+            controller: "factory".to_string(),
+            factory: "factory".to_string(),
+            swap_fee: MIN_FEE,
+            finalized: true,
+            public_swap: true,
+            records,
+            tokens: vec!["token1".to_string(), "token2".to_string()],
+            total_weight: 20 * BONE,
+            token: PrevToken {
+                accounts,
+                total_supply: 100 * BONE,
+            },
+        });
+
+        let mut pool = BPool::migrate();
+        assert_eq!(
+            pool.get_token_balance("token2".to_string()),
+            (100 * BONE).into()
+        );
+        assert_eq!(pool.get_decimals("token1".to_string()), None);
+        assert_eq!(pool.ft_total_supply(), (100 * BONE).into());
+
+        testing_env!(get_context("user".to_string(), 10 * BONE, 0, false));
+        assert_eq!(pool.claim_legacy_shares(), (100 * BONE).into());
+        assert_eq!(
+            pool.ft_balance_of("user".to_string().try_into().unwrap()),
+            (100 * BONE).into()
+        );
+        assert_eq!(pool.ft_total_supply(), (100 * BONE).into());
+    }
+
+    #[test]
+    fn test_deploy_with_migration() {
+        let mut context = get_context("factory".to_string(), 10 * BONE, 0, false);
+        testing_env!(context.clone());
+        let mut pool = BPool::new();
+        pool.stage_code_with_migration(vec![1, 2, 3], UPGRADE_STAGING_DURATION);
+        assert!(pool.is_staging_migration());
+
+        context.block_timestamp = UPGRADE_STAGING_DURATION;
+        testing_env!(context);
+        pool.deploy_code();
+        assert!(!pool.is_staging_migration());
+        assert_eq!(pool.get_staging_timestamp(), None);
+    }
+}
@@ -27,15 +27,15 @@ impl BPool {
         Self { contract_id }
     }
 
-    pub fn getController(&self, runtime: &mut TestRuntime) -> AccountId {
-        runtime.view(self.contract_id.clone(), "getController", json!({}))
+    pub fn get_controller(&self, runtime: &mut TestRuntime) -> AccountId {
+        runtime.view(self.contract_id.clone(), "get_controller", json!({}))
             .as_str()
             .unwrap()
             .to_string()
     }
 
-    pub fn getNumTokens(&self, runtime: &mut TestRuntime) -> u64 {
-        runtime.view(self.contract_id.clone(), "getNumTokens", json!({}))
+    pub fn get_num_tokens(&self, runtime: &mut TestRuntime) -> u64 {
+        runtime.view(self.contract_id.clone(), "get_num_tokens", json!({}))
             .as_u64()
             .unwrap()
     }
@@ -91,8 +91,8 @@ fn setup_multi_token_pool() -> (
 fn multi_token_pool() {
     let (mut user, pool, weth, mkr, dai, xxx) = setup_multi_token_pool();
     let root = "root".to_string();
-    assert_eq!(pool.getController(&mut user), root);
-    assert_eq!(pool.getNumTokens(&mut user), 0);
+    assert_eq!(pool.get_controller(&mut user), root);
+    assert_eq!(pool.get_num_tokens(&mut user), 0);
 }
 
 #[test]
@@ -0,0 +1,105 @@
+//! Entry and exit fees: a share of every deposit for minting and of the NEAR returned for burning
+//! is forwarded to the beneficiary, e.g. the treasury of the organization.
+
+use near_sdk::serde::Deserialize;
+
+use crate::*;
+
+/// Fees are given in basis points.
+const FEE_DIVISOR: u32 = 10_000;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Fees {
+    /// Account receiving the fees.
+    pub beneficiary: AccountId,
+    /// Fee taken from the deposit for minting.
+    pub entry_fee_bps: u32,
+    /// Fee taken from the NEAR returned for burning.
+    pub exit_fee_bps: u32,
+}
+
+impl Fees {
+    pub fn assert_valid(&self) {
+        assert!(
+            env::is_valid_account_id(self.beneficiary.as_bytes()),
+            "ERR_INVALID_BENEFICIARY"
+        );
+        assert!(
+            self.entry_fee_bps < FEE_DIVISOR && self.exit_fee_bps < FEE_DIVISOR,
+            "ERR_INVALID_FEE"
+        );
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectedFeesView {
+    /// Total NEAR taken from the deposits for minting.
+    pub entry_fees: U128,
+    /// Total NEAR taken from burning.
+    pub exit_fees: U128,
+}
+
+/// Returns `amount * fee_bps / FEE_DIVISOR` rounded down.
+fn calc_fee(amount: Balance, fee_bps: u32) -> Balance {
+    (U256::from(amount) * U256::from(fee_bps) / U256::from(FEE_DIVISOR)).as_u128()
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_fees(&self) -> Option<Fees> {
+        self.fees.clone()
+    }
+
+    /// Returns fees forwarded to the beneficiary so far.
+    pub fn get_collected_fees(&self) -> CollectedFeesView {
+        CollectedFeesView {
+            entry_fees: self.entry_fees_collected.into(),
+            exit_fees: self.exit_fees_collected.into(),
+        }
+    }
+}
+
+impl Contract {
+    /// Returns the entry fee taken from `deposit`.
+    pub(crate) fn internal_entry_fee(&self, deposit: Balance) -> Balance {
+        self.fees
+            .as_ref()
+            .map(|fees| calc_fee(deposit, fees.entry_fee_bps))
+            .unwrap_or(0)
+    }
+
+    /// Returns the deposit which leaves at least `amount` after the entry fee.
+    pub(crate) fn internal_with_entry_fee(&self, amount: Balance) -> Balance {
+        match self.fees.as_ref() {
+            Some(fees) => {
+                let divisor = U256::from(FEE_DIVISOR);
+                let (deposit, remainder) = (U256::from(amount) * divisor)
+                    .div_mod(divisor - U256::from(fees.entry_fee_bps));
+                if remainder.is_zero() {
+                    deposit.as_u128()
+                } else {
+                    deposit.as_u128() + 1
+                }
+            }
+            None => amount,
+        }
+    }
+
+    /// Returns the exit fee taken from `return_amount`.
+    pub(crate) fn internal_exit_fee(&self, return_amount: Balance) -> Balance {
+        self.fees
+            .as_ref()
+            .map(|fees| calc_fee(return_amount, fees.exit_fee_bps))
+            .unwrap_or(0)
+    }
+
+    /// Forwards the fee to the beneficiary.
+    pub(crate) fn internal_send_fee(&self, fee: Balance) {
+        if fee > 0 {
+            let beneficiary = self.fees.as_ref().unwrap().beneficiary.clone();
+            self.internal_send_reserve(beneficiary, fee);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Randomized tests of the fixed-point bonding curve math against `f64` references,
+//! which use `ln_1p` and `exp_m1` to stay precise for small trades.
+
+use near_sdk::Balance;
+use near_test::fuzz::gen_amount;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::math::{calc_purchase_amount, calc_purchase_cost, calc_sale_amount};
+
+const ITERATIONS: usize = 1_000;
+const MAX_RESERVE_RATIO: u32 = 1_000_000;
+/// Relative error allowed on top of one unit of rounding, bounded by the precision of the references.
+const REL_ERROR: f64 = 1e-12;
+
+/// Returns random supply, reserve balance and reserve ratio.
+/// The max ratio, which is computed without powers, is drawn often enough to be covered.
+fn gen_curve(rng: &mut StdRng) -> (Balance, Balance, u32) {
+    let reserve_ratio = if rng.gen_bool(0.1) {
+        MAX_RESERVE_RATIO
+    } else {
+        rng.gen_range(10_000, MAX_RESERVE_RATIO)
+    };
+    (
+        gen_amount(rng, 10u128.pow(18), 10u128.pow(30)),
+        gen_amount(rng, 10u128.pow(18), 10u128.pow(30)),
+        reserve_ratio,
+    )
+}
+
+fn assert_close(actual: Balance, expected: f64, inputs: &str) {
+    let diff = (actual as f64 - expected).abs();
+    assert!(
+        diff <= 1.0 + expected * REL_ERROR,
+        "{}: got {}, expected {}, error {}",
+        inputs,
+        actual,
+        expected,
+        diff
+    );
+}
+
+#[test]
+fn fuzz_purchase_amount() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..ITERATIONS {
+        let (supply, reserve_balance, reserve_ratio) = gen_curve(&mut rng);
+        let deposit = gen_amount(
+            &mut rng,
+            reserve_balance / 10u128.pow(9),
+            reserve_balance * 1000,
+        );
+        let ratio = reserve_ratio as f64 / MAX_RESERVE_RATIO as f64;
+        let expected =
+            supply as f64 * (ratio * (deposit as f64 / reserve_balance as f64).ln_1p()).exp_m1();
+        assert_close(
+            calc_purchase_amount(supply, reserve_balance, reserve_ratio, deposit),
+            expected,
+            &format!(
+                "calc_purchase_amount({}, {}, {}, {})",
+                supply, reserve_balance, reserve_ratio, deposit
+            ),
+        );
+    }
+}
+
+#[test]
+fn fuzz_sale_amount() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..ITERATIONS {
+        let (supply, reserve_balance, reserve_ratio) = gen_curve(&mut rng);
+        let sell_amount = gen_amount(&mut rng, supply / 10u128.pow(9), supply);
+        let ratio = reserve_ratio as f64 / MAX_RESERVE_RATIO as f64;
+        let expected = -(reserve_balance as f64)
+            * ((-(sell_amount as f64) / supply as f64).ln_1p() / ratio).exp_m1();
+        assert_close(
+            calc_sale_amount(supply, reserve_balance, reserve_ratio, sell_amount),
+            expected,
+            &format!(
+                "calc_sale_amount({}, {}, {}, {})",
+                supply, reserve_balance, reserve_ratio, sell_amount
+            ),
+        );
+    }
+}
+
+#[test]
+fn fuzz_purchase_cost() {
+    let mut rng = StdRng::seed_from_u64(3);
+    for _ in 0..ITERATIONS {
+        let (supply, reserve_balance, reserve_ratio) = gen_curve(&mut rng);
+        let amount = gen_amount(&mut rng, supply / 10u128.pow(9), supply / 10);
+        let ratio = reserve_ratio as f64 / MAX_RESERVE_RATIO as f64;
+        let expected =
+            reserve_balance as f64 * ((amount as f64 / supply as f64).ln_1p() / ratio).exp_m1();
+        let cost = calc_purchase_cost(supply, reserve_balance, reserve_ratio, amount);
+        let inputs = format!(
+            "calc_purchase_cost({}, {}, {}, {})",
+            supply, reserve_balance, reserve_ratio, amount
+        );
+        assert_close(cost, expected, &inputs);
+        // Depositing the cost mints at least the amount.
+        assert!(
+            calc_purchase_amount(supply, reserve_balance, reserve_ratio, cost) >= amount,
+            "{}: not enough to purchase",
+            inputs
+        );
+    }
+}
+
+/// Buying and immediately selling back never returns more than was deposited.
+#[test]
+fn fuzz_round_trip() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for _ in 0..ITERATIONS {
+        let (supply, reserve_balance, reserve_ratio) = gen_curve(&mut rng);
+        let deposit = gen_amount(
+            &mut rng,
+            reserve_balance / 10u128.pow(9),
+            reserve_balance * 1000,
+        );
+        let minted = calc_purchase_amount(supply, reserve_balance, reserve_ratio, deposit);
+        if minted == 0 {
+            continue;
+        }
+        let returned = calc_sale_amount(
+            supply + minted,
+            reserve_balance + deposit,
+            reserve_ratio,
+            minted,
+        );
+        assert!(
+            returned <= deposit,
+            "round trip of {} on ({}, {}, {}) returned {}",
+            deposit,
+            supply,
+            reserve_balance,
+            reserve_ratio,
+            returned
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Hatch phase: before the bonding curve opens, contributors buy at a fixed price until `min_raise` is reached.
+//! Once it's reached, the raised NEAR is added to the reserve, contributors can claim their tokens
+//! and the curve opens. If it's not reached by the deadline, contributors can refund their deposits.
+
+use near_sdk::json_types::U64;
+use near_sdk::serde::Deserialize;
+use near_sdk::Timestamp;
+
+use crate::*;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HatchConfig {
+    /// Price in yoctoNEAR of one whole token (`10^decimals` of the smallest units).
+    pub price: U128,
+    /// NEAR to raise for the hatch to succeed.
+    pub min_raise: U128,
+    /// Hatch fails if `min_raise` is not reached by this time.
+    pub deadline: U64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct Contribution {
+    deposit: Balance,
+    tokens: Balance,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Hatch {
+    price: Balance,
+    min_raise: Balance,
+    deadline: Timestamp,
+    /// Total NEAR contributed.
+    raised: Balance,
+    /// Total tokens bought by the contributors.
+    tokens: Balance,
+    /// Set once `min_raise` is reached.
+    hatched: bool,
+    contributions: LookupMap<AccountId, Contribution>,
+}
+
+impl Hatch {
+    pub fn new(config: HatchConfig) -> Self {
+        assert!(
+            config.price.0 > 0
+                && config.min_raise.0 > 0
+                && config.deadline.0 > env::block_timestamp(),
+            "ERR_INVALID_HATCH"
+        );
+        Self {
+            price: config.price.into(),
+            min_raise: config.min_raise.into(),
+            deadline: config.deadline.into(),
+            raised: 0,
+            tokens: 0,
+            hatched: false,
+            contributions: LookupMap::new(b"h".to_vec()),
+        }
+    }
+
+    pub fn is_hatched(&self) -> bool {
+        self.hatched
+    }
+
+    fn is_failed(&self) -> bool {
+        !self.hatched && env::block_timestamp() >= self.deadline
+    }
+
+    /// NEAR contributed by given account that is not claimed or refunded yet.
+    pub fn contributed(&self, account_id: &AccountId) -> Balance {
+        self.contributions
+            .get(account_id)
+            .map(|contribution| contribution.deposit)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HatchView {
+    pub price: U128,
+    pub min_raise: U128,
+    pub deadline: U64,
+    pub raised: U128,
+    pub tokens: U128,
+    pub hatched: bool,
+    pub failed: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContributionView {
+    /// NEAR contributed.
+    pub deposit: U128,
+    /// Tokens to claim once hatched.
+    pub tokens: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_hatch(&self) -> Option<HatchView> {
+        self.hatch.as_ref().map(|hatch| HatchView {
+            price: hatch.price.into(),
+            min_raise: hatch.min_raise.into(),
+            deadline: hatch.deadline.into(),
+            raised: hatch.raised.into(),
+            tokens: hatch.tokens.into(),
+            hatched: hatch.hatched,
+            failed: hatch.is_failed(),
+        })
+    }
+
+    /// Returns unclaimed contribution of given account to the hatch.
+    pub fn get_hatch_contribution(&self, account_id: ValidAccountId) -> ContributionView {
+        let contribution = self
+            .hatch
+            .as_ref()
+            .and_then(|hatch| hatch.contributions.get(account_id.as_ref()))
+            .unwrap_or_default();
+        ContributionView {
+            deposit: contribution.deposit.into(),
+            tokens: contribution.tokens.into(),
+        }
+    }
+
+    /// Contributes attached NEAR to the hatch at the fixed price. Returns tokens bought.
+    /// The contribution reaching `min_raise` completes the hatch and opens the bonding curve.
+    /// Contributions count towards the purchase cap, as they become the cost basis of the claimed tokens.
+    #[payable]
+    pub fn hatch_contribute(&mut self) -> U128 {
+        self.assert_not_paused();
+        let deposit = env::attached_deposit();
+        let account_id = env::predecessor_account_id();
+        self.internal_assert_purchase_cap(&account_id, deposit);
+        let decimals = self.ft_metadata().decimals;
+        let supply = self.ft_total_supply().0;
+        let mut hatch = self.hatch.take().expect("ERR_NO_HATCH");
+        assert!(!hatch.hatched && !hatch.is_failed(), "ERR_HATCH_NOT_ACTIVE");
+        let tokens = (U256::from(deposit) * U256::from(10u128.pow(decimals as u32))
+            / U256::from(hatch.price))
+        .as_u128();
+        assert!(tokens > 0, "ERR_ZERO_AMOUNT");
+        if let Some(max_supply) = self.max_supply {
+            assert!(
+                supply + hatch.tokens + tokens <= max_supply,
+                "ERR_MAX_SUPPLY"
+            );
+        }
+        let mut contribution = hatch.contributions.get(&account_id).unwrap_or_default();
+        contribution.deposit += deposit;
+        contribution.tokens += tokens;
+        hatch.contributions.insert(&account_id, &contribution);
+        hatch.raised += deposit;
+        hatch.tokens += tokens;
+        if hatch.raised >= hatch.min_raise {
+            hatch.hatched = true;
+            // Tokens of the contributors are held by this contract until claimed.
+            let contract_id = env::current_account_id();
+            if !self.token.accounts.contains_key(&contract_id) {
+                self.token.internal_register_account(&contract_id);
+            }
+            self.token.internal_deposit(&contract_id, hatch.tokens);
+            self.reserve_balance += hatch.raised;
+        }
+        self.hatch = Some(hatch);
+        tokens.into()
+    }
+
+    /// Sends the caller's tokens bought in the hatch once it's completed.
+    pub fn claim_hatch_tokens(&mut self) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let hatch = self.hatch.as_mut().expect("ERR_NO_HATCH");
+        assert!(hatch.hatched, "ERR_NOT_HATCHED");
+        let contribution = hatch
+            .contributions
+            .remove(&account_id)
+            .expect("ERR_NO_CONTRIBUTION");
+        self.token.internal_transfer(
+            &env::current_account_id(),
+            &account_id,
+            contribution.tokens,
+            None,
+        );
+        let mut cost_basis = self.cost_basis.get(&account_id).unwrap_or_default();
+        cost_basis.deposited += contribution.deposit;
+        cost_basis.minted += contribution.tokens;
+        self.cost_basis.insert(&account_id, &cost_basis);
+        contribution.tokens.into()
+    }
+
+    /// Returns the caller's contribution if the hatch didn't reach `min_raise` by the deadline.
+    pub fn hatch_refund(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let hatch = self.hatch.as_mut().expect("ERR_NO_HATCH");
+        assert!(hatch.is_failed(), "ERR_HATCH_NOT_FAILED");
+        let contribution = hatch
+            .contributions
+            .remove(&account_id)
+            .expect("ERR_NO_CONTRIBUTION");
+        hatch.raised -= contribution.deposit;
+        hatch.tokens -= contribution.tokens;
+        Promise::new(account_id).transfer(contribution.deposit)
+    }
+}
@@ -1,33 +1,170 @@
+mod fees;
+#[cfg(test)]
+mod fuzz_tests;
+mod hatch;
 mod math;
+mod token_receiver;
+mod vesting;
 
-use near_contract_standards::fungible_token::{
-    FungibleToken, FungibleTokenCore, FungibleTokenMetadata, FungibleTokenMetadataProvider,
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider,
 };
-use near_contract_standards::storage_manager::{AccountStorageBalance, StorageManager};
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
+use near_contract_standards::fungible_token::FungibleToken;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LazyOption, LookupMap};
 use near_sdk::json_types::{ValidAccountId, U128};
-use near_sdk::{env, near_bindgen, Balance, PanicOnDefault, Promise};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, ext_contract, near_bindgen, AccountId, Balance, Gas, PanicOnDefault, Promise,
+    PromiseOrValue, PromiseResult,
+};
+
+use crate::fees::Fees;
+use crate::hatch::{Hatch, HatchConfig};
+use crate::math::U256;
+use crate::vesting::{Vesting, VestingSchedule};
+
+near_sdk::setup_alloc!();
+
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_SEND_RESERVE: Gas = 10_000_000_000_000;
+
+#[ext_contract(ext_reserve_token)]
+pub trait ReserveToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
+#[ext_contract(ext_self)]
+pub trait SelfCallbacks {
+    fn on_send_reserve(&mut self, account_id: AccountId, amount: U128);
+}
+
+/// How much NEAR the account has paid for the tokens it has minted and still holds.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct CostBasis {
+    deposited: Balance,
+    minted: Balance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CostBasisView {
+    /// NEAR deposited for minting.
+    pub deposited: U128,
+    /// Tokens minted for the deposit.
+    pub minted: U128,
+}
+
+/// Optional settings of the curve passed to `new`, each one is off when left out.
+#[derive(Deserialize, Default)]
+#[serde(crate = "near_sdk::serde", default)]
+pub struct CurveConfig {
+    /// Maximum NEAR single account can deposit for minting.
+    pub purchase_cap: Option<U128>,
+    /// Vesting of the initial amount.
+    pub vesting: Option<VestingSchedule>,
+    /// Entry and exit fees.
+    pub fees: Option<Fees>,
+    /// Maximum total supply.
+    pub max_supply: Option<U128>,
+    /// Hatch phase before the curve opens, not supported with `reserve_token`.
+    pub hatch: Option<HatchConfig>,
+    /// Fungible token used as the reserve instead of NEAR.
+    pub reserve_token: Option<ValidAccountId>,
+}
 
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 struct Contract {
+    /// Owner can pause minting and burning.
+    owner_id: AccountId,
+    paused: bool,
     token: FungibleToken,
     reserve_balance: Balance,
     reserve_ratio: u32,
+    /// Cost basis of the tokens minted per account.
+    cost_basis: LookupMap<AccountId, CostBasis>,
+    /// Maximum NEAR single account can deposit for minting, counting only tokens it still holds.
+    purchase_cap: Option<Balance>,
+    metadata: LazyOption<FungibleTokenMetadata>,
+    /// Vesting of the initial amount, if any.
+    vesting: Option<Vesting>,
+    /// Entry and exit fees, if any.
+    fees: Option<Fees>,
+    entry_fees_collected: Balance,
+    exit_fees_collected: Balance,
+    /// Maximum total supply, minting stops once it's reached.
+    max_supply: Option<Balance>,
+    /// Hatch phase before the curve opens, if any.
+    hatch: Option<Hatch>,
+    /// Fungible token used as the reserve instead of NEAR, if any. It's deposited with `ft_transfer_call`.
+    reserve_token: Option<AccountId>,
+    /// Whether the reserve backing the initial amount was deposited. The NEAR reserve is seeded by `new`.
+    reserve_seeded: bool,
+    /// Reserve tokens that failed to be sent, by receiver. Withdrawn with `withdraw_reserve_refund`.
+    reserve_refunds: LookupMap<AccountId, Balance>,
 }
 
 #[near_bindgen]
 impl Contract {
     #[init]
-    pub fn new(initial_amount: U128, reserve_ratio: u32) -> Self {
+    pub fn new(
+        owner_id: ValidAccountId,
+        initial_amount: U128,
+        reserve_ratio: u32,
+        metadata: FungibleTokenMetadata,
+        config: CurveConfig,
+    ) -> Self {
+        let CurveConfig {
+            purchase_cap,
+            vesting,
+            fees,
+            max_supply,
+            hatch,
+            reserve_token,
+        } = config;
         assert!(!env::state_exists(), "ERR_CONTRACT_IS_INITIALIZED");
-        // Attached deposit and account balance must be larger than storage usage, otherwise tx fails anyway.
-        let reserve_balance =
-            env::account_balance() - (env::storage_usage() as u128) * env::storage_byte_cost();
+        if let Some(schedule) = vesting.as_ref() {
+            schedule.assert_valid();
+        }
+        if let Some(fees) = fees.as_ref() {
+            fees.assert_valid();
+        }
+        if let Some(max_supply) = max_supply {
+            assert!(initial_amount.0 <= max_supply.0, "ERR_MAX_SUPPLY");
+        }
+        // Reserve token is seeded by the owner with a `"seed"` deposit, see `ft_on_transfer`.
+        let reserve_balance = if reserve_token.is_some() {
+            assert!(hatch.is_none(), "ERR_INVALID_HATCH");
+            0
+        } else {
+            // Attached deposit and account balance must be larger than storage usage, otherwise tx fails anyway.
+            env::account_balance() - (env::storage_usage() as u128) * env::storage_byte_cost()
+        };
         let mut this = Self {
-            token: FungibleToken::new(),
+            owner_id: owner_id.into(),
+            paused: false,
+            token: FungibleToken::new(b"a".to_vec()),
             reserve_balance,
             reserve_ratio,
+            cost_basis: LookupMap::new(b"cost".to_vec()),
+            purchase_cap: purchase_cap.map(|cap| cap.into()),
+            metadata: LazyOption::new(b"m".to_vec(), Some(&metadata)),
+            vesting: vesting.map(|schedule| Vesting {
+                account_id: env::predecessor_account_id(),
+                amount: initial_amount.into(),
+                schedule,
+            }),
+            fees,
+            entry_fees_collected: 0,
+            exit_fees_collected: 0,
+            max_supply: max_supply.map(|max_supply| max_supply.into()),
+            hatch: hatch.map(Hatch::new),
+            reserve_seeded: reserve_token.is_none(),
+            reserve_token: reserve_token.map(|token| token.into()),
+            reserve_refunds: LookupMap::new(b"r".to_vec()),
         };
         this.token
             .internal_register_account(&env::predecessor_account_id());
@@ -36,31 +173,324 @@ impl Contract {
         this
     }
 
+    /// Mints tokens for the attached NEAR, less the entry fee, to `account_id`.
+    /// If the max supply is reached, mints up to it and refunds the unused part of the deposit.
+    /// Fails if less than `min_tokens_out` would be minted (prevents front running and other slippage issues),
+    /// which refunds the attached deposit.
+    /// With a reserve token, tokens are minted via `ft_transfer_call` of the reserve token instead.
     #[payable]
-    pub fn mint(&mut self, account_id: ValidAccountId) -> U128 {
-        let deposit = env::attached_deposit();
-        let amount = math::calc_purchase_amount(
-            self.ft_total_supply().0,
-            self.reserve_balance,
-            self.reserve_ratio,
-            deposit,
+    pub fn mint(&mut self, account_id: ValidAccountId, min_tokens_out: U128) -> U128 {
+        assert!(self.reserve_token.is_none(), "ERR_RESERVE_IS_TOKEN");
+        let (amount, deposit) = self.internal_mint(
+            account_id.as_ref(),
+            env::attached_deposit(),
+            min_tokens_out.into(),
         );
-        self.reserve_balance += deposit;
-        self.token.internal_deposit(account_id.as_ref(), amount);
+        if env::attached_deposit() > deposit {
+            Promise::new(env::predecessor_account_id()).transfer(env::attached_deposit() - deposit);
+        }
         amount.into()
     }
 
-    pub fn burn(&mut self, amount: U128) -> Promise {
+    /// Burns `amount` of the caller's tokens and sends the NEAR returned by the curve, less the exit fee.
+    /// Fails if less than `min_near_out` would be sent.
+    pub fn burn(&mut self, amount: U128, min_near_out: U128) -> Promise {
+        self.assert_not_paused();
+        self.assert_hatched();
         let return_amount = math::calc_sale_amount(
             self.ft_total_supply().0,
             self.reserve_balance,
             self.reserve_ratio,
             amount.into(),
         );
+        let fee = self.internal_exit_fee(return_amount);
+        assert!(return_amount - fee >= min_near_out.0, "ERR_MIN_AMOUNT");
+        self.internal_assert_unlocked(&env::predecessor_account_id(), amount.into());
         self.reserve_balance -= return_amount;
+        self.internal_reduce_cost_basis(&env::predecessor_account_id(), amount.into());
         self.token
             .internal_withdraw(&env::predecessor_account_id(), amount.into());
-        Promise::new(env::predecessor_account_id()).transfer(return_amount)
+        self.exit_fees_collected += fee;
+        self.internal_send_fee(fee);
+        self.internal_send_reserve(env::predecessor_account_id(), return_amount - fee)
+    }
+
+    /// Callback after sending reserve tokens: if the transfer failed, e.g. the receiver is not registered
+    /// with the reserve token, the amount is credited to the receiver to withdraw later.
+    #[private]
+    pub fn on_send_reserve(&mut self, account_id: AccountId, amount: U128) {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return;
+        }
+        env::log(
+            format!(
+                "Failed to send {} of the reserve to {}",
+                amount.0, account_id
+            )
+            .as_bytes(),
+        );
+        let refund = self.reserve_refunds.get(&account_id).unwrap_or(0);
+        self.reserve_refunds
+            .insert(&account_id, &(refund + amount.0));
+    }
+
+    /// Sends the reserve tokens that failed to be sent to the caller before.
+    pub fn withdraw_reserve_refund(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let amount = self
+            .reserve_refunds
+            .remove(&account_id)
+            .expect("ERR_NO_REFUND");
+        self.internal_send_reserve(account_id, amount)
+    }
+
+    pub fn get_reserve_refund(&self, account_id: ValidAccountId) -> U128 {
+        self.reserve_refunds
+            .get(account_id.as_ref())
+            .unwrap_or(0)
+            .into()
+    }
+
+    /// Stops minting and burning, e.g. in case of a bug in the curve. Transfers are not affected.
+    pub fn pause(&mut self) {
+        self.assert_owner();
+        assert!(!self.paused, "ERR_IS_PAUSED");
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.assert_owner();
+        assert!(self.paused, "ERR_NOT_PAUSED");
+        self.paused = false;
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Returns how many tokens would be minted for `deposit` of NEAR now, after the entry fee and capped by the max supply.
+    pub fn get_buy_quote(&self, deposit: U128) -> U128 {
+        self.internal_purchase(deposit.into()).0.into()
+    }
+
+    /// Returns how much NEAR would be sent for burning `amount` of tokens now, after the exit fee.
+    pub fn get_sell_quote(&self, amount: U128) -> U128 {
+        let return_amount = math::calc_sale_amount(
+            self.ft_total_supply().0,
+            self.reserve_balance,
+            self.reserve_ratio,
+            amount.into(),
+        );
+        (return_amount - self.internal_exit_fee(return_amount)).into()
+    }
+
+    /// Returns current price in yoctoNEAR of one whole token (`10^decimals` of the smallest units).
+    pub fn get_spot_price(&self) -> U128 {
+        math::calc_spot_price(
+            self.ft_total_supply().0,
+            self.reserve_balance,
+            self.reserve_ratio,
+            10u128.pow(self.ft_metadata().decimals as u32),
+        )
+        .into()
+    }
+
+    pub fn get_max_supply(&self) -> Option<U128> {
+        self.max_supply.map(|max_supply| max_supply.into())
+    }
+
+    pub fn get_reserve_balance(&self) -> U128 {
+        self.reserve_balance.into()
+    }
+
+    /// Returns reserve ratio in millionths.
+    pub fn get_reserve_ratio(&self) -> u32 {
+        self.reserve_ratio
+    }
+
+    pub fn get_vesting_schedule(&self) -> Option<VestingSchedule> {
+        self.vesting
+            .as_ref()
+            .map(|vesting| vesting.schedule.clone())
+    }
+
+    /// Returns how many tokens of given account are still locked by vesting.
+    pub fn get_locked_balance(&self, account_id: ValidAccountId) -> U128 {
+        self.internal_locked_balance(account_id.as_ref()).into()
+    }
+
+    /// Returns cost basis of the tokens minted by given account that it still holds.
+    pub fn get_cost_basis(&self, account_id: ValidAccountId) -> CostBasisView {
+        let cost_basis = self.cost_basis.get(account_id.as_ref()).unwrap_or_default();
+        CostBasisView {
+            deposited: cost_basis.deposited.into(),
+            minted: cost_basis.minted.into(),
+        }
+    }
+}
+
+impl Contract {
+    fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "ERR_NOT_OWNER"
+        );
+    }
+
+    fn assert_not_paused(&self) {
+        assert!(!self.paused, "ERR_IS_PAUSED");
+    }
+
+    /// Asserts that the bonding curve is open: there is no hatch or it's completed.
+    fn assert_hatched(&self) {
+        assert!(
+            self.hatch
+                .as_ref()
+                .map(|hatch| hatch.is_hatched())
+                .unwrap_or(true),
+            "ERR_NOT_HATCHED"
+        );
+    }
+
+    /// Mints tokens for `deposit` of the reserve to `account_id`.
+    /// Returns tokens minted and the part of the deposit used, the rest must be refunded.
+    pub(crate) fn internal_mint(
+        &mut self,
+        account_id: &AccountId,
+        deposit: Balance,
+        min_tokens_out: Balance,
+    ) -> (Balance, Balance) {
+        self.assert_not_paused();
+        self.assert_hatched();
+        let (amount, deposit, fee) = self.internal_purchase(deposit);
+        assert!(amount >= min_tokens_out, "ERR_MIN_AMOUNT");
+        self.internal_assert_purchase_cap(account_id, deposit);
+        let mut cost_basis = self.cost_basis.get(account_id).unwrap_or_default();
+        cost_basis.deposited += deposit;
+        cost_basis.minted += amount;
+        self.cost_basis.insert(account_id, &cost_basis);
+        self.reserve_balance += deposit - fee;
+        self.token.internal_deposit(account_id, amount);
+        self.entry_fees_collected += fee;
+        self.internal_send_fee(fee);
+        (amount, deposit)
+    }
+
+    /// Asserts that `deposit` keeps the account within the purchase cap, counting the cost basis of the tokens
+    /// it holds and its unclaimed hatch contribution.
+    pub(crate) fn internal_assert_purchase_cap(&self, account_id: &AccountId, deposit: Balance) {
+        if let Some(purchase_cap) = self.purchase_cap {
+            let deposited = self
+                .cost_basis
+                .get(account_id)
+                .map(|cost_basis| cost_basis.deposited)
+                .unwrap_or(0);
+            let contributed = self
+                .hatch
+                .as_ref()
+                .map(|hatch| hatch.contributed(account_id))
+                .unwrap_or(0);
+            assert!(
+                deposited + contributed + deposit <= purchase_cap,
+                "ERR_PURCHASE_CAP"
+            );
+        }
+    }
+
+    /// Sends `amount` of the reserve, NEAR or the reserve token, to `account_id`.
+    /// Reserve tokens that fail to be sent are credited to `account_id`, see `on_send_reserve`.
+    pub(crate) fn internal_send_reserve(&self, account_id: AccountId, amount: Balance) -> Promise {
+        match self.reserve_token.as_ref() {
+            Some(reserve_token) => ext_reserve_token::ft_transfer(
+                account_id.clone(),
+                amount.into(),
+                None,
+                reserve_token,
+                1,
+                GAS_FOR_FT_TRANSFER,
+            )
+            .then(ext_self::on_send_reserve(
+                account_id,
+                amount.into(),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_ON_SEND_RESERVE,
+            )),
+            None => Promise::new(account_id).transfer(amount),
+        }
+    }
+
+    /// Returns tokens minted for `deposit`, the part of the deposit used and the entry fee taken from it.
+    /// Only part of the deposit is used if the purchase is capped by the max supply.
+    fn internal_purchase(&self, deposit: Balance) -> (Balance, Balance, Balance) {
+        let supply = self.ft_total_supply().0;
+        let fee = self.internal_entry_fee(deposit);
+        let amount = math::calc_purchase_amount(
+            supply,
+            self.reserve_balance,
+            self.reserve_ratio,
+            deposit - fee,
+        );
+        match self.max_supply {
+            Some(max_supply) if supply + amount > max_supply => {
+                assert!(supply < max_supply, "ERR_MAX_SUPPLY");
+                let amount = max_supply - supply;
+                let cost = math::calc_purchase_cost(
+                    supply,
+                    self.reserve_balance,
+                    self.reserve_ratio,
+                    amount,
+                );
+                let used = std::cmp::min(self.internal_with_entry_fee(cost), deposit);
+                (amount, used, used - cost)
+            }
+            _ => (amount, deposit, fee),
+        }
+    }
+
+    fn internal_locked_balance(&self, account_id: &AccountId) -> Balance {
+        match self.vesting.as_ref() {
+            Some(vesting) if &vesting.account_id == account_id => vesting
+                .schedule
+                .locked_amount(vesting.amount, env::block_timestamp()),
+            _ => 0,
+        }
+    }
+
+    /// Asserts that the account keeps its locked tokens after sending or burning `amount`.
+    fn internal_assert_unlocked(&self, account_id: &AccountId, amount: Balance) {
+        let locked = self.internal_locked_balance(account_id);
+        if locked > 0 {
+            let balance = self.token.internal_unwrap_balance_of(account_id);
+            assert!(balance >= amount + locked, "ERR_TOKENS_LOCKED");
+        }
+    }
+
+    /// Removes burnt tokens from the cost basis proportionally.
+    /// Tokens received via transfers have no cost basis and are burnt first.
+    fn internal_reduce_cost_basis(&mut self, account_id: &AccountId, amount: Balance) {
+        let mut cost_basis = match self.cost_basis.get(account_id) {
+            Some(cost_basis) => cost_basis,
+            None => return,
+        };
+        let balance = self.token.internal_unwrap_balance_of(account_id);
+        let not_minted = balance.saturating_sub(cost_basis.minted);
+        let amount = amount.saturating_sub(not_minted);
+        if amount >= cost_basis.minted {
+            self.cost_basis.remove(account_id);
+            return;
+        }
+        let deposited = (U256::from(cost_basis.deposited) * U256::from(amount)
+            / U256::from(cost_basis.minted))
+        .as_u128();
+        cost_basis.deposited -= deposited;
+        cost_basis.minted -= amount;
+        self.cost_basis.insert(account_id, &cost_basis);
     }
 }
 
@@ -68,6 +498,7 @@ impl Contract {
 impl FungibleTokenCore for Contract {
     #[payable]
     fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        self.internal_assert_unlocked(&env::predecessor_account_id(), amount.into());
         self.token.ft_transfer(receiver_id, amount, memo)
     }
 
@@ -76,10 +507,11 @@ impl FungibleTokenCore for Contract {
         &mut self,
         receiver_id: ValidAccountId,
         amount: U128,
-        msg: String,
         memo: Option<String>,
-    ) -> Promise {
-        self.token.ft_transfer_call(receiver_id, amount, msg, memo)
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.internal_assert_unlocked(&env::predecessor_account_id(), amount.into());
+        self.token.ft_transfer_call(receiver_id, amount, memo, msg)
     }
 
     fn ft_total_supply(&self) -> U128 {
@@ -92,35 +524,32 @@ impl FungibleTokenCore for Contract {
 }
 
 #[near_bindgen]
-impl StorageManager for Contract {
-    #[payable]
-    fn storage_deposit(&mut self, account_id: Option<ValidAccountId>) -> AccountStorageBalance {
-        self.token.storage_deposit(account_id)
-    }
-
-    #[payable]
-    fn storage_withdraw(&mut self, amount: U128) -> AccountStorageBalance {
-        self.token.storage_withdraw(amount)
-    }
-
-    fn storage_minimum_balance(&self) -> U128 {
-        self.token.storage_minimum_balance()
-    }
-
-    fn storage_balance_of(&self, account_id: ValidAccountId) -> AccountStorageBalance {
-        self.token.storage_balance_of(account_id)
+impl FungibleTokenResolver for Contract {
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        receiver_id: ValidAccountId,
+        amount: U128,
+    ) -> U128 {
+        self.token
+            .ft_resolve_transfer(sender_id, receiver_id, amount)
     }
 }
 
+near_contract_standards::impl_fungible_token_storage!(Contract, token);
+
 #[near_bindgen]
 impl FungibleTokenMetadataProvider for Contract {
-    fn ft_metadata() -> FungibleTokenMetadata {
-        unimplemented!()
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        self.metadata.get().unwrap()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_lib::context::testing_env_with_promise_results;
     use near_sdk::test_utils::{accounts, VMContextBuilder};
     use near_sdk::{env, testing_env, MockedBlockchain};
 
@@ -128,30 +557,483 @@ mod tests {
 
     const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
 
-    #[test]
-    fn test_basics() {
-        let mut context = VMContextBuilder::new();
+    fn metadata() -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: "ft-1.0.0".to_string(),
+            name: "Continuous Token".to_string(),
+            symbol: "CT".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
+    }
+
+    /// Creates the curve owned by `accounts(3)` with 1 token backed by 1 NEAR at reserve ratio 1/2,
+    /// and registers `accounts(0)`. Reserve token, if any, is seeded with 1 token by the owner.
+    fn setup(context: &mut VMContextBuilder, config: CurveConfig) -> Contract {
+        let reserve_token = config.reserve_token.clone();
         testing_env!(context
+            .current_account_id(accounts(5))
             .predecessor_account_id(accounts(3))
             .account_balance(1000 * env::storage_byte_cost())
             .storage_usage(1000)
-            .attached_deposit(ONE_NEAR)
+            .attached_deposit(if reserve_token.is_some() { 0 } else { ONE_NEAR })
             .build());
-        // Reserve 1/2, initial amount = 1e24 with 1e24N in reserve.
-        let mut contract = Contract::new(ONE_NEAR.into(), 500_000);
+        let mut contract = Contract::new(accounts(3), ONE_NEAR.into(), 500_000, metadata(), config);
         testing_env!(context
             .attached_deposit(125 * env::storage_byte_cost())
             .build());
-        contract.storage_deposit(Some(accounts(0)));
+        contract.storage_deposit(Some(accounts(0)), None);
+        if let Some(reserve_token) = reserve_token {
+            testing_env!(context
+                .predecessor_account_id(reserve_token)
+                .attached_deposit(0)
+                .build());
+            contract.ft_on_transfer(accounts(3), ONE_NEAR.into(), "seed".to_string());
+        }
+        contract
+    }
+
+    #[test]
+    fn test_basics() {
+        let mut context = VMContextBuilder::new();
+        // Reserve 1/2, initial amount = 1e24 with 1e24N in reserve.
+        let mut contract = setup(&mut context, CurveConfig::default());
+        assert_eq!(contract.ft_metadata().symbol, "CT");
         testing_env!(context.attached_deposit(ONE_NEAR).build());
-        let minted_amount = contract.mint(accounts(0));
+        let minted_amount = contract.mint(accounts(0), 0.into());
         assert_eq!(
             contract.ft_balance_of(accounts(0)),
-            414213562373095139835904.into()
+            414213562373095048801688.into()
+        );
+        assert_eq!(
+            contract.get_cost_basis(accounts(0)).deposited,
+            ONE_NEAR.into()
         );
         let rb = contract.reserve_balance;
-        contract.burn(minted_amount);
+        contract.burn(minted_amount, (ONE_NEAR - 10u128.pow(10)).into());
         // After burning, the balance subtracted is around what was deposited.
         assert!(rb - contract.reserve_balance < ONE_NEAR + 10u128.pow(10));
+        assert_eq!(contract.get_cost_basis(accounts(0)).minted, 0.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PURCHASE_CAP")]
+    fn test_purchase_cap() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                purchase_cap: Some((2 * ONE_NEAR).into()),
+                ..Default::default()
+            },
+        );
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.mint(accounts(0), 0.into());
+        testing_env!(context.attached_deposit(ONE_NEAR + 1).build());
+        contract.mint(accounts(0), 0.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MIN_AMOUNT")]
+    fn test_mint_slippage() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, CurveConfig::default());
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.mint(accounts(0), (ONE_NEAR / 2).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MIN_AMOUNT")]
+    fn test_burn_slippage() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, CurveConfig::default());
+        testing_env!(context.attached_deposit(0).build());
+        // Burning half of the supply returns 3/4 of the reserve.
+        contract.burn((ONE_NEAR / 2).into(), ONE_NEAR.into());
+    }
+
+    #[test]
+    fn test_views() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, CurveConfig::default());
+        assert_eq!(contract.get_reserve_balance(), ONE_NEAR.into());
+        assert_eq!(contract.get_reserve_ratio(), 500_000);
+        // Price is reserve / (supply * ratio).
+        assert_eq!(contract.get_spot_price(), (2 * ONE_NEAR).into());
+        let quote = contract.get_buy_quote(ONE_NEAR.into());
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        assert_eq!(contract.mint(accounts(0), 0.into()), quote);
+        assert_eq!(contract.get_reserve_balance(), (2 * ONE_NEAR).into());
+        assert_eq!(
+            contract.get_sell_quote(contract.ft_total_supply()),
+            contract.get_reserve_balance()
+        );
+    }
+
+    #[test]
+    fn test_vesting() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                vesting: Some(VestingSchedule {
+                    start_timestamp: 0.into(),
+                    cliff_timestamp: 100.into(),
+                    end_timestamp: 200.into(),
+                }),
+                ..Default::default()
+            },
+        );
+        testing_env!(context.block_timestamp(50).build());
+        assert_eq!(contract.get_locked_balance(accounts(3)), ONE_NEAR.into());
+        testing_env!(context.block_timestamp(150).attached_deposit(1).build());
+        assert_eq!(
+            contract.get_locked_balance(accounts(3)),
+            (ONE_NEAR / 4).into()
+        );
+        contract.ft_transfer(accounts(0), (3 * ONE_NEAR / 4).into(), None);
+        assert_eq!(contract.get_locked_balance(accounts(0)), 0.into());
+        testing_env!(context.block_timestamp(200).build());
+        assert_eq!(contract.get_locked_balance(accounts(3)), 0.into());
+        contract.ft_transfer(accounts(0), (ONE_NEAR / 4).into(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOKENS_LOCKED")]
+    fn test_vesting_transfer_locked() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                vesting: Some(VestingSchedule {
+                    start_timestamp: 0.into(),
+                    cliff_timestamp: 100.into(),
+                    end_timestamp: 200.into(),
+                }),
+                ..Default::default()
+            },
+        );
+        testing_env!(context.block_timestamp(150).attached_deposit(1).build());
+        contract.ft_transfer(accounts(0), (3 * ONE_NEAR / 4 + 1).into(), None);
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                fees: Some(Fees {
+                    beneficiary: accounts(4).into(),
+                    entry_fee_bps: 100,
+                    exit_fee_bps: 200,
+                }),
+                ..Default::default()
+            },
+        );
+        let quote = contract.get_buy_quote(ONE_NEAR.into());
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        let minted_amount = contract.mint(accounts(0), quote);
+        assert_eq!(minted_amount, quote);
+        assert_eq!(
+            contract.get_reserve_balance(),
+            (ONE_NEAR + ONE_NEAR * 99 / 100).into()
+        );
+        assert_eq!(
+            contract.get_collected_fees().entry_fees,
+            (ONE_NEAR / 100).into()
+        );
+        let rb = contract.reserve_balance;
+        let sell_quote = contract.get_sell_quote(minted_amount);
+        testing_env!(context.attached_deposit(0).build());
+        contract.burn(minted_amount, sell_quote);
+        let return_amount = rb - contract.reserve_balance;
+        assert_eq!(
+            contract.get_collected_fees().exit_fees,
+            (return_amount * 2 / 100).into()
+        );
+        assert_eq!(sell_quote.0, return_amount - return_amount * 2 / 100);
+    }
+
+    #[test]
+    fn test_max_supply() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                max_supply: Some((ONE_NEAR + ONE_NEAR / 5).into()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            contract.get_buy_quote(ONE_NEAR.into()),
+            (ONE_NEAR / 5).into()
+        );
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        assert_eq!(contract.mint(accounts(0), 0.into()), (ONE_NEAR / 5).into());
+        assert_eq!(
+            contract.ft_total_supply(),
+            contract.get_max_supply().unwrap()
+        );
+        // Minting 1/5 of the supply costs 0.44 of the reserve, the rest is refunded.
+        let cost = contract.reserve_balance - ONE_NEAR;
+        assert!(cost >= ONE_NEAR * 44 / 100 && cost < ONE_NEAR * 44 / 100 + 10u128.pow(10));
+        assert_eq!(contract.get_cost_basis(accounts(0)).deposited, cost.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MAX_SUPPLY")]
+    fn test_max_supply_reached() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                max_supply: Some((ONE_NEAR + ONE_NEAR / 5).into()),
+                ..Default::default()
+            },
+        );
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.mint(accounts(0), 0.into());
+        contract.mint(accounts(0), 0.into());
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, CurveConfig::default());
+        testing_env!(context.attached_deposit(0).build());
+        contract.pause();
+        assert!(contract.is_paused());
+        // Transfers still work while paused.
+        testing_env!(context.attached_deposit(1).build());
+        contract.ft_transfer(accounts(0), (ONE_NEAR / 2).into(), None);
+        testing_env!(context.attached_deposit(0).build());
+        contract.resume();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.mint(accounts(0), 0.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_IS_PAUSED")]
+    fn test_mint_paused() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, CurveConfig::default());
+        testing_env!(context.attached_deposit(0).build());
+        contract.pause();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.mint(accounts(3), 0.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_OWNER")]
+    fn test_pause_not_owner() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(&mut context, CurveConfig::default());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.pause();
+    }
+
+    #[test]
+    fn test_hatch() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                hatch: Some(HatchConfig {
+                    price: ONE_NEAR.into(),
+                    min_raise: (2 * ONE_NEAR).into(),
+                    deadline: 100.into(),
+                }),
+                ..Default::default()
+            },
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        assert_eq!(contract.hatch_contribute(), ONE_NEAR.into());
+        assert!(!contract.get_hatch().unwrap().hatched);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.hatch_contribute();
+        assert!(contract.get_hatch().unwrap().hatched);
+        assert_eq!(contract.get_reserve_balance(), (3 * ONE_NEAR).into());
+        assert_eq!(contract.ft_total_supply(), (3 * ONE_NEAR).into());
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(0)
+            .build());
+        assert_eq!(contract.claim_hatch_tokens(), ONE_NEAR.into());
+        assert_eq!(contract.ft_balance_of(accounts(0)), ONE_NEAR.into());
+        assert_eq!(
+            contract.get_cost_basis(accounts(0)).deposited,
+            ONE_NEAR.into()
+        );
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.mint(accounts(0), 0.into());
+    }
+
+    #[test]
+    fn test_hatch_refund() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                hatch: Some(HatchConfig {
+                    price: ONE_NEAR.into(),
+                    min_raise: (2 * ONE_NEAR).into(),
+                    deadline: 100.into(),
+                }),
+                ..Default::default()
+            },
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        contract.hatch_contribute();
+        testing_env!(context.block_timestamp(100).attached_deposit(0).build());
+        assert!(contract.get_hatch().unwrap().failed);
+        contract.hatch_refund();
+        assert_eq!(
+            contract.get_hatch_contribution(accounts(0)).deposit,
+            0.into()
+        );
+        assert_eq!(contract.get_hatch().unwrap().raised, 0.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PURCHASE_CAP")]
+    fn test_hatch_purchase_cap() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                purchase_cap: Some((ONE_NEAR + ONE_NEAR / 2).into()),
+                hatch: Some(HatchConfig {
+                    price: ONE_NEAR.into(),
+                    min_raise: (3 * ONE_NEAR).into(),
+                    deadline: 100.into(),
+                }),
+                ..Default::default()
+            },
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        contract.hatch_contribute();
+        contract.hatch_contribute();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_HATCHED")]
+    fn test_mint_before_hatch() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                hatch: Some(HatchConfig {
+                    price: ONE_NEAR.into(),
+                    min_raise: (2 * ONE_NEAR).into(),
+                    deadline: 100.into(),
+                }),
+                ..Default::default()
+            },
+        );
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.mint(accounts(3), 0.into());
+    }
+
+    #[test]
+    fn test_mint_for() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                reserve_token: Some(accounts(4)),
+                ..Default::default()
+            },
+        );
+        assert_eq!(contract.get_reserve_balance(), ONE_NEAR.into());
+        let result = contract.ft_on_transfer(
+            accounts(1),
+            ONE_NEAR.into(),
+            format!("{{\"mint_for\": \"{}\"}}", accounts(0)),
+        );
+        assert!(matches!(result, PromiseOrValue::Value(U128(0))));
+        assert_eq!(
+            contract.ft_balance_of(accounts(0)),
+            414213562373095048801688.into()
+        );
+        assert_eq!(contract.get_reserve_balance(), (2 * ONE_NEAR).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_RESERVE_SEEDED")]
+    fn test_seed_twice() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                reserve_token: Some(accounts(4)),
+                ..Default::default()
+            },
+        );
+        contract.ft_on_transfer(accounts(3), ONE_NEAR.into(), "seed".to_string());
+    }
+
+    #[test]
+    fn test_burn_reserve_token_failed() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                reserve_token: Some(accounts(4)),
+                ..Default::default()
+            },
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(0)
+            .build());
+        contract.burn((ONE_NEAR / 2).into(), 0.into());
+        let reserve_balance = contract.get_reserve_balance();
+        testing_env_with_promise_results(
+            context.predecessor_account_id(accounts(5)).build(),
+            PromiseResult::Failed,
+        );
+        contract.on_send_reserve(accounts(3).into(), (ONE_NEAR * 3 / 4).into());
+        assert_eq!(
+            contract.get_reserve_refund(accounts(3)),
+            (ONE_NEAR * 3 / 4).into()
+        );
+        assert_eq!(contract.get_reserve_balance(), reserve_balance);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.withdraw_reserve_refund();
+        assert_eq!(contract.get_reserve_refund(accounts(3)), 0.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WRONG_TOKEN")]
+    fn test_mint_for_wrong_token() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = setup(
+            &mut context,
+            CurveConfig {
+                reserve_token: Some(accounts(4)),
+                ..Default::default()
+            },
+        );
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(
+            accounts(1),
+            ONE_NEAR.into(),
+            format!("{{\"mint_for\": \"{}\"}}", accounts(0)),
+        );
     }
 }
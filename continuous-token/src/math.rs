@@ -1,10 +1,88 @@
+pub use near_lib::math::U256;
+use near_lib::math::{mul_div, mul_div_ceil};
 use near_sdk::Balance;
 
 const MAX_RESERVE_RATIO: u32 = 1_000_000;
 
+/// Number of fractional bits of the fixed-point numbers used for the curve.
+const PRECISION: usize = 96;
+
+/// Power rounded down is bumped by `power >> POWER_MARGIN` to round up, which is well above its error.
+const POWER_MARGIN: usize = 80;
+
+/// ln(2) in fixed-point, rounded down.
+const LN2: U256 = U256([0xd1cf_79ab_c9e3_b398, 0xb172_17f7, 0, 0]);
+
+fn fixed_one() -> U256 {
+    U256::one() << PRECISION
+}
+
+/// Returns `a / b` rounded up.
+fn div_ceil(a: U256, b: U256) -> U256 {
+    let (quotient, remainder) = a.div_mod(b);
+    if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + U256::one()
+    }
+}
+
+/// Returns log2 of fixed-point `x >= 1`, rounded down.
+fn log2(x: U256) -> U256 {
+    let one = fixed_one();
+    let int_part = x.bits() - 1 - PRECISION;
+    let mut y = x >> int_part;
+    let mut result = U256::from(int_part) << PRECISION;
+    // Squaring `y` in [1, 2) yields the next binary digit of the fraction.
+    for i in 1..=PRECISION {
+        y = (y * y) >> PRECISION;
+        if y >= one << 1 {
+            y >>= 1;
+            result += one >> i;
+        }
+    }
+    result
+}
+
+/// Returns natural logarithm of fixed-point `x >= 1`, rounded down.
+fn ln(x: U256) -> U256 {
+    (log2(x) * LN2) >> PRECISION
+}
+
+/// Returns e^y of fixed-point `y`, rounded down, or `None` if the result doesn't fit.
+fn exp(y: U256) -> Option<U256> {
+    let one = fixed_one();
+    // e^y = 2^k * e^r, where r = y - k * ln(2).
+    // ln(2) is rounded up here, so that r is not overestimated.
+    let ln2 = LN2 + U256::one();
+    let k = y / ln2;
+    let r = y - k * ln2;
+    if k >= U256::from(256 - PRECISION - 2) {
+        return None;
+    }
+    // Taylor series of e^r converges fast for r < ln(2).
+    let mut term = one;
+    let mut result = one;
+    let mut n = 1u32;
+    while !term.is_zero() {
+        term = ((term * r) >> PRECISION) / U256::from(n);
+        result += term;
+        n += 1;
+    }
+    Some(result << k.as_usize())
+}
+
+/// Returns `(base_n / base_d) ^ (exp_n / exp_d)` in fixed-point, rounded down, or `None` if the result doesn't fit.
+/// Requires `base_n >= base_d`.
+fn pow(base_n: U256, base_d: U256, exp_n: u32, exp_d: u32) -> Option<U256> {
+    let base = (base_n << PRECISION) / base_d;
+    exp(ln(base) * U256::from(exp_n) / U256::from(exp_d))
+}
+
 /// Given continues token supply, reserve balance and reserve ratio, return how much tokens will be purchased with given `deposit_amount`.
 /// Formula:
 ///     return = supply * ((1 + deposit_amount / reserve_balance) ^ (reserve_ratio / MAX_RESERVE_RATIO) - 1)
+/// Rounded down, in favor of the reserve.
 pub(crate) fn calc_purchase_amount(
     supply: Balance,
     reserve_balance: Balance,
@@ -16,19 +94,54 @@ pub(crate) fn calc_purchase_amount(
         "ERR_INPUT_ZERO"
     );
     if reserve_ratio == MAX_RESERVE_RATIO {
-        return supply * deposit_amount / reserve_balance;
+        return mul_div(supply, deposit_amount, reserve_balance);
     }
+    let power = pow(
+        U256::from(reserve_balance) + U256::from(deposit_amount),
+        U256::from(reserve_balance),
+        reserve_ratio,
+        MAX_RESERVE_RATIO,
+    )
+    .expect("ERR_MATH_OVERFLOW");
+    ((U256::from(supply) * (power - fixed_one())) >> PRECISION).as_u128()
+}
 
-    (supply as f64
-        * ((1f64 + deposit_amount as f64 / reserve_balance as f64)
-            .powf(reserve_ratio as f64 / MAX_RESERVE_RATIO as f64)
-            - 1f64))
-        .ceil() as u128
+/// Given continues token supply, reserve balance and reserve ratio, return how much reserve must be deposited to purchase `amount` tokens.
+/// Formula:
+///     return = reserve_balance * ((1 + amount / supply) ^ (MAX_RESERVE_RATIO / reserve_ratio) - 1)
+/// Rounded up, in favor of the reserve.
+pub(crate) fn calc_purchase_cost(
+    supply: Balance,
+    reserve_balance: Balance,
+    reserve_ratio: u32,
+    amount: Balance,
+) -> Balance {
+    assert!(
+        supply > 0 && reserve_balance > 0 && reserve_ratio > 0 && amount > 0,
+        "ERR_INPUT_ZERO"
+    );
+    if reserve_ratio == MAX_RESERVE_RATIO {
+        return mul_div_ceil(reserve_balance, amount, supply);
+    }
+    let power = pow(
+        U256::from(supply) + U256::from(amount),
+        U256::from(supply),
+        MAX_RESERVE_RATIO,
+        reserve_ratio,
+    )
+    .expect("ERR_MATH_OVERFLOW");
+    let power = power + (power >> POWER_MARGIN);
+    div_ceil(
+        U256::from(reserve_balance) * (power - fixed_one()),
+        fixed_one(),
+    )
+    .as_u128()
 }
 
 /// Given total supply, reserve balance and reserve ratio, calculate how much reserve to return for given number of tokens to sell.
 /// Formula:
 ///     return = reserve_balance * (1 - (1 - sell_amount / supply) ^ (1 / (reserve_ration / MAX_RESERVE_RATIO)))
+/// Rounded down, in favor of the reserve.
 pub(crate) fn calc_sale_amount(
     supply: Balance,
     reserve_balance: Balance,
@@ -42,11 +155,35 @@ pub(crate) fn calc_sale_amount(
     if sell_amount == supply {
         return reserve_balance;
     } else if reserve_ratio == MAX_RESERVE_RATIO {
-        return reserve_balance * sell_amount / supply;
+        return mul_div(reserve_balance, sell_amount, supply);
+    }
+    // Computed as `reserve_balance * (1 - 1 / power)`, where `power = (supply / (supply - sell_amount)) ^ (1 / ratio)`.
+    match pow(
+        U256::from(supply),
+        U256::from(supply - sell_amount),
+        MAX_RESERVE_RATIO,
+        reserve_ratio,
+    ) {
+        Some(power) => {
+            reserve_balance - div_ceil(U256::from(reserve_balance) << PRECISION, power).as_u128()
+        }
+        // Less than one unit of the reserve is kept.
+        None => reserve_balance - 1,
     }
-    (reserve_balance as f64
-        * (1f64
-            - (1f64 - sell_amount as f64 / supply as f64)
-                .powf(MAX_RESERVE_RATIO as f64 / reserve_ratio as f64)))
-    .floor() as u128
+}
+
+/// Given total supply, reserve balance and reserve ratio, return the current price of `amount` tokens in reserve.
+/// Formula:
+///     return = amount * reserve_balance / (supply * reserve_ratio / MAX_RESERVE_RATIO)
+/// Rounded down.
+pub(crate) fn calc_spot_price(
+    supply: Balance,
+    reserve_balance: Balance,
+    reserve_ratio: u32,
+    amount: Balance,
+) -> Balance {
+    assert!(supply > 0 && reserve_ratio > 0, "ERR_INPUT_ZERO");
+    (U256::from(amount) * U256::from(reserve_balance) * U256::from(MAX_RESERVE_RATIO)
+        / (U256::from(supply) * U256::from(reserve_ratio)))
+    .as_u128()
 }
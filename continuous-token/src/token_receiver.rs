@@ -0,0 +1,63 @@
+//! Minting with the reserve token: `ft_transfer_call` of the reserve token to this contract mints for the sender,
+//! or for the account given in the message, so integrators can buy for their users in one transfer.
+
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::serde::Deserialize;
+use near_sdk::serde_json;
+
+use crate::*;
+
+/// Message of the owner's deposit that seeds the reserve.
+const SEED_MSG: &str = "seed";
+
+#[derive(Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+struct MintMessage {
+    /// Account to mint for instead of the sender.
+    mint_for: Option<ValidAccountId>,
+    min_tokens_out: Option<U128>,
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// Mints for the received reserve tokens.
+    /// Message is either empty or `{"mint_for": "alice.near", "min_tokens_out": "100"}`, with both fields optional.
+    /// If the max supply is reached, unused reserve tokens are returned to the sender.
+    /// Before any minting, the owner deposits the reserve backing the initial amount with the message `"seed"`.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert_eq!(
+            Some(env::predecessor_account_id()),
+            self.reserve_token,
+            "ERR_WRONG_TOKEN"
+        );
+        if msg == SEED_MSG {
+            assert_eq!(sender_id.as_ref(), &self.owner_id, "ERR_NOT_OWNER");
+            assert!(!self.reserve_seeded, "ERR_RESERVE_SEEDED");
+            assert!(amount.0 > 0, "ERR_ZERO_AMOUNT");
+            self.reserve_seeded = true;
+            self.reserve_balance = amount.into();
+            return PromiseOrValue::Value(U128(0));
+        }
+        assert!(self.reserve_seeded, "ERR_RESERVE_NOT_SEEDED");
+        let message: MintMessage = if msg.is_empty() {
+            MintMessage::default()
+        } else {
+            serde_json::from_str(&msg).expect("ERR_MSG_INCORRECT")
+        };
+        let account_id: AccountId = message
+            .mint_for
+            .map(|account_id| account_id.into())
+            .unwrap_or_else(|| sender_id.into());
+        let (_, deposit) = self.internal_mint(
+            &account_id,
+            amount.into(),
+            message.min_tokens_out.map(|min| min.0).unwrap_or(0),
+        );
+        PromiseOrValue::Value(U128(amount.0 - deposit))
+    }
+}
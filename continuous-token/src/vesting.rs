@@ -0,0 +1,53 @@
+//! Vesting of the `initial_amount` minted to the deployer: nothing is released before the cliff,
+//! then tokens are released linearly until the end of the schedule.
+//! Unvested tokens can't be transferred or burnt.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{AccountId, Balance, Timestamp};
+
+use crate::math::U256;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingSchedule {
+    /// When linear release starts.
+    pub start_timestamp: U64,
+    /// Nothing is released before the cliff, at the cliff everything accrued since the start is released.
+    pub cliff_timestamp: U64,
+    /// When everything is released.
+    pub end_timestamp: U64,
+}
+
+impl VestingSchedule {
+    pub fn assert_valid(&self) {
+        assert!(
+            self.start_timestamp.0 <= self.cliff_timestamp.0
+                && self.cliff_timestamp.0 <= self.end_timestamp.0
+                && self.start_timestamp.0 < self.end_timestamp.0,
+            "ERR_INVALID_VESTING"
+        );
+    }
+
+    /// Returns how much of `total_amount` is still locked at `timestamp`.
+    pub fn locked_amount(&self, total_amount: Balance, timestamp: Timestamp) -> Balance {
+        if timestamp < self.cliff_timestamp.0 {
+            total_amount
+        } else if timestamp >= self.end_timestamp.0 {
+            0
+        } else {
+            let remaining = self.end_timestamp.0 - timestamp;
+            let duration = self.end_timestamp.0 - self.start_timestamp.0;
+            (U256::from(total_amount) * U256::from(remaining) / U256::from(duration)).as_u128()
+        }
+    }
+}
+
+/// Tokens of `account_id` under vesting.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Vesting {
+    pub account_id: AccountId,
+    pub amount: Balance,
+    pub schedule: VestingSchedule,
+}
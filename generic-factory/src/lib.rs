@@ -1,20 +1,48 @@
+use near_lib::account::{full_sub_account, is_direct_sub_account};
+use near_lib::types::{Timestamp, WrappedTimestamp};
 use near_lib::upgrade::Ownable;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedMap;
 use near_sdk::json_types::Base64VecU8;
 use near_sdk::{env, near_bindgen, AccountId, Promise};
+use near_sdk::serde::{Deserialize, Serialize};
 
-#[global_allocator]
-static ALLOC: near_sdk::wee_alloc::WeeAlloc<'_> = near_sdk::wee_alloc::WeeAlloc::INIT;
+near_sdk::setup_alloc!();
 
 const CODE_KEY: &[u8; 4] = b"code";
 
 /// This gas spent on the call & account creation, the rest goes to the `new` call.
 const CREATE_CALL_GAS: u64 = 5_000_000_000_000;
 
+/// Maximum length of the status reported by a child, to bound the storage it can take.
+const MAX_STATUS_LENGTH: usize = 256;
+
+/// Latest status reported by a child contract.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ChildStatus {
+    status: String,
+    timestamp: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChildStatusView {
+    pub account_id: AccountId,
+    pub status: String,
+    pub timestamp: WrappedTimestamp,
+}
+
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct GenericFactory {
     owner: AccountId,
+    statuses: UnorderedMap<AccountId, ChildStatus>,
+}
+
+/// Layout of `GenericFactory` before children could report their status.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct PrevGenericFactory {
+    owner: AccountId,
 }
 
 impl Default for GenericFactory {
@@ -29,12 +57,27 @@ impl GenericFactory {
     pub fn new(#[serializer(borsh)] owner: AccountId, #[serializer(borsh)] code: Vec<u8>) -> Self {
         assert!(!env::state_exists(), "The contract is already initialized");
         env::storage_write(CODE_KEY, &code);
-        Self { owner }
+        Self {
+            owner,
+            statuses: UnorderedMap::new(b"s".to_vec()),
+        }
+    }
+
+    /// Converts state of the previous version of the contract.
+    /// Must be updated together with any change to the `GenericFactory` layout.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let prev: PrevGenericFactory = env::state_read().expect("The contract is not initialized");
+        Self {
+            owner: prev.owner,
+            statuses: UnorderedMap::new(b"s".to_vec()),
+        }
     }
 
     pub fn create(&self, name: AccountId, args: Base64VecU8) -> Promise {
         let code = env::storage_read(CODE_KEY).expect("Code must be present");
-        Promise::new(format!("{}.{}", name, env::current_account_id()))
+        Promise::new(full_sub_account(&env::current_account_id(), &name))
             .create_account()
             .deploy_contract(code)
             .function_call(
@@ -45,6 +88,45 @@ impl GenericFactory {
             )
     }
 
+    /// Records status of the calling child contract, e.g. its version and health.
+    /// Only accounts created by this factory can report.
+    pub fn report_status(&mut self, status: String) {
+        let account_id = env::predecessor_account_id();
+        assert!(
+            is_direct_sub_account(&env::current_account_id(), &account_id),
+            "Only child accounts can report status"
+        );
+        assert!(status.len() <= MAX_STATUS_LENGTH, "Status is too long");
+        self.statuses.insert(
+            &account_id,
+            &ChildStatus {
+                status,
+                timestamp: env::block_timestamp(),
+            },
+        );
+    }
+
+    /// Returns number of children that reported their status.
+    pub fn get_num_statuses(&self) -> u64 {
+        self.statuses.len()
+    }
+
+    /// Returns latest statuses of children, `limit` of them starting from `from_index`.
+    pub fn get_statuses(&self, from_index: u64, limit: u64) -> Vec<ChildStatusView> {
+        let keys = self.statuses.keys_as_vector();
+        let values = self.statuses.values_as_vector();
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .map(|index| {
+                let status = values.get(index).unwrap();
+                ChildStatusView {
+                    account_id: keys.get(index).unwrap(),
+                    status: status.status,
+                    timestamp: status.timestamp.into(),
+                }
+            })
+            .collect()
+    }
+
     pub fn upgrade(&self, #[serializer(borsh)] code: Vec<u8>) {
         self.assert_owner();
         env::storage_write(CODE_KEY, &code);
@@ -75,4 +157,79 @@ mod tests {
         assert_eq!(factory.get_owner(), accounts(0));
         factory.create("test".to_string(), vec![].into());
     }
+
+    #[test]
+    #[should_panic(expected = "Sub-account id is too long")]
+    fn test_create_too_long() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory.near".to_string())
+            .finish());
+        let factory = GenericFactory::new(accounts(0), vec![].into());
+        factory.create("a".repeat(60), vec![].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid sub-account name")]
+    fn test_create_invalid_name() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory.near".to_string())
+            .finish());
+        let factory = GenericFactory::new(accounts(0), vec![].into());
+        factory.create("test.other".to_string(), vec![].into());
+    }
+
+    #[test]
+    fn test_report_status() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory".to_string())
+            .predecessor_account_id("test.factory".to_string())
+            .block_timestamp(10)
+            .finish());
+        let mut factory = GenericFactory::new(accounts(0), vec![].into());
+        factory.report_status("v1".to_string());
+        factory.report_status("v2".to_string());
+        assert_eq!(factory.get_num_statuses(), 1);
+        assert_eq!(
+            factory.get_statuses(0, 10),
+            vec![ChildStatusView {
+                account_id: "test.factory".to_string(),
+                status: "v2".to_string(),
+                timestamp: 10.into(),
+            }]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Status is too long")]
+    fn test_report_status_too_long() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory".to_string())
+            .predecessor_account_id("test.factory".to_string())
+            .finish());
+        let mut factory = GenericFactory::new(accounts(0), vec![].into());
+        factory.report_status("a".repeat(MAX_STATUS_LENGTH + 1));
+    }
+
+    #[test]
+    fn test_migrate() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory".to_string())
+            .predecessor_account_id("factory".to_string())
+            .finish());
+        env::state_write(&PrevGenericFactory { owner: accounts(0) });
+        let factory = GenericFactory::migrate();
+        assert_eq!(factory.get_owner(), accounts(0));
+        assert_eq!(factory.get_num_statuses(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only child accounts can report status")]
+    fn test_report_status_not_child() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory".to_string())
+            .predecessor_account_id("test.other".to_string())
+            .finish());
+        let mut factory = GenericFactory::new(accounts(0), vec![].into());
+        factory.report_status("v1".to_string());
+    }
 }
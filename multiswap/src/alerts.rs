@@ -0,0 +1,138 @@
+//! Per-pool alert thresholds for monitoring.
+//! When a swap crosses any of the thresholds configured by the owner, a structured `WARN` event is logged,
+//! so monitoring systems can alert on anomalies without diffing pool state for every block.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance};
+
+use crate::*;
+use near_lib::events::NearEvent;
+use near_lib::math::U256;
+/// Thresholds are given in basis points.
+const ALERT_BPS_DIVISOR: u32 = 10_000;
+
+/// Thresholds for the swaps in the pool, each in basis points. `None` disables given check.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AlertThresholds {
+    /// Drop of the `token_out` reserve in a single swap.
+    pub reserve_drop_bps: Option<u32>,
+    /// Move of the `token_in` price in `token_out` in a single swap.
+    pub price_move_bps: Option<u32>,
+    /// Size of a single trade relative to the `token_in` reserve.
+    pub trade_size_bps: Option<u32>,
+}
+
+/// Which threshold was crossed.
+#[derive(Serialize, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+enum AlertKind {
+    ReserveDrop,
+    PriceMove,
+    TradeSize,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AlertData<'a> {
+    kind: AlertKind,
+    pool_id: u64,
+    account_id: &'a AccountId,
+    token_in: &'a AccountId,
+    token_out: &'a AccountId,
+    value_bps: u32,
+    threshold_bps: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Alert<'a> {
+    level: &'static str,
+    event: &'static str,
+    data: AlertData<'a>,
+}
+
+impl NearEvent for Alert<'_> {
+    const STANDARD: &'static str = "multiswap";
+    const VERSION: &'static str = "1.0.0";
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns alert thresholds of given pool.
+    pub fn get_alert_thresholds(&self, pool_id: u64) -> Option<AlertThresholds> {
+        self.alert_thresholds.get(&pool_id)
+    }
+}
+
+impl Contract {
+    /// Logs `WARN` events for the thresholds of the pool crossed by the swap.
+    /// Reserves are `(token_in, token_out)` reserves of the pool before and after the swap.
+    pub(crate) fn internal_check_alerts(
+        &self,
+        pool_id: u64,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        token_out: &AccountId,
+        reserves_before: (Balance, Balance),
+        reserves_after: (Balance, Balance),
+    ) {
+        let thresholds = match self.alert_thresholds.get(&pool_id) {
+            Some(thresholds) => thresholds,
+            None => return,
+        };
+        let (in_before, out_before) = reserves_before;
+        let (in_after, out_after) = reserves_after;
+        let bps = |numerator: U256, denominator: U256| {
+            std::cmp::min(
+                numerator * U256::from(ALERT_BPS_DIVISOR) / denominator,
+                U256::from(u32::MAX),
+            )
+            .as_u32()
+        };
+        let checks = [
+            (
+                AlertKind::TradeSize,
+                thresholds.trade_size_bps,
+                bps(U256::from(in_after - in_before), U256::from(in_before)),
+            ),
+            (
+                AlertKind::ReserveDrop,
+                thresholds.reserve_drop_bps,
+                bps(U256::from(out_before - out_after), U256::from(out_before)),
+            ),
+            (
+                // Price of token_in in token_out only goes down with the swap.
+                AlertKind::PriceMove,
+                thresholds.price_move_bps,
+                bps(
+                    U256::from(out_before) * U256::from(in_after)
+                        - U256::from(out_after) * U256::from(in_before),
+                    U256::from(out_before) * U256::from(in_after),
+                ),
+            ),
+        ];
+        for (kind, threshold_bps, value_bps) in checks.iter() {
+            if let Some(threshold_bps) = threshold_bps {
+                if value_bps >= threshold_bps {
+                    Alert {
+                        level: "WARN",
+                        event: "alert",
+                        data: AlertData {
+                            kind: *kind,
+                            pool_id,
+                            account_id: sender_id,
+                            token_in,
+                            token_out,
+                            value_bps: *value_bps,
+                            threshold_bps: *threshold_bps,
+                        },
+                    }
+                    .emit();
+                }
+            }
+        }
+    }
+}
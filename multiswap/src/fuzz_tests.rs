@@ -0,0 +1,81 @@
+//! Randomized round-trip tests of the pool math: rounding must always be in favor of the pool,
+//! so that no sequence of operations lets an account take out more than it put in.
+
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::{testing_env, Balance, MockedBlockchain};
+use near_test::fuzz::gen_amount;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::simple_pool::{SimplePool, FEE_DIVISOR};
+
+const ITERATIONS: usize = 1_000;
+
+/// Pool of `accounts(1)` and `accounts(2)` with given reserves, added by `accounts(0)`.
+fn setup_pool(rng: &mut StdRng, fee: u32) -> SimplePool {
+    testing_env!(VMContextBuilder::new().build());
+    let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], fee);
+    pool.add_liquidity(
+        accounts(0).as_ref(),
+        &mut vec![
+            gen_amount(rng, 1_000, 10u128.pow(30)),
+            gen_amount(rng, 1_000, 10u128.pow(30)),
+        ],
+    );
+    pool
+}
+
+#[test]
+fn fuzz_add_remove_liquidity() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..ITERATIONS {
+        let mut pool = setup_pool(&mut rng, 30);
+        let reserves = pool.amounts.clone();
+        let amounts = vec![
+            gen_amount(&mut rng, 1, 10u128.pow(30)),
+            gen_amount(&mut rng, 1, 10u128.pow(30)),
+        ];
+        let mut used = amounts.clone();
+        let shares = pool.add_liquidity(accounts(3).as_ref(), &mut used);
+        let added: Vec<Balance> = (0..2).map(|i| pool.amounts[i] - reserves[i]).collect();
+        let removed = pool.remove_liquidity(accounts(3).as_ref(), shares, vec![0, 0]);
+        for i in 0..2 {
+            assert_eq!(added[i], used[i]);
+            assert!(
+                added[i] <= amounts[i] && removed[i] <= added[i],
+                "reserves {:?}, amounts {:?}: added {:?}, removed {:?}",
+                reserves,
+                amounts,
+                added,
+                removed
+            );
+            assert!(pool.amounts[i] >= reserves[i]);
+        }
+    }
+}
+
+#[test]
+fn fuzz_swap_round_trip() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..ITERATIONS {
+        let fee = rng.gen_range(0, FEE_DIVISOR / 10);
+        let mut pool = setup_pool(&mut rng, fee);
+        let reserves = pool.amounts.clone();
+        let amount_in = gen_amount(&mut rng, 1, reserves[0]);
+        let amount_out = pool.swap(accounts(1).as_ref(), amount_in, accounts(2).as_ref(), 0);
+        if amount_out == 0 {
+            continue;
+        }
+        let amount_back = pool.swap(accounts(2).as_ref(), amount_out, accounts(1).as_ref(), 0);
+        assert!(
+            amount_back <= amount_in,
+            "reserves {:?}, fee {}: swapped {} into {} and back into {}",
+            reserves,
+            fee,
+            amount_in,
+            amount_out,
+            amount_back
+        );
+        assert!(pool.amounts[0] >= reserves[0] && pool.amounts[1] >= reserves[1]);
+    }
+}
@@ -0,0 +1,44 @@
+//! Configuration of the exchange by the owner. Enabled by the `governance` feature.
+
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Sets account that receives donations from swaps. Only owner can call.
+    /// The account must be registered, as donations are credited to its deposits.
+    pub fn set_public_goods_account(&mut self, account_id: Option<ValidAccountId>) {
+        self.assert_owner();
+        let account_id = account_id.map(|a| a.into());
+        if let Some(account_id) = account_id.as_ref() {
+            assert!(
+                self.deposited_amounts.contains_key(account_id),
+                "ERR_NOT_REGISTERED"
+            );
+        }
+        self.public_goods_account_id = account_id;
+    }
+
+    /// Sets alert thresholds for given pool, `None` removes them. Only owner can call.
+    pub fn set_alert_thresholds(&mut self, pool_id: u64, thresholds: Option<AlertThresholds>) {
+        self.assert_owner();
+        assert!(pool_id < self.pools.len(), "ERR_NO_POOL");
+        match thresholds {
+            Some(thresholds) => {
+                self.alert_thresholds.insert(&pool_id, &thresholds);
+            }
+            None => {
+                self.alert_thresholds.remove(&pool_id);
+            }
+        }
+    }
+}
+
+impl Contract {
+    fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "ERR_NOT_ALLOWED"
+        );
+    }
+}
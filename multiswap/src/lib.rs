@@ -4,6 +4,7 @@ use std::convert::TryInto;
 use near_contract_standards::storage_management::{
     StorageBalance, StorageBalanceBounds, StorageManagement,
 };
+use near_lib::math::mul_div;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::{LookupMap, Vector};
 use near_sdk::json_types::{ValidAccountId, U128};
@@ -12,11 +13,19 @@ use near_sdk::{
     assert_one_yocto, env, log, near_bindgen, AccountId, Balance, PanicOnDefault, Promise,
 };
 
+pub use crate::alerts::AlertThresholds;
 use crate::pool::Pool;
-use crate::simple_pool::SimplePool;
-use crate::utils::{check_token_duplicates, ext_fungible_token, GAS_FOR_FT_TRANSFER};
+pub use crate::pool::PoolStatus;
+use crate::utils::{ext_fungible_token, GAS_FOR_FT_TRANSFER};
 pub use crate::views::PoolInfo;
 
+mod alerts;
+#[cfg(all(test, feature = "pools"))]
+mod fuzz_tests;
+#[cfg(feature = "governance")]
+mod governance;
+#[cfg(feature = "pools")]
+mod liquidity;
 mod pool;
 mod simple_pool;
 mod storage_impl;
@@ -30,6 +39,8 @@ const MAX_ACCOUNT_LENGTH: u128 = 64;
 const MAX_NUMBER_OF_TOKENS: u128 = 10;
 const BYTES_PER_DEPOSIT_RECORD: u128 =
     MAX_NUMBER_OF_TOKENS * (MAX_ACCOUNT_LENGTH + 16) + 4 + MAX_ACCOUNT_LENGTH;
+/// Donations are given in basis points of the swap output.
+const DONATION_DIVISOR: u32 = 10_000;
 
 /// Single swap action.
 #[derive(Serialize, Deserialize)]
@@ -49,37 +60,46 @@ pub struct SwapAction {
     pub min_amount_out: U128,
 }
 
+/// Transfer of deposited token to another registered account inside the exchange.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InternalTransfer {
+    pub token_id: ValidAccountId,
+    pub receiver_id: ValidAccountId,
+    pub amount: U128,
+}
+
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 pub struct Contract {
+    /// Account that can change configuration of the exchange.
+    owner_id: AccountId,
     pools: Vector<Pool>,
     /// Balances of deposited tokens for each account.
     deposited_amounts: LookupMap<AccountId, HashMap<AccountId, Balance>>,
+    /// Account that receives donations from swaps into its deposits.
+    public_goods_account_id: Option<AccountId>,
+    /// Cumulative donations per account per token.
+    donations: LookupMap<AccountId, HashMap<AccountId, Balance>>,
+    /// Alert thresholds per pool.
+    alert_thresholds: LookupMap<u64, AlertThresholds>,
 }
 
 #[near_bindgen]
 impl Contract {
     #[init]
-    pub fn new() -> Self {
+    pub fn new(owner_id: ValidAccountId) -> Self {
         assert!(!env::state_exists(), "ERR_CONTRACT_IS_INITIALIZED");
         Self {
+            owner_id: owner_id.into(),
             pools: Vector::new(b"p".to_vec()),
             deposited_amounts: LookupMap::new(b"d".to_vec()),
+            public_goods_account_id: None,
+            donations: LookupMap::new(b"g".to_vec()),
+            alert_thresholds: LookupMap::new(b"a".to_vec()),
         }
     }
 
-    /// Adds new "Simple Pool" with given tokens and given fee.
-    /// Attached NEAR should be enough to cover the added storage.
-    #[payable]
-    pub fn add_simple_pool(&mut self, tokens: Vec<ValidAccountId>, fee: u32) -> u32 {
-        check_token_duplicates(&tokens);
-        self.internal_add_pool(Pool::SimplePool(SimplePool::new(
-            self.pools.len() as u32,
-            tokens,
-            fee,
-        )))
-    }
-
     /// Swaps given amount_in of token_in into token_out via given pool.
     /// Should be at least min_amount_out or swap will fail (prevents front running and other slippage issues).
     pub fn internal_swap(
@@ -96,25 +116,45 @@ impl Contract {
         let amount_in: u128 = amount_in.into();
         assert!(amount_in <= prev_amount_in, "ERR_NOT_ENOUGH_DEPOSIT");
         let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let reserves_before = (
+            pool.reserve(token_in.as_ref()),
+            pool.reserve(token_out.as_ref()),
+        );
         let amount_out = pool.swap(
             token_in.as_ref(),
             amount_in,
             token_out.as_ref(),
             min_amount_out.into(),
         );
+        self.internal_check_alerts(
+            pool_id,
+            sender_id,
+            token_in.as_ref(),
+            token_out.as_ref(),
+            reserves_before,
+            (
+                pool.reserve(token_in.as_ref()),
+                pool.reserve(token_out.as_ref()),
+            ),
+        );
         self.internal_deposit(&sender_id, token_in.as_ref(), prev_amount_in - amount_in);
         self.internal_deposit(&sender_id, token_out.as_ref(), prev_amount_out + amount_out);
         self.pools.replace(pool_id, &pool);
         amount_out.into()
     }
 
-    pub fn swap(&mut self, actions: Vec<SwapAction>) -> U128 {
+    /// Executes given swap actions in order.
+    /// If `donation_bps` is given, that share of the final output goes to the public goods account
+    /// and the returned amount is what is left to the sender.
+    pub fn swap(&mut self, actions: Vec<SwapAction>, donation_bps: Option<u32>) -> U128 {
         let sender_id = env::predecessor_account_id();
         let mut prev_amount = None;
+        let mut token_out = None;
         for action in actions {
             let amount_in = action
                 .amount_in
                 .unwrap_or_else(|| prev_amount.expect("ERR_FIRST_SWAP_MISSING_AMOUNT"));
+            token_out = Some(action.token_out.clone());
             prev_amount = Some(self.internal_swap(
                 &sender_id,
                 action.pool_id,
@@ -124,54 +164,35 @@ impl Contract {
                 action.min_amount_out,
             ));
         }
-        prev_amount.unwrap()
-    }
-
-    /// Add liquidity from already deposited amounts to given pool.
-    pub fn add_liquidity(&mut self, pool_id: u64, amounts: Vec<U128>) {
-        let sender_id = env::predecessor_account_id();
-        let amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
-        let mut deposits = self.internal_get_deposits(&sender_id);
-        let tokens = pool.tokens();
-        for i in 0..tokens.len() {
-            let amount = *deposits
-                .get(&tokens[i])
-                .expect(&format!("ERR_MISSING_TOKEN:{}", tokens[i]));
-            assert!(
-                amounts[i] <= amount,
-                format!("ERR_NOT_ENOUGH_TOKEN:{}", tokens[i])
-            );
-            if amounts[i] == amount {
-                deposits.remove(&tokens[i]);
-            } else {
-                deposits.insert(tokens[i].clone(), amount - amounts[i]);
+        let amount_out = prev_amount.unwrap();
+        match donation_bps {
+            Some(donation_bps) if donation_bps > 0 => {
+                let donation = self.internal_donate(
+                    &sender_id,
+                    token_out.unwrap().as_ref(),
+                    amount_out.into(),
+                    donation_bps,
+                );
+                U128(amount_out.0 - donation)
             }
+            _ => amount_out,
         }
-        pool.add_liquidity(&sender_id, amounts);
-        self.deposited_amounts.insert(&sender_id, &deposits);
-        self.pools.replace(pool_id, &pool);
     }
 
-    /// Remove liquidity from the pool into general pool of liquidity.
-    pub fn remove_liquidity(&mut self, pool_id: u64, shares: U128, min_amounts: Vec<U128>) {
+    /// Moves deposited tokens of the sender to other registered accounts, without transferring the tokens
+    /// outside of the exchange. Transfers are applied in order, the whole batch fails if any of them fails.
+    #[payable]
+    pub fn deposit_batch(&mut self, transfers: Vec<InternalTransfer>) {
+        assert_one_yocto();
         let sender_id = env::predecessor_account_id();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
-        let amounts = pool.remove_liquidity(
-            &sender_id,
-            shares.into(),
-            min_amounts
-                .into_iter()
-                .map(|amount| amount.into())
-                .collect(),
-        );
-        self.pools.replace(pool_id, &pool);
-        let tokens = pool.tokens();
-        let mut deposits = self.internal_get_deposits(&sender_id);
-        for i in 0..tokens.len() {
-            *deposits.entry(tokens[i].clone()).or_default() += amounts[i];
+        for transfer in transfers {
+            self.internal_transfer(
+                &sender_id,
+                transfer.token_id.as_ref(),
+                transfer.receiver_id.as_ref(),
+                transfer.amount.into(),
+            );
         }
-        self.deposited_amounts.insert(&sender_id, &deposits);
     }
 
     /// Withdraws given token from the deposits of given user.
@@ -204,18 +225,69 @@ impl Contract {
 
 /// Internal methods implementation.
 impl Contract {
-    /// Adds given pool to the list and returns it's id.
-    /// If there is not enough attached balance to cover storage, fails.
-    fn internal_add_pool(&mut self, pool: Pool) -> u32 {
-        let prev_storage = env::storage_usage();
-        let id = self.pools.len() as u32;
-        self.pools.push(&pool);
+    /// Moves `donation_bps` of given amount of token from sender's deposit to the public goods account.
+    /// Returns donated amount.
+    fn internal_donate(
+        &mut self,
+        sender_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+        donation_bps: u32,
+    ) -> Balance {
+        assert!(donation_bps <= DONATION_DIVISOR, "ERR_DONATION_TOO_LARGE");
+        let public_goods_account_id = self
+            .public_goods_account_id
+            .clone()
+            .expect("ERR_NO_PUBLIC_GOODS_ACCOUNT");
+        let donation = mul_div(amount, donation_bps as u128, DONATION_DIVISOR as u128);
+        if donation == 0 {
+            return 0;
+        }
+        let sender_amount = self.internal_get_deposit(sender_id, token_id);
+        self.internal_deposit(sender_id, token_id, sender_amount - donation);
+        let receiver_amount = self.internal_get_deposit(&public_goods_account_id, token_id);
+        self.internal_deposit(
+            &public_goods_account_id,
+            token_id,
+            receiver_amount + donation,
+        );
+        let mut donations = self.donations.get(sender_id).unwrap_or_default();
+        *donations.entry(token_id.clone()).or_default() += donation;
+        self.donations.insert(sender_id, &donations);
+        log!(
+            "Donated {} {} to {}",
+            donation,
+            token_id,
+            public_goods_account_id
+        );
+        donation
+    }
+
+    /// Moves given amount of token from sender's deposit to receiver's deposit.
+    fn internal_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        token_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        assert_ne!(sender_id, receiver_id, "ERR_SAME_ACCOUNT");
         assert!(
-            (env::storage_usage() - prev_storage) as u128 * env::storage_byte_cost()
-                <= env::attached_deposit(),
-            "ERR_STORAGE_DEPOSIT"
+            self.deposited_amounts.contains_key(receiver_id),
+            "ERR_RECEIVER_NOT_REGISTERED"
+        );
+        let sender_amount = self.internal_get_deposit(sender_id, token_id);
+        assert!(amount <= sender_amount, "ERR_NOT_ENOUGH_DEPOSIT");
+        self.internal_deposit(sender_id, token_id, sender_amount - amount);
+        let receiver_amount = self.internal_get_deposit(receiver_id, token_id);
+        self.internal_deposit(receiver_id, token_id, receiver_amount + amount);
+        log!(
+            "Transferred {} {} from {} to {}",
+            amount,
+            token_id,
+            sender_id,
+            receiver_id
         );
-        id
     }
 
     /// Registers account in deposited amounts.
@@ -262,12 +334,13 @@ mod tests {
     use super::*;
 
     #[test]
+    #[cfg(feature = "pools")]
     fn test_basics() {
         let one_near = 10u128.pow(24);
         let mut context = VMContextBuilder::new();
         context.predecessor_account_id(accounts(0));
         testing_env!(context.build());
-        let mut contract = Contract::new();
+        let mut contract = Contract::new(accounts(0));
 
         // create 1st pool (1, 2) with 0.3% fee.
         testing_env!(context
@@ -301,20 +374,34 @@ mod tests {
         contract.add_liquidity(0, vec![U128(5 * one_near), U128(10 * one_near)]);
         assert_eq!(
             contract.get_pool_total_shares(0),
-            U128(1000000000000000000000000)
+            Some(U128(1000000000000000000000000))
         );
+        assert_eq!(contract.get_pool_status(0), Some(PoolStatus::Active));
 
         // Get price from pool #0 1 -> 2 tokens.
         let amount_out = contract.get_return(0, accounts(1), one_near.into(), accounts(2));
-        assert_eq!(amount_out, 1662497915624478906119726.into());
+        assert_eq!(amount_out, Some(1662497915624478906119726.into()));
 
-        let amount_out = contract.swap(vec![SwapAction {
+        let simulation = contract.simulate_swap(vec![SwapAction {
             pool_id: 0,
             token_in: accounts(1),
             amount_in: Some(one_near.into()),
             token_out: accounts(2),
             min_amount_out: U128(1),
         }]);
+        assert_eq!(simulation.amount_out, 1662497915624478906119726.into());
+        assert_eq!(simulation.fees, vec![(one_near * 30 / 10_000).into()]);
+
+        let amount_out = contract.swap(
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: accounts(1),
+                amount_in: Some(one_near.into()),
+                token_out: accounts(2),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
         assert_eq!(amount_out, 1662497915624478906119726.into());
         assert_eq!(
             contract.get_deposit(accounts(3).as_ref(), accounts(1).as_ref()),
@@ -328,10 +415,16 @@ mod tests {
         testing_env!(context.predecessor_account_id(accounts(3)).build());
         contract.remove_liquidity(
             0,
-            contract.get_pool_shares(0, accounts(3)),
+            contract.get_pool_shares(0, accounts(3)).unwrap(),
             vec![1.into(), 2.into()],
         );
-        assert_eq!(contract.get_pool_total_shares(0), U128(0));
+        assert_eq!(contract.get_pool_total_shares(0), Some(U128(0)));
+        assert_eq!(contract.get_pool_status(0), Some(PoolStatus::Removed));
+        assert_eq!(
+            contract.get_return(0, accounts(1), one_near.into(), accounts(2)),
+            None
+        );
+        assert_eq!(contract.get_pool_status(1), None);
 
         contract.withdraw(
             accounts(1),
@@ -339,13 +432,207 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(feature = "pools")]
+    fn test_add_liquidity_debits_used_amounts() {
+        let one_near = 10u128.pow(24);
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(env::storage_byte_cost() * 300)
+            .build());
+        contract.add_simple_pool(vec![accounts(1), accounts(2)], 30);
+        testing_env!(context
+            .attached_deposit(contract.storage_balance_bounds().min.0)
+            .build());
+        contract.storage_deposit(None, None);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_on_transfer(accounts(3), (100 * one_near).into(), "".to_string());
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(accounts(3), (100 * one_near).into(), "".to_string());
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.add_liquidity(0, vec![U128(5 * one_near), U128(10 * one_near)]);
+
+        // Pool is at 1:2, so only half of the first token is taken.
+        contract.add_liquidity(0, vec![U128(5 * one_near), U128(5 * one_near)]);
+        assert_eq!(
+            contract.get_deposit(accounts(3).as_ref(), accounts(1).as_ref()),
+            (100 * one_near - 5 * one_near - 5 * one_near / 2).into()
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(3).as_ref(), accounts(2).as_ref()),
+            (85 * one_near).into()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "pools")]
+    fn test_add_liquidity_for() {
+        let one_near = 10u128.pow(24);
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(env::storage_byte_cost() * 300)
+            .build());
+        contract.add_simple_pool(vec![accounts(1), accounts(2)], 30);
+        for account_id in [accounts(3), accounts(4)].iter() {
+            testing_env!(context
+                .predecessor_account_id(account_id.clone())
+                .attached_deposit(contract.storage_balance_bounds().min.0)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_on_transfer(accounts(3), (20 * one_near).into(), "".to_string());
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(accounts(3), (30 * one_near).into(), "".to_string());
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.add_liquidity(0, vec![U128(5 * one_near), U128(10 * one_near)]);
+
+        // Shares and the part the pool doesn't take go to accounts(4).
+        let shares = contract.get_pool_shares(0, accounts(3)).unwrap();
+        contract.add_liquidity_for(
+            0,
+            vec![U128(10 * one_near), U128(10 * one_near)],
+            accounts(4),
+        );
+        assert_eq!(contract.get_pool_shares(0, accounts(4)), Some(shares));
+        assert_eq!(
+            contract.get_deposit(accounts(3).as_ref(), accounts(1).as_ref()),
+            (5 * one_near).into()
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(3).as_ref(), accounts(2).as_ref()),
+            (10 * one_near).into()
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(4).as_ref(), accounts(1).as_ref()),
+            (5 * one_near).into()
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(4).as_ref(), accounts(2).as_ref()),
+            0.into()
+        );
+    }
+
+    #[test]
+    fn test_deposit_batch() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0));
+        for account_id in [accounts(3), accounts(4)].iter() {
+            testing_env!(context
+                .predecessor_account_id(account_id.clone())
+                .attached_deposit(contract.storage_balance_bounds().min.0)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_on_transfer(accounts(3), 100.into(), "".to_string());
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.deposit_batch(vec![
+            InternalTransfer {
+                token_id: accounts(1),
+                receiver_id: accounts(4),
+                amount: 30.into(),
+            },
+            InternalTransfer {
+                token_id: accounts(1),
+                receiver_id: accounts(4),
+                amount: 20.into(),
+            },
+        ]);
+        assert_eq!(
+            contract.get_deposit(accounts(3).as_ref(), accounts(1).as_ref()),
+            50.into()
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(4).as_ref(), accounts(1).as_ref()),
+            50.into()
+        );
+        // Tokens can be deposited for another registered account.
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_on_transfer(accounts(3), 10.into(), accounts(4).as_ref().clone());
+        assert_eq!(
+            contract.get_deposit(accounts(4).as_ref(), accounts(1).as_ref()),
+            60.into()
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "pools", feature = "governance"))]
+    fn test_alert_thresholds() {
+        let one_near = 10u128.pow(24);
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(env::storage_byte_cost() * 300)
+            .build());
+        contract.add_simple_pool(vec![accounts(1), accounts(2)], 30);
+        testing_env!(context
+            .attached_deposit(contract.storage_balance_bounds().min.0)
+            .build());
+        contract.storage_deposit(None, None);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_on_transfer(accounts(3), (20 * one_near).into(), "".to_string());
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(accounts(3), (10 * one_near).into(), "".to_string());
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.add_liquidity(0, vec![U128(10 * one_near), U128(10 * one_near)]);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let thresholds = AlertThresholds {
+            reserve_drop_bps: Some(500),
+            price_move_bps: None,
+            trade_size_bps: Some(2_000),
+        };
+        contract.set_alert_thresholds(0, Some(thresholds.clone()));
+        assert_eq!(contract.get_alert_thresholds(0), Some(thresholds));
+
+        // Swapping 10% of the reserve drops the other reserve by ~9%: only reserve drop is crossed.
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.swap(
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: accounts(1),
+                amount_in: Some(one_near.into()),
+                token_out: accounts(2),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+        let alerts: Vec<String> = near_sdk::test_utils::get_logs()
+            .into_iter()
+            .filter(|log| log.contains("\"level\":\"WARN\""))
+            .collect();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].contains("\"kind\":\"reserve_drop\""));
+        assert!(alerts[0].contains("\"threshold_bps\":500"));
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_alert_thresholds(0, None);
+        assert_eq!(contract.get_alert_thresholds(0), None);
+    }
+
     /// Should deny creating a pool with duplicate tokens.
     #[test]
+    #[cfg(feature = "pools")]
     #[should_panic(expected = "ERR_TOKEN_DUPLICATES")]
     fn test_deny_duplicate_tokens_pool() {
         let mut context = VMContextBuilder::new();
         testing_env!(context.build());
-        let mut contract = Contract::new();
+        let mut contract = Contract::new(accounts(0));
         testing_env!(context
             .predecessor_account_id(accounts(3))
             .attached_deposit(env::storage_byte_cost() * 300)
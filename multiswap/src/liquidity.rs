@@ -0,0 +1,135 @@
+//! Creation of pools and management of their liquidity. Enabled by the `pools` feature.
+
+use crate::simple_pool::SimplePool;
+use crate::utils::check_token_duplicates;
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Adds new "Simple Pool" with given tokens and given fee.
+    /// Attached NEAR should be enough to cover the added storage.
+    #[payable]
+    pub fn add_simple_pool(&mut self, tokens: Vec<ValidAccountId>, fee: u32) -> u32 {
+        check_token_duplicates(&tokens);
+        self.internal_add_pool(Pool::SimplePool(SimplePool::new(
+            self.pools.len() as u32,
+            tokens,
+            fee,
+        )))
+    }
+
+    /// Add liquidity from already deposited amounts to given pool.
+    /// Only the amounts the pool actually takes are withdrawn from the deposits.
+    pub fn add_liquidity(&mut self, pool_id: u64, amounts: Vec<U128>) {
+        let sender_id = env::predecessor_account_id();
+        self.internal_add_liquidity(
+            &sender_id,
+            pool_id,
+            amounts.into_iter().map(|amount| amount.into()).collect(),
+            &sender_id,
+        );
+    }
+
+    /// Adds liquidity from the sender's deposits to given pool on behalf of `account_id`, which receives
+    /// the shares and, into its deposits, the part of `amounts` the pool doesn't take.
+    /// Lets other contracts add liquidity for their users, e.g. exchanges migrating liquidity here.
+    pub fn add_liquidity_for(
+        &mut self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        account_id: ValidAccountId,
+    ) {
+        let sender_id = env::predecessor_account_id();
+        assert_ne!(&sender_id, account_id.as_ref(), "ERR_SAME_ACCOUNT");
+        assert!(
+            self.deposited_amounts.contains_key(account_id.as_ref()),
+            "ERR_RECEIVER_NOT_REGISTERED"
+        );
+        let amounts: Vec<Balance> = amounts.into_iter().map(|amount| amount.into()).collect();
+        let used_amounts =
+            self.internal_add_liquidity(&sender_id, pool_id, amounts.clone(), account_id.as_ref());
+        let tokens = self.pools.get(pool_id).unwrap().tokens().to_vec();
+        for i in 0..tokens.len() {
+            if amounts[i] > used_amounts[i] {
+                self.internal_transfer(
+                    &sender_id,
+                    &tokens[i],
+                    account_id.as_ref(),
+                    amounts[i] - used_amounts[i],
+                );
+            }
+        }
+    }
+
+    /// Remove liquidity from the pool into general pool of liquidity.
+    pub fn remove_liquidity(&mut self, pool_id: u64, shares: U128, min_amounts: Vec<U128>) {
+        let sender_id = env::predecessor_account_id();
+        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let amounts = pool.remove_liquidity(
+            &sender_id,
+            shares.into(),
+            min_amounts
+                .into_iter()
+                .map(|amount| amount.into())
+                .collect(),
+        );
+        self.pools.replace(pool_id, &pool);
+        let tokens = pool.tokens();
+        let mut deposits = self.internal_get_deposits(&sender_id);
+        for i in 0..tokens.len() {
+            *deposits.entry(tokens[i].clone()).or_default() += amounts[i];
+        }
+        self.deposited_amounts.insert(&sender_id, &deposits);
+    }
+}
+
+impl Contract {
+    /// Adds liquidity from the deposits of `sender_id` to given pool, minting the shares to `shares_receiver_id`.
+    /// Returns the amounts the pool took, only these are withdrawn from the deposits.
+    fn internal_add_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        pool_id: u64,
+        mut amounts: Vec<Balance>,
+        shares_receiver_id: &AccountId,
+    ) -> Vec<Balance> {
+        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let mut deposits = self.internal_get_deposits(sender_id);
+        let tokens = pool.tokens().to_vec();
+        for i in 0..tokens.len() {
+            let amount = *deposits
+                .get(&tokens[i])
+                .expect(&format!("ERR_MISSING_TOKEN:{}", tokens[i]));
+            assert!(
+                amounts[i] <= amount,
+                format!("ERR_NOT_ENOUGH_TOKEN:{}", tokens[i])
+            );
+        }
+        pool.add_liquidity(shares_receiver_id, &mut amounts);
+        for i in 0..tokens.len() {
+            let amount = deposits[&tokens[i]];
+            if amounts[i] == amount {
+                deposits.remove(&tokens[i]);
+            } else {
+                deposits.insert(tokens[i].clone(), amount - amounts[i]);
+            }
+        }
+        self.deposited_amounts.insert(sender_id, &deposits);
+        self.pools.replace(pool_id, &pool);
+        amounts
+    }
+
+    /// Adds given pool to the list and returns it's id.
+    /// If there is not enough attached balance to cover storage, fails.
+    fn internal_add_pool(&mut self, pool: Pool) -> u32 {
+        let prev_storage = env::storage_usage();
+        let id = self.pools.len() as u32;
+        self.pools.push(&pool);
+        assert!(
+            (env::storage_usage() - prev_storage) as u128 * env::storage_byte_cost()
+                <= env::attached_deposit(),
+            "ERR_STORAGE_DEPOSIT"
+        );
+        id
+    }
+}
@@ -1,8 +1,21 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{AccountId, Balance};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{AccountId, Balance, BlockHeight};
 
 use crate::simple_pool::SimplePool;
 
+/// Lifecycle stage of the pool, so clients can tell which pools can be traded against.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PoolStatus {
+    /// Pool was created, but liquidity was never added.
+    Initializing,
+    /// Pool has liquidity.
+    Active,
+    /// All liquidity was removed from the pool.
+    Removed,
+}
+
 /// Generic Pool, providing wrapper around different implementations of swap pools.
 /// Allows to add new types of pools just by adding extra item in the enum without needing to migrate the storage.
 #[derive(BorshSerialize, BorshDeserialize)]
@@ -18,6 +31,28 @@ impl Pool {
         }
     }
 
+    /// Returns lifecycle stage of the pool.
+    pub fn status(&self) -> PoolStatus {
+        match self {
+            Pool::SimplePool(pool) => {
+                if pool.shares_total_supply > 0 {
+                    PoolStatus::Active
+                } else if pool.share_supply_checkpoints.is_empty() {
+                    PoolStatus::Initializing
+                } else {
+                    PoolStatus::Removed
+                }
+            }
+        }
+    }
+
+    /// Returns fee charged for swaps in the underlying pool (gets divided by FEE_DIVISOR).
+    pub fn get_fee(&self) -> u32 {
+        match self {
+            Pool::SimplePool(pool) => pool.fee,
+        }
+    }
+
     /// Returns which tokens are in the underlying pool.
     pub fn tokens(&self) -> &[AccountId] {
         match self {
@@ -25,14 +60,24 @@ impl Pool {
         }
     }
 
+    /// Returns amount of given token in the underlying pool.
+    pub fn reserve(&self, token_id: &AccountId) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.reserve(token_id),
+        }
+    }
+
     /// Adds liquidity into underlying pool.
-    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: Vec<Balance>) -> Balance {
+    /// Updates `amounts` to the amounts actually taken by the pool.
+    #[cfg(feature = "pools")]
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut Vec<Balance>) -> Balance {
         match self {
             Pool::SimplePool(pool) => pool.add_liquidity(sender_id, amounts),
         }
     }
 
     /// Removes liquidity from underlying pool.
+    #[cfg(feature = "pools")]
     pub fn remove_liquidity(
         &mut self,
         sender_id: &AccountId,
@@ -75,6 +120,13 @@ impl Pool {
         }
     }
 
+    /// Returns total number of shares at the end of given block.
+    pub fn share_supply_at(&self, block_height: BlockHeight) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.share_supply_at(block_height),
+        }
+    }
+
     pub fn share_balances(&self, account_id: &AccountId) -> Balance {
         match self {
             Pool::SimplePool(pool) => pool.share_balances(account_id),
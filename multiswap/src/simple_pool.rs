@@ -1,14 +1,23 @@
+#[cfg(feature = "pools")]
 use std::cmp::min;
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LookupMap;
+use near_sdk::collections::{LookupMap, Vector};
+#[cfg(feature = "pools")]
 use near_sdk::json_types::ValidAccountId;
-use near_sdk::{env, AccountId, Balance};
+use near_sdk::{env, AccountId, Balance, BlockHeight};
 
-use crate::utils::{add_to_collection, U256};
+use near_lib::math::U256;
+#[cfg(feature = "pools")]
+use near_lib::math::{mul_div, mul_div_ceil};
 
-const FEE_DIVISOR: u32 = 10_000;
+#[cfg(feature = "pools")]
+use crate::utils::add_to_collection;
+
+pub const FEE_DIVISOR: u32 = 10_000;
+#[cfg(feature = "pools")]
 const MAX_NUM_TOKENS: usize = 10;
+#[cfg(feature = "pools")]
 const INIT_SHARES_SUPPLY: u128 = 1_000_000_000_000_000_000_000_000;
 
 /// Implementation of simple pool, that maintains constant product between balances of all the tokens.
@@ -26,9 +35,12 @@ pub struct SimplePool {
     pub shares: LookupMap<AccountId, Balance>,
     /// Total number of shares.
     pub shares_total_supply: Balance,
+    /// Total number of shares after the last liquidity change in each block, ordered by block height.
+    pub share_supply_checkpoints: Vector<(BlockHeight, Balance)>,
 }
 
 impl SimplePool {
+    #[cfg(feature = "pools")]
     pub fn new(id: u32, token_account_ids: Vec<ValidAccountId>, fee: u32) -> Self {
         assert!(fee < FEE_DIVISOR, "ERR_FEE_TOO_LARGE");
         assert!(
@@ -41,6 +53,7 @@ impl SimplePool {
             fee,
             shares: LookupMap::new(format!("s{}", id).into_bytes()),
             shares_total_supply: 0,
+            share_supply_checkpoints: Vector::new(format!("c{}", id).into_bytes()),
             // liquidity_amounts: LookupMap::new(format!("l{}", id).into_bytes()),
         }
     }
@@ -55,33 +68,73 @@ impl SimplePool {
         self.shares_total_supply
     }
 
+    /// Returns total number of shares at the end of given block.
+    pub fn share_supply_at(&self, block_height: BlockHeight) -> Balance {
+        // Find the last checkpoint at or before the given block.
+        let (mut low, mut high) = (0, self.share_supply_checkpoints.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            let (height, _) = self.share_supply_checkpoints.get(mid).unwrap();
+            if height <= block_height {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            0
+        } else {
+            self.share_supply_checkpoints.get(low - 1).unwrap().1
+        }
+    }
+
+    /// Records current total number of shares for the current block.
+    #[cfg(feature = "pools")]
+    fn checkpoint_share_supply(&mut self) {
+        let block_height = env::block_index();
+        let checkpoint = (block_height, self.shares_total_supply);
+        let len = self.share_supply_checkpoints.len();
+        if len > 0 && self.share_supply_checkpoints.get(len - 1).unwrap().0 == block_height {
+            self.share_supply_checkpoints.replace(len - 1, &checkpoint);
+        } else {
+            self.share_supply_checkpoints.push(&checkpoint);
+        }
+    }
+
     /// Returns list of tokens in this pool.
     pub fn tokens(&self) -> &[AccountId] {
         &self.token_account_ids
     }
 
+    /// Returns amount of given token in this pool.
+    pub fn reserve(&self, token_id: &AccountId) -> Balance {
+        self.amounts[self.token_index(token_id)]
+    }
+
     /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
-    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: Vec<Balance>) -> Balance {
+    /// Updates `amounts` to the amounts actually taken, which can be less than given to keep the ratio.
+    #[cfg(feature = "pools")]
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut Vec<Balance>) -> Balance {
         assert_eq!(
             amounts.len(),
             self.token_account_ids.len(),
             "ERR_WRONG_TOKEN_COUNT"
         );
         let shares = if self.shares_total_supply > 0 {
-            let mut fair_supply = U256::max_value();
+            let mut fair_supply = Balance::MAX;
             for i in 0..self.token_account_ids.len() {
                 assert!(amounts[i] > 0, "ERR_AMOUNT_ZERO");
                 fair_supply = min(
                     fair_supply,
-                    U256::from(amounts[i]) * U256::from(self.shares_total_supply) / self.amounts[i],
+                    mul_div(amounts[i], self.shares_total_supply, self.amounts[i]),
                 );
             }
             for i in 0..self.token_account_ids.len() {
-                let amount = U256::from(self.amounts[i]) * fair_supply
-                    / U256::from(self.shares_total_supply);
-                self.amounts[i] += amount.as_u128();
+                // Round up, so the pool is never short of what the minted shares are worth.
+                amounts[i] = mul_div_ceil(self.amounts[i], fair_supply, self.shares_total_supply);
+                self.amounts[i] += amounts[i];
             }
-            fair_supply.as_u128()
+            fair_supply
         } else {
             for i in 0..self.token_account_ids.len() {
                 self.amounts[i] += amounts[i];
@@ -89,11 +142,13 @@ impl SimplePool {
             INIT_SHARES_SUPPLY
         };
         self.shares_total_supply += shares;
+        self.checkpoint_share_supply();
         add_to_collection(&mut self.shares, &sender_id, shares);
         shares
     }
 
     /// Removes given number of shares from the pool and returns amounts to the parent.
+    #[cfg(feature = "pools")]
     pub fn remove_liquidity(
         &mut self,
         sender_id: &AccountId,
@@ -104,9 +159,7 @@ impl SimplePool {
         assert!(prev_shares_amount >= shares, "ERR_NOT_ENOUGH_SHARES");
         let mut result = vec![];
         for i in 0..self.token_account_ids.len() {
-            let amount = (U256::from(self.amounts[i]) * U256::from(shares)
-                / U256::from(self.shares_total_supply))
-            .as_u128();
+            let amount = mul_div(self.amounts[i], shares, self.shares_total_supply);
             assert!(amount >= min_amounts[i], "ERR_MIN_AMOUNT");
             self.amounts[i] -= amount;
             result.push(amount);
@@ -118,6 +171,7 @@ impl SimplePool {
                 .insert(&sender_id, &(prev_shares_amount - shares));
         }
         self.shares_total_supply -= shares;
+        self.checkpoint_share_supply();
         result
     }
 
@@ -193,7 +247,7 @@ impl SimplePool {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "pools"))]
 mod tests {
     use near_sdk::test_utils::{accounts, VMContextBuilder};
     use near_sdk::{testing_env, MockedBlockchain};
@@ -208,8 +262,26 @@ mod tests {
         testing_env!(context.build());
         let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30);
         let num_shares =
-            pool.add_liquidity(accounts(0).as_ref(), vec![5 * one_near, 10 * one_near]);
+            pool.add_liquidity(accounts(0).as_ref(), &mut vec![5 * one_near, 10 * one_near]);
         pool.swap(accounts(1).as_ref(), one_near, accounts(2).as_ref(), 1);
         pool.remove_liquidity(accounts(0).as_ref(), num_shares, vec![1, 1]);
     }
+
+    #[test]
+    fn test_share_supply_checkpoints() {
+        let one_near = 10u128.pow(24);
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0)).block_index(10);
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30);
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut vec![one_near, one_near]);
+        testing_env!(context.block_index(20).build());
+        pool.add_liquidity(accounts(0).as_ref(), &mut vec![one_near, one_near]);
+        pool.remove_liquidity(accounts(0).as_ref(), num_shares / 2, vec![1, 1]);
+        assert_eq!(pool.share_supply_at(9), 0);
+        assert_eq!(pool.share_supply_at(10), num_shares);
+        assert_eq!(pool.share_supply_at(15), num_shares);
+        assert_eq!(pool.share_supply_at(20), num_shares * 3 / 2);
+        assert_eq!(pool.share_supply_at(100), num_shares * 3 / 2);
+    }
 }
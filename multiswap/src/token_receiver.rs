@@ -1,3 +1,5 @@
+use std::convert::TryFrom;
+
 use crate::*;
 use near_sdk::PromiseOrValue;
 
@@ -13,8 +15,16 @@ impl FungibleTokenReceiver for Contract {
         msg: String,
     ) -> PromiseOrValue<U128> {
         let token_in = env::predecessor_account_id();
-        assert!(msg.is_empty(), "ERR_MSG_INCORRECT");
-        self.internal_deposit(sender_id.as_ref(), &token_in, amount.into());
+        // Empty message deposits to the sender, otherwise the message is the account to deposit to.
+        let account_id: AccountId = if msg.is_empty() {
+            sender_id.into()
+        } else {
+            ValidAccountId::try_from(msg)
+                .expect("ERR_MSG_INCORRECT")
+                .into()
+        };
+        let prev_amount = self.internal_get_deposit(&account_id, &token_in);
+        self.internal_deposit(&account_id, &token_in, prev_amount + amount.0);
         PromiseOrValue::Value(U128(0))
     }
 }
@@ -1,17 +1,17 @@
+#[cfg(feature = "pools")]
 use std::collections::HashSet;
 
+#[cfg(feature = "pools")]
 use near_sdk::collections::LookupMap;
-use near_sdk::json_types::{ValidAccountId, U128};
-use near_sdk::{ext_contract, AccountId, Balance, Gas};
-use uint::construct_uint;
+#[cfg(feature = "pools")]
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::json_types::U128;
+#[cfg(feature = "pools")]
+use near_sdk::Balance;
+use near_sdk::{ext_contract, AccountId, Gas};
 
 pub const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
 
-construct_uint! {
-    /// 256-bit unsigned integer.
-    pub struct U256(4);
-}
-
 /// TODO: this should be in the near_standard_contracts
 #[ext_contract(ext_fungible_token)]
 pub trait FungibleToken {
@@ -19,12 +19,14 @@ pub trait FungibleToken {
 }
 
 /// Adds given value to item stored in the given key in the LookupMap collection.
+#[cfg(feature = "pools")]
 pub fn add_to_collection(c: &mut LookupMap<AccountId, Balance>, key: &String, value: Balance) {
     let prev_value = c.get(key).unwrap_or(0);
     c.insert(key, &(prev_value + value));
 }
 
 /// Checks if there are any duplicates in the given list of tokens.
+#[cfg(feature = "pools")]
 pub fn check_token_duplicates(tokens: &[ValidAccountId]) {
     let token_set: HashSet<_> = tokens.iter().map(|a| AccountId::from(a.clone())).collect();
     assert_eq!(token_set.len(), tokens.len(), "ERR_TOKEN_DUPLICATES");
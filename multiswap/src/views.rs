@@ -2,10 +2,12 @@
 
 use std::collections::HashMap;
 
+use near_lib::simulation::SimulationResult;
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{near_bindgen, AccountId};
+use near_sdk::{near_bindgen, AccountId, BlockHeight};
 
+use crate::simple_pool::FEE_DIVISOR;
 use crate::*;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -19,16 +21,20 @@ pub struct PoolInfo {
     pub fee: u32,
     /// Total number of shares.
     pub shares_total_supply: U128,
+    /// Lifecycle stage of the pool.
+    pub status: PoolStatus,
 }
 
 impl From<Pool> for PoolInfo {
     fn from(pool: Pool) -> Self {
+        let status = pool.status();
         match pool {
             Pool::SimplePool(pool) => Self {
                 token_account_ids: pool.token_account_ids,
                 amounts: pool.amounts.into_iter().map(|a| U128(a)).collect(),
                 fee: pool.fee,
                 shares_total_supply: U128(pool.shares_total_supply),
+                status,
             },
         }
     }
@@ -44,31 +50,39 @@ impl Contract {
     /// Returns list of pools of given length from given start index.
     pub fn get_pools(&self, from_index: u64, limit: u64) -> Vec<PoolInfo> {
         (from_index..std::cmp::min(from_index + limit, self.pools.len()))
-            .map(|index| self.get_pool(index))
+            .filter_map(|index| self.get_pool(index))
             .collect()
     }
 
-    /// Returns information about specified pool.
-    pub fn get_pool(&self, pool_id: u64) -> PoolInfo {
-        self.pools.get(pool_id).expect("ERR_NO_POOL").into()
+    /// Returns information about specified pool or None if there is no such pool.
+    pub fn get_pool(&self, pool_id: u64) -> Option<PoolInfo> {
+        self.pools.get(pool_id).map(|pool| pool.into())
+    }
+
+    /// Returns lifecycle stage of the given pool or None if there is no such pool.
+    pub fn get_pool_status(&self, pool_id: u64) -> Option<PoolStatus> {
+        self.pools.get(pool_id).map(|pool| pool.status())
     }
 
     /// Returns number of shares given account has in given pool.
-    pub fn get_pool_shares(&self, pool_id: u64, account_id: ValidAccountId) -> U128 {
+    pub fn get_pool_shares(&self, pool_id: u64, account_id: ValidAccountId) -> Option<U128> {
         self.pools
             .get(pool_id)
-            .expect("ERR_NO_POOL")
-            .share_balances(account_id.as_ref())
-            .into()
+            .map(|pool| pool.share_balances(account_id.as_ref()).into())
     }
 
     /// Returns total number of shares in the given pool.
-    pub fn get_pool_total_shares(&self, pool_id: u64) -> U128 {
+    pub fn get_pool_total_shares(&self, pool_id: u64) -> Option<U128> {
+        self.pools
+            .get(pool_id)
+            .map(|pool| pool.share_total_balance().into())
+    }
+
+    /// Returns total number of shares in the given pool at the end of given block.
+    pub fn get_share_supply_at(&self, pool_id: u64, block_height: BlockHeight) -> Option<U128> {
         self.pools
             .get(pool_id)
-            .expect("ERR_NO_POOL")
-            .share_total_balance()
-            .into()
+            .map(|pool| pool.share_supply_at(block_height).into())
     }
 
     /// Returns balances of the deposits for given user outside of any pools.
@@ -84,16 +98,68 @@ impl Contract {
         self.internal_get_deposit(account_id, token_id).into()
     }
 
+    /// Returns account that receives swap donations, if any.
+    pub fn get_public_goods_account(&self) -> Option<AccountId> {
+        self.public_goods_account_id.clone()
+    }
+
+    /// Returns cumulative donations made by given account per token.
+    pub fn get_donations(&self, account_id: ValidAccountId) -> HashMap<AccountId, U128> {
+        self.donations
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(token_id, amount)| (token_id, U128(amount)))
+            .collect()
+    }
+
     /// Given specific pool, returns amount of token_out recevied swapping amount_in of token_in.
+    /// Returns None if there is no such pool or it has no liquidity.
     pub fn get_return(
         &self,
         pool_id: u64,
         token_in: ValidAccountId,
         amount_in: U128,
         token_out: ValidAccountId,
-    ) -> U128 {
-        let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
-        pool.get_return(token_in.as_ref(), amount_in.into(), token_out.as_ref())
-            .into()
+    ) -> Option<U128> {
+        let pool = self.pools.get(pool_id)?;
+        if pool.status() != PoolStatus::Active {
+            return None;
+        }
+        Some(
+            pool.get_return(token_in.as_ref(), amount_in.into(), token_out.as_ref())
+                .into(),
+        )
+    }
+
+    /// Simulates `swap` with given actions without changing state.
+    /// Returns the final amount out and fee charged on each step in the token_in of that step.
+    /// Pools used more than once see reserves changed by the previous steps, same as in `swap`.
+    pub fn simulate_swap(&self, actions: Vec<SwapAction>) -> SimulationResult {
+        near_lib::simulate!({
+            let mut pools: HashMap<u64, Pool> = HashMap::new();
+            let mut fees = vec![];
+            let mut prev_amount: Option<Balance> = None;
+            for action in actions {
+                let amount_in = action
+                    .amount_in
+                    .map(|amount| amount.into())
+                    .unwrap_or_else(|| prev_amount.expect("ERR_FIRST_SWAP_MISSING_AMOUNT"));
+                let pool = pools
+                    .entry(action.pool_id)
+                    .or_insert_with(|| self.pools.get(action.pool_id).expect("ERR_NO_POOL"));
+                fees.push(mul_div(amount_in, pool.get_fee() as u128, FEE_DIVISOR as u128).into());
+                prev_amount = Some(pool.swap(
+                    action.token_in.as_ref(),
+                    amount_in,
+                    action.token_out.as_ref(),
+                    action.min_amount_out.into(),
+                ));
+            }
+            SimulationResult {
+                amount_out: prev_amount.expect("ERR_NO_ACTIONS").into(),
+                fees,
+            }
+        })
     }
 }
@@ -0,0 +1,200 @@
+//! Tests of the contract built with each combination of the `pools` and `governance` features.
+//! Run with the commands listed in the README.
+
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::U128;
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::{testing_env, MockedBlockchain};
+
+use multiswap::{Contract, InternalTransfer};
+
+/// Contract owned by `accounts(0)` with `accounts(3)` and `accounts(4)` registered
+/// and `accounts(3)` holding 100 of `accounts(1)` and `accounts(2)` tokens.
+fn setup_contract() -> (VMContextBuilder, Contract) {
+    let mut context = VMContextBuilder::new();
+    testing_env!(context.build());
+    let mut contract = Contract::new(accounts(0));
+    for account_id in [accounts(3), accounts(4)].iter() {
+        testing_env!(context
+            .predecessor_account_id(account_id.clone())
+            .attached_deposit(contract.storage_balance_bounds().min.0)
+            .build());
+        contract.storage_deposit(None, None);
+    }
+    for token_id in [accounts(1), accounts(2)].iter() {
+        testing_env!(context
+            .predecessor_account_id(token_id.clone())
+            .attached_deposit(0)
+            .build());
+        contract.ft_on_transfer(accounts(3), U128(100), "".to_string());
+    }
+    (context, contract)
+}
+
+/// Deposits, transfers and views are part of every build.
+mod ledger {
+    use super::*;
+
+    #[test]
+    fn test_deposit_batch() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.deposit_batch(vec![InternalTransfer {
+            token_id: accounts(1),
+            receiver_id: accounts(4),
+            amount: U128(40),
+        }]);
+        assert_eq!(
+            contract.get_deposit(accounts(3).as_ref(), accounts(1).as_ref()),
+            U128(60)
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(4).as_ref(), accounts(1).as_ref()),
+            U128(40)
+        );
+    }
+
+    #[test]
+    fn test_no_pools() {
+        let (_, contract) = setup_contract();
+        assert_eq!(contract.get_number_of_pools(), 0);
+        assert!(contract.get_pool(0).is_none());
+    }
+}
+
+/// Router-only build can only swap in pools created by a full build, see README.
+#[cfg(not(any(feature = "pools", feature = "governance")))]
+mod router_only {
+    use super::*;
+    use multiswap::SwapAction;
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_POOL")]
+    fn test_swap_no_pool() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.swap(
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: accounts(1),
+                amount_in: Some(U128(10)),
+                token_out: accounts(2),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+    }
+}
+
+#[cfg(feature = "pools")]
+mod pools {
+    use super::*;
+    use multiswap::{PoolStatus, SwapAction};
+    use near_sdk::env;
+
+    /// Adds pool of `accounts(1)` and `accounts(2)` with liquidity of `accounts(3)`.
+    pub fn add_pool(context: &mut VMContextBuilder, contract: &mut Contract) {
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(env::storage_byte_cost() * 300)
+            .build());
+        contract.add_simple_pool(vec![accounts(1), accounts(2)], 30);
+        testing_env!(context.attached_deposit(0).build());
+        contract.add_liquidity(0, vec![U128(50), U128(50)]);
+    }
+
+    #[test]
+    fn test_liquidity_and_swap() {
+        let (mut context, mut contract) = setup_contract();
+        add_pool(&mut context, &mut contract);
+        assert_eq!(contract.get_pool_status(0), Some(PoolStatus::Active));
+        let amount_out = contract.swap(
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: accounts(1),
+                amount_in: Some(U128(10)),
+                token_out: accounts(2),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(3).as_ref(), accounts(2).as_ref()),
+            U128(50 + amount_out.0)
+        );
+        let shares = contract.get_pool_shares(0, accounts(3)).unwrap();
+        contract.remove_liquidity(0, shares, vec![U128(1), U128(1)]);
+        assert_eq!(contract.get_pool_status(0), Some(PoolStatus::Removed));
+    }
+}
+
+#[cfg(feature = "governance")]
+mod governance {
+    use super::*;
+
+    #[test]
+    fn test_set_public_goods_account() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_public_goods_account(Some(accounts(4)));
+        assert_eq!(
+            contract.get_public_goods_account(),
+            Some(accounts(4).into())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ALLOWED")]
+    fn test_set_public_goods_account_not_owner() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.set_public_goods_account(Some(accounts(4)));
+    }
+}
+
+#[cfg(all(feature = "pools", feature = "governance"))]
+mod pools_governance {
+    use super::*;
+    use multiswap::{AlertThresholds, SwapAction};
+
+    #[test]
+    fn test_swap_with_donation() {
+        let (mut context, mut contract) = setup_contract();
+        pools::add_pool(&mut context, &mut contract);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_public_goods_account(Some(accounts(4)));
+        contract.set_alert_thresholds(
+            0,
+            Some(AlertThresholds {
+                reserve_drop_bps: None,
+                price_move_bps: None,
+                trade_size_bps: Some(100),
+            }),
+        );
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        let amount_out = contract.swap(
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: accounts(1),
+                amount_in: Some(U128(10)),
+                token_out: accounts(2),
+                min_amount_out: U128(1),
+            }],
+            Some(5_000),
+        );
+        let donated = contract.get_deposit(accounts(4).as_ref(), accounts(2).as_ref());
+        assert!(donated.0 > 0);
+        assert_eq!(
+            contract.get_donations(accounts(3))[accounts(2).as_ref()],
+            donated
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(3).as_ref(), accounts(2).as_ref()),
+            U128(50 + amount_out.0)
+        );
+    }
+}
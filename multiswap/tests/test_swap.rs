@@ -1,10 +1,13 @@
+// Simulation of the full build in `res/multiswap.wasm`, which creates the pools itself.
+#![cfg(feature = "pools")]
+
 use std::convert::TryFrom;
 
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::AccountId;
 use near_sdk_sim::{call, deploy, init_simulator, to_yocto, view, ContractAccount, UserAccount};
 
-use multiswap::{ContractContract as Multiswap, PoolInfo, SwapAction};
+use multiswap::{ContractContract as Multiswap, PoolInfo, PoolStatus, SwapAction};
 use std::collections::HashMap;
 use test_token::ContractContract as TestToken;
 
@@ -20,7 +23,7 @@ fn test_token(root: &UserAccount, token_id: AccountId) -> ContractAccount<TestTo
         bytes: &TEST_TOKEN_WASM_BYTES,
         signer_account: root
     );
-    call!(root, t.new()).assert_success();
+    call!(root, t.new(None)).assert_success();
     call!(
         root,
         t.mint(to_va(root.account_id.clone()), to_yocto("1000").into())
@@ -56,7 +59,7 @@ fn test_swap() {
         bytes: &MUTLISWAP_WASM_BYTES,
         signer_account: root
     );
-    call!(root, pool.new());
+    call!(root, pool.new(to_va(root.account_id.clone())));
     call!(
         root,
         pool.add_simple_pool(vec![to_va(dai()), to_va(eth())], 30),
@@ -101,13 +104,14 @@ fn test_swap() {
     )
     .assert_success();
     assert_eq!(
-        view!(pool.get_pool(0)).unwrap_json::<PoolInfo>(),
-        PoolInfo {
+        view!(pool.get_pool(0)).unwrap_json::<Option<PoolInfo>>(),
+        Some(PoolInfo {
             token_account_ids: vec![dai(), eth()],
             amounts: vec![to_yocto("5").into(), to_yocto("10").into()],
             fee: 30,
             shares_total_supply: to_yocto("1").into(),
-        }
+            status: PoolStatus::Active,
+        })
     );
     let balances =
         view!(pool.get_deposits(&root.account_id)).unwrap_json::<HashMap<AccountId, U128>>();
@@ -116,13 +120,16 @@ fn test_swap() {
 
     call!(
         root,
-        pool.swap(vec![SwapAction {
-            pool_id: 0,
-            token_in: to_va(dai()),
-            amount_in: Some(U128(to_yocto("1"))),
-            token_out: to_va(eth()),
-            min_amount_out: U128(1)
-        }])
+        pool.swap(
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: to_va(dai()),
+                amount_in: Some(U128(to_yocto("1"))),
+                token_out: to_va(eth()),
+                min_amount_out: U128(1)
+            }],
+            None
+        )
     )
     .assert_success();
 
@@ -0,0 +1,48 @@
+use near_sdk::AccountId;
+
+/// Minimum length of the account id.
+pub const MIN_ACCOUNT_ID_LEN: usize = 2;
+
+/// Maximum length of the account id.
+pub const MAX_ACCOUNT_ID_LEN: usize = 64;
+
+/// Returns true if given string is a valid single part of the account id, i.e. a name without dots.
+/// Name consists of lowercase letters and digits, separated by single `-` or `_`.
+pub fn is_valid_sub_account_name(name: &str) -> bool {
+    let mut last_is_separator = true;
+    for c in name.chars() {
+        match c {
+            'a'..='z' | '0'..='9' => last_is_separator = false,
+            '-' | '_' if !last_is_separator => last_is_separator = true,
+            _ => return false,
+        }
+    }
+    !last_is_separator
+}
+
+/// Returns true if given string is a valid account id.
+pub fn is_valid_account_id(account_id: &str) -> bool {
+    account_id.len() >= MIN_ACCOUNT_ID_LEN
+        && account_id.len() <= MAX_ACCOUNT_ID_LEN
+        && account_id.split('.').all(is_valid_sub_account_name)
+}
+
+/// Returns account id of the direct sub-account `name` of `parent`.
+/// Panics if the name is not valid or the resulting account id is too long.
+pub fn full_sub_account(parent: &str, name: &str) -> AccountId {
+    assert!(is_valid_sub_account_name(name), "Invalid sub-account name");
+    let account_id = format!("{}.{}", name, parent);
+    assert!(
+        account_id.len() <= MAX_ACCOUNT_ID_LEN,
+        "Sub-account id is too long"
+    );
+    account_id
+}
+
+/// Returns true if `account_id` is a direct sub-account of `parent`.
+pub fn is_direct_sub_account(parent: &str, account_id: &str) -> bool {
+    account_id.len() > parent.len() + 1
+        && account_id.ends_with(parent)
+        && account_id[..account_id.len() - parent.len()].ends_with('.')
+        && is_valid_sub_account_name(&account_id[..account_id.len() - parent.len() - 1])
+}
@@ -93,6 +93,7 @@ pub fn testing_env_with_promise_results(context: VMContext, promise_result: Prom
         vec![promise_result],
         storage,
         Default::default(),
+        None,
     )));
 }
 
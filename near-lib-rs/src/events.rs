@@ -0,0 +1,33 @@
+//! Structured event logs following NEP-297: `EVENT_JSON:{"standard": .., "version": .., "event": .., "data": ..}`.
+
+use near_sdk::serde::Serialize;
+use near_sdk::{env, serde_json};
+
+/// Event of given standard. Implementor serializes into the rest of the log fields,
+/// usually as `#[serde(tag = "event", content = "data")]` enum.
+pub trait NearEvent: Serialize {
+    const STANDARD: &'static str;
+    const VERSION: &'static str;
+
+    /// Logs the event as `EVENT_JSON:{...}`.
+    fn emit(&self)
+    where
+        Self: Sized,
+    {
+        let log = EventLog {
+            standard: Self::STANDARD,
+            version: Self::VERSION,
+            event: self,
+        };
+        env::log(format!("EVENT_JSON:{}", serde_json::to_string(&log).unwrap()).as_bytes());
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a, T: Serialize> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a T,
+}
@@ -1,5 +1,9 @@
+pub mod account;
 pub mod context;
+pub mod events;
+pub mod math;
 pub mod promises;
+pub mod simulation;
 pub mod token;
 pub mod types;
-pub mod upgrade;
\ No newline at end of file
+pub mod upgrade;
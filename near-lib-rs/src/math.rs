@@ -0,0 +1,136 @@
+//! Wide integer and fixed-point arithmetic, shared by the exchanges so that they round the same way.
+
+use std::ops::{Div, Mul};
+
+use uint::construct_uint;
+
+construct_uint! {
+    /// 256-bit unsigned integer.
+    pub struct U256(4);
+}
+
+/// Returns `a * b / c` rounded down, computing the product in 256 bits.
+/// Panics if `c` is zero or the result doesn't fit into `u128`.
+pub fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (U256::from(a) * U256::from(b) / U256::from(c)).as_u128()
+}
+
+/// Returns `a * b / c` rounded up, computing the product in 256 bits.
+/// Panics if `c` is zero or the result doesn't fit into `u128`.
+pub fn mul_div_ceil(a: u128, b: u128, c: u128) -> u128 {
+    let (result, remainder) = (U256::from(a) * U256::from(b)).div_mod(U256::from(c));
+    if remainder.is_zero() {
+        result.as_u128()
+    } else {
+        (result + 1).as_u128()
+    }
+}
+
+/// Unsigned fixed-point number with 64 integer and 64 fractional bits.
+/// All operations round down.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Q64x64(pub u128);
+
+const Q64X64_ONE: u128 = 1 << 64;
+
+impl Q64x64 {
+    pub const ONE: Q64x64 = Q64x64(Q64X64_ONE);
+
+    pub fn from_int(value: u64) -> Self {
+        Q64x64((value as u128) << 64)
+    }
+
+    /// Panics if the ratio is not less than 2^64.
+    pub fn from_ratio(numerator: u128, denominator: u128) -> Self {
+        Q64x64(mul_div(numerator, Q64X64_ONE, denominator))
+    }
+
+    /// Returns `value` multiplied by this number.
+    pub fn mul_int(self, value: u128) -> u128 {
+        mul_div(value, self.0, Q64X64_ONE)
+    }
+
+    /// Integer part of the number.
+    pub fn floor(self) -> u64 {
+        (self.0 >> 64) as u64
+    }
+}
+
+impl Mul for Q64x64 {
+    type Output = Self;
+
+    fn mul(self, other: Q64x64) -> Self {
+        Q64x64(mul_div(self.0, other.0, Q64X64_ONE))
+    }
+}
+
+impl Div for Q64x64 {
+    type Output = Self;
+
+    fn div(self, other: Q64x64) -> Self {
+        Q64x64(mul_div(self.0, Q64X64_ONE, other.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul_div_rounding() {
+        assert_eq!(mul_div(10, 10, 3), 33);
+        assert_eq!(mul_div_ceil(10, 10, 3), 34);
+        assert_eq!(mul_div(10, 9, 3), 30);
+        assert_eq!(mul_div_ceil(10, 9, 3), 30);
+        assert_eq!(mul_div(1, 1, 2), 0);
+        assert_eq!(mul_div_ceil(1, 1, 2), 1);
+        assert_eq!(mul_div_ceil(0, 5, 7), 0);
+    }
+
+    #[test]
+    fn test_mul_div_wide_product() {
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), u128::MAX);
+        assert_eq!(mul_div_ceil(u128::MAX, u128::MAX, u128::MAX), u128::MAX);
+        assert_eq!(mul_div(u128::MAX, 2, 4), u128::MAX / 2);
+        assert_eq!(mul_div_ceil(u128::MAX, 2, 4), u128::MAX / 2 + 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mul_div_overflow() {
+        mul_div(u128::MAX, 2, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mul_div_ceil_overflow() {
+        mul_div_ceil(u128::MAX, u128::MAX, u128::MAX - 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mul_div_zero_divisor() {
+        mul_div(1, 1, 0);
+    }
+
+    #[test]
+    fn test_q64x64() {
+        let half = Q64x64::from_ratio(1, 2);
+        assert_eq!(half * Q64x64::from_int(4), Q64x64::from_int(2));
+        assert_eq!(
+            Q64x64::from_int(3) / Q64x64::from_int(2),
+            Q64x64::from_ratio(3, 2)
+        );
+        assert_eq!(Q64x64::from_ratio(3, 2).floor(), 1);
+        assert_eq!(Q64x64::ONE.mul_int(u128::MAX), u128::MAX);
+        // Rounds down.
+        assert_eq!(Q64x64::from_ratio(1, 3).mul_int(3), 0);
+        assert_eq!(Q64x64::from_ratio(2, 3).mul_int(300), 199);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_q64x64_from_ratio_overflow() {
+        Q64x64::from_ratio(1 << 64, 1);
+    }
+}
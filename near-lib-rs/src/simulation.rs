@@ -0,0 +1,42 @@
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::types::WrappedBalance;
+
+/// Outcome of a `simulate_*` view: what the mutating twin would return if called with the same arguments
+/// against the current state.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SimulationResult {
+    /// Amount the mutating method would return.
+    pub amount_out: WrappedBalance,
+    /// Fees that would be charged, one per step in the order they are charged.
+    pub fees: Vec<WrappedBalance>,
+}
+
+/// Runs given block as a simulation: all changes must be done on in-memory copies of the state.
+/// The block usually evaluates to a `SimulationResult`, but any return type is accepted.
+/// Panics if the block wrote anything to the storage, which would fail in a view call anyway,
+/// but only for the inputs that reach the write.
+///
+/// ```ignore
+/// pub fn simulate_swap(&self, amount_in: U128) -> SimulationResult {
+///     near_lib::simulate!({
+///         let mut pool = self.pool.clone();
+///         let amount_out = pool.swap(amount_in.into());
+///         SimulationResult { amount_out: amount_out.into(), fees: vec![] }
+///     })
+/// }
+/// ```
+#[macro_export]
+macro_rules! simulate {
+    ($body:block) => {{
+        let storage_usage = near_sdk::env::storage_usage();
+        let result = $body;
+        assert_eq!(
+            storage_usage,
+            near_sdk::env::storage_usage(),
+            "ERR_SIMULATION_CHANGED_STATE"
+        );
+        result
+    }};
+}
@@ -0,0 +1,14 @@
+//! Input generators for the randomized tests.
+
+use rand::Rng;
+
+/// Random value in `[min, max)` with uniformly distributed number of digits,
+/// so that small and large magnitudes are equally covered.
+pub fn gen_amount<R: Rng>(rng: &mut R, min: u128, max: u128) -> u128 {
+    let min_digits = (min as f64).log10().floor() as u32;
+    let max_digits = (max as f64).log10().ceil() as u32;
+    let digits = rng.gen_range(min_digits, max_digits + 1);
+    let upper = std::cmp::min(max, 10u128.saturating_pow(digits));
+    let lower = std::cmp::max(min, upper / 10);
+    rng.gen_range(lower, std::cmp::max(upper, lower + 1))
+}
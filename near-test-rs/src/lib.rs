@@ -0,0 +1,3 @@
+//! Helpers shared by the tests of the contracts in this repository.
+
+pub mod fuzz;
@@ -4,8 +4,7 @@ use near_sdk::{env, near_bindgen, AccountId};
 
 use near_lib::token::{FungibleToken, Token};
 
-#[global_allocator]
-static ALLOC: near_sdk::wee_alloc::WeeAlloc<'_> = near_sdk::wee_alloc::WeeAlloc::INIT;
+near_sdk::setup_alloc!();
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize)]
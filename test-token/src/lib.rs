@@ -1,6 +1,8 @@
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
 use near_contract_standards::fungible_token::metadata::{
     FungibleTokenMetadata, FungibleTokenMetadataProvider,
 };
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
 use near_contract_standards::fungible_token::FungibleToken;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::json_types::{ValidAccountId, U128};
@@ -12,20 +14,24 @@ near_sdk::setup_alloc!();
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 pub struct Contract {
     token: FungibleToken,
+    /// If set, accounts are registered automatically on first credit instead of requiring `storage_deposit`.
+    /// Meant for simulation tests with many synthetic accounts.
+    storage_free: bool,
 }
 
 #[near_bindgen]
 impl Contract {
     #[init]
-    pub fn new() -> Self {
+    pub fn new(storage_free: Option<bool>) -> Self {
         assert!(!env::state_exists(), "ERR_CONTRACT_IS_INITIALIZED");
         Self {
             token: FungibleToken::new(b"t".to_vec()),
+            storage_free: storage_free.unwrap_or(false),
         }
     }
 
     pub fn mint(&mut self, account_id: ValidAccountId, amount: U128) {
-        self.token.internal_register_account(account_id.as_ref());
+        self.internal_register_if_missing(account_id.as_ref());
         self.token
             .internal_deposit(account_id.as_ref(), amount.into());
     }
@@ -36,7 +42,64 @@ impl Contract {
     }
 }
 
-near_contract_standards::impl_fungible_token_core!(Contract, token);
+impl Contract {
+    fn internal_register_if_missing(&mut self, account_id: &AccountId) {
+        if !self.token.accounts.contains_key(account_id) {
+            self.token.internal_register_account(account_id);
+        }
+    }
+
+    /// Registers receiver of the transfer if running in storage free mode.
+    fn internal_auto_register(&mut self, account_id: &AccountId) {
+        if self.storage_free {
+            self.internal_register_if_missing(account_id);
+        }
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenCore for Contract {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        self.internal_auto_register(receiver_id.as_ref());
+        self.token.ft_transfer(receiver_id, amount, memo)
+    }
+
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.internal_auto_register(receiver_id.as_ref());
+        self.token.ft_transfer_call(receiver_id, amount, memo, msg)
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
+    }
+
+    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.token.ft_balance_of(account_id)
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenResolver for Contract {
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        receiver_id: ValidAccountId,
+        amount: U128,
+    ) -> U128 {
+        self.token
+            .ft_resolve_transfer(sender_id, receiver_id, amount)
+    }
+}
+
 near_contract_standards::impl_fungible_token_storage!(Contract, token);
 
 #[near_bindgen]
@@ -57,7 +120,7 @@ mod tests {
     fn test_basics() {
         let mut context = VMContextBuilder::new();
         testing_env!(context.build());
-        let mut contract = Contract::new();
+        let mut contract = Contract::new(None);
         testing_env!(context
             .attached_deposit(125 * env::storage_byte_cost())
             .build());
@@ -78,4 +141,21 @@ mod tests {
         contract.burn(accounts(1), 500.into());
         assert_eq!(contract.ft_balance_of(accounts(1)), 500.into());
     }
+
+    #[test]
+    fn test_storage_free() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.build());
+        let mut contract = Contract::new(Some(true));
+        contract.mint(accounts(0), 1_000_000.into());
+        contract.mint(accounts(0), 1_000_000.into());
+        assert_eq!(contract.ft_balance_of(accounts(0)), 2_000_000.into());
+
+        testing_env!(context
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.ft_transfer(accounts(1), 1_000.into(), None);
+        assert_eq!(contract.ft_balance_of(accounts(1)), 1_000.into());
+    }
 }
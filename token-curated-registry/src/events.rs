@@ -0,0 +1,59 @@
+//! Structured event logs following NEP-297, so UIs and bots can react to the registry changes.
+
+use near_lib::events::NearEvent;
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::AccountId;
+
+use crate::Vote;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub(crate) enum Event<'a> {
+    Insert {
+        table_name: &'a str,
+        id: u64,
+        owner: &'a AccountId,
+    },
+    Update {
+        table_name: &'a str,
+        id: u64,
+        owner: &'a AccountId,
+    },
+    Remove {
+        table_name: &'a str,
+        id: u64,
+        owner: &'a AccountId,
+    },
+    SetRowOwner {
+        table_name: &'a str,
+        id: u64,
+        old_owner: &'a AccountId,
+        new_owner: &'a AccountId,
+    },
+    Challenge {
+        table_name: &'a str,
+        challenge_id: u64,
+        row_id: u64,
+        challenger: &'a AccountId,
+    },
+    ChallengeVote {
+        table_name: &'a str,
+        challenge_id: u64,
+        account_id: &'a AccountId,
+        vote: &'a Vote,
+        amount: U128,
+    },
+    FinalizeChallenge {
+        table_name: &'a str,
+        challenge_id: u64,
+        row_id: u64,
+        outcome: &'a Vote,
+    },
+}
+
+impl NearEvent for Event<'_> {
+    const STANDARD: &'static str = "tcr";
+    const VERSION: &'static str = "1.0.0";
+}
@@ -1,30 +1,73 @@
 use std::collections::HashMap;
 
-use near_lib::token::{FungibleToken, Token};
-use near_lib::types::{Duration, Timestamp, WrappedDuration};
-use near_lib::upgrade::{Upgradable, Upgrade};
-use near_sdk::{AccountId, Balance, env, Promise, near_bindgen, init};
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
+use near_contract_standards::fungible_token::metadata::{FungibleTokenMetadata, FungibleTokenMetadataProvider};
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
+use near_contract_standards::fungible_token::FungibleToken;
+use near_contract_standards::storage_management::{StorageBalance, StorageBalanceBounds, StorageManagement};
+use near_lib::events::NearEvent;
+use near_lib::math::mul_div;
+use near_sdk::{AccountId, Balance, env, Gas, Promise, PromiseOrValue, near_bindgen, PanicOnDefault};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
-use near_sdk::json_types::{Base64VecU8, U128};
+use near_sdk::collections::{LazyOption, LookupMap, UnorderedMap};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
 
-#[global_allocator]
-static ALLOC: near_sdk::wee_alloc::WeeAlloc<'_> = near_sdk::wee_alloc::WeeAlloc::INIT;
+use crate::events::Event;
+use crate::parameterizer::{Params, ParamsProposal};
+use crate::upgrade::Upgrade;
+
+mod events;
+mod parameterizer;
+mod upgrade;
+
+near_sdk::setup_alloc!();
+
+/// Raw type for duration in nanoseconds.
+type Duration = u64;
+
+/// Raw type for timestamp in nanoseconds.
+type Timestamp = u64;
 
 /// Upgrade duration is 1 day.
 const UPGRADE_STAGING_DURATION: Duration = 24 * 60 * 60 * 1_000_000_000;
 
-/// Challenge duration.
+/// Initial challenge duration.
 const CHALLENGE_DURATION: Duration = 5 * 24 * 60 * 60 * 1_000_000_000;
 
 /// Initial $TCR supply.
 const INITIAL_SUPPLY: Balance = 1_000_000_000_000_000_000_000_000;
 
+/// Initial $TCR locked by the owner of the row while it's listed.
+const LISTING_DEPOSIT: Balance = 1_000_000_000_000_000_000_000;
+
+/// Initial $TCR locked by the challenger until the challenge is finalized.
+const CHALLENGE_DEPOSIT: Balance = 1_000_000_000_000_000_000_000;
+
+/// Initial percentage of the voted $TCR that must be for the change to pass.
+const VOTE_QUORUM_PCT: Balance = 50;
+
+/// Initial percentage of the forfeited deposit that goes to the winner of the challenge.
+const DISPENSATION_PCT: Balance = 50;
+
+/// Price per 1 byte of storage, charged to the callers of the state changing functions.
+const STORAGE_PRICE_PER_BYTE: Balance = 10_000_000_000_000_000_000;
+
+/// Number of previous versions of the row kept in its history.
+const MAX_ROW_HISTORY: usize = 10;
+
+const NO_DEPOSIT: Balance = 0;
+
+/// Gas for `migrate` of the deployed code.
+const MIGRATE_GAS: Gas = 50_000_000_000_000;
+
 /// Keeps track how much NEAR this contract has received.
 /// Accounts for storage usage and contract rewards.
 #[derive(BorshSerialize, BorshDeserialize)]
 struct Bank {
     balance: Balance,
+    /// Storage usage at the start of the current call.
+    /// Only the difference within the call is charged, as writing the state itself happens after the call.
     storage_usage: u64,
 }
 
@@ -38,279 +81,1303 @@ impl Bank {
 
     /// Called at the start of the function at the state changing function.
     pub fn start_record(&mut self) {
-        assert_eq!(self.storage_usage, env::storage_usage(), "Incorrect usage of Bank: was not called when storaged changed");
+        self.storage_usage = env::storage_usage();
     }
 
     /// Called at the end of the function at the state changing function.
+    /// Charges the storage added by the function to the attached deposit and refunds the rest of it
+    /// to the caller, together with the cost of the storage released by the function.
     pub fn end_record(&mut self) {
-        self.storage_usage = env::storage_usage();
+        let storage_usage = env::storage_usage();
+        let attached_deposit = env::attached_deposit();
+        let refund = if storage_usage >= self.storage_usage {
+            let storage_cost = (storage_usage - self.storage_usage) as Balance * STORAGE_PRICE_PER_BYTE;
+            assert!(attached_deposit >= storage_cost, "Not enough deposit to cover storage");
+            self.balance += storage_cost;
+            attached_deposit - storage_cost
+        } else {
+            let storage_refund = (self.storage_usage - storage_usage) as Balance * STORAGE_PRICE_PER_BYTE;
+            self.balance -= storage_refund;
+            attached_deposit + storage_refund
+        };
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
     }
 }
 
-#[derive(BorshSerialize, BorshDeserialize)]
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
 struct Row {
     owner: AccountId,
     fields: HashMap<String, String>,
+    /// $TCR locked for this row, returned to the owner of the row.
+    deposit: Balance,
+    /// Currently open challenge of this row.
+    challenge_id: Option<u64>,
+    /// When the row expires, if it was listed with a TTL.
+    expires_at: Option<Timestamp>,
+    /// Version of the fields, incremented on every update.
+    version: u64,
+    /// When the fields were last set.
+    updated_at: Timestamp,
+}
+
+impl Row {
+    fn is_expired(&self) -> bool {
+        self.expires_at.map(|expires_at| expires_at <= env::block_timestamp()).unwrap_or(false)
+    }
+}
+
+/// Previous version of the fields of the row.
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RowVersion {
+    version: u64,
+    fields: HashMap<String, String>,
+    /// Owner of the row who set the fields.
+    editor: AccountId,
+    /// When the fields were set.
+    timestamp: Timestamp,
+}
+
+/// Row to import with its original owner.
+#[derive(BorshSerialize, BorshDeserialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RowInput {
+    owner: AccountId,
+    fields: HashMap<String, String>,
+}
+
+/// Fields of the rows of a table, enforced on insert and update.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(default)]
+struct Schema {
+    /// Allowed field names, any fields are allowed if empty.
+    fields: Vec<String>,
+    /// Fields that every row must have.
+    required: Vec<String>,
+    /// Max length of any value in bytes.
+    max_value_len: Option<u32>,
+}
+
+impl Schema {
+    pub fn assert_consistent(&self) {
+        if self.fields.is_empty() {
+            return;
+        }
+        for field in self.required.iter() {
+            assert!(self.fields.contains(field), "Required field {} is not allowed", field);
+        }
+    }
+
+    pub fn assert_valid(&self, fields: &HashMap<String, String>) {
+        for field in self.required.iter() {
+            assert!(fields.contains_key(field), "Missing field {}", field);
+        }
+        for (field, value) in fields.iter() {
+            assert!(self.fields.is_empty() || self.fields.contains(field), "Unknown field {}", field);
+            if let Some(max_value_len) = self.max_value_len {
+                assert!(value.len() <= max_value_len as usize, "Value of field {} is too long", field);
+            }
+        }
+    }
 }
 
+/// Registry with its own rows, challenges and parameters.
 #[derive(BorshSerialize, BorshDeserialize)]
 struct Table {
+    schema: Schema,
+    /// Parameters, governed by $TCR holders.
+    params: Params,
     last_id: u64,
     rows: UnorderedMap<u64, Row>,
+    /// (field name, value) -> ids of the rows with this value, sorted.
+    index: LookupMap<(String, String), Vec<u64>>,
+    last_challenge_id: u64,
+    /// Open challenges.
+    challenges: UnorderedMap<u64, Challenge>,
+    /// Finalized challenges, kept for voters to claim rewards.
+    finalized_challenges: LookupMap<u64, Challenge>,
+    /// Row id -> previous versions of the row, oldest first.
+    history: LookupMap<u64, Vec<RowVersion>>,
+    /// Owner -> ids of the rows owned, sorted.
+    owner_index: LookupMap<AccountId, Vec<u64>>,
 }
 
 impl Table {
-    pub fn new() -> Self {
+    /// Storage keys of the table's collections start with `prefix`.
+    pub fn new(prefix: String, schema: Schema, params: Params) -> Self {
         Self {
+            schema,
+            params,
             last_id: 0,
-            rows: UnorderedMap::new(b"t".to_vec()),
+            rows: UnorderedMap::new(format!("{}t", prefix).into_bytes()),
+            index: LookupMap::new(format!("{}i", prefix).into_bytes()),
+            last_challenge_id: 0,
+            challenges: UnorderedMap::new(format!("{}c", prefix).into_bytes()),
+            finalized_challenges: LookupMap::new(format!("{}f", prefix).into_bytes()),
+            history: LookupMap::new(format!("{}h", prefix).into_bytes()),
+            owner_index: LookupMap::new(format!("{}o", prefix).into_bytes()),
         }
     }
 
     pub fn insert(&mut self, row: Row) -> u64 {
         self.rows.insert(&self.last_id, &row);
+        self.index_add(self.last_id, &row);
+        self.owner_index_add(self.last_id, &row.owner);
         self.last_id += 1;
         self.last_id - 1
     }
 
     pub fn delete(&mut self, id: u64) {
-        self.rows.remove(&id);
+        if let Some(row) = self.rows.remove(&id) {
+            self.index_remove(id, &row);
+            self.owner_index_remove(id, &row.owner);
+            self.history.remove(&id);
+        }
+    }
+
+    /// Saves current fields of the row to its history, keeping up to `MAX_ROW_HISTORY` latest versions.
+    pub fn push_history(&mut self, id: u64, row: &Row) {
+        let mut history = self.history.get(&id).unwrap_or_default();
+        if history.len() >= MAX_ROW_HISTORY {
+            history.remove(0);
+        }
+        history.push(RowVersion { version: row.version, fields: row.fields.clone(), editor: row.owner.clone(), timestamp: row.updated_at });
+        self.history.insert(&id, &history);
     }
 
     pub fn update(&mut self, id: u64, new_row: Row) {
-        self.rows.insert(&id, &new_row);
+        if let Some(row) = self.rows.insert(&id, &new_row) {
+            self.index_remove(id, &row);
+            if row.owner != new_row.owner {
+                self.owner_index_remove(id, &row.owner);
+                self.owner_index_add(id, &new_row.owner);
+            }
+        }
+        self.index_add(id, &new_row);
+    }
+
+    pub fn get(&self, id: u64) -> Row {
+        self.rows.get(&id).expect("Row is missing")
     }
 
-    pub fn get(&self, id: u64) -> Option<Row> {
-        self.rows.get(&id)
+    pub fn len(&self) -> u64 {
+        self.rows.len()
     }
 
-    pub fn list(&self) -> Vec<(u64, Row)> {
-        self.rows.to_vec()
+    /// Returns up to `limit` rows starting from `from_index`, in storage order.
+    pub fn list(&self, from_index: u64, limit: u64) -> Vec<(u64, Row)> {
+        let keys = self.rows.keys_as_vector();
+        let values = self.rows.values_as_vector();
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    /// Returns up to `limit` rows with id starting from `from_id`, ordered by id.
+    pub fn list_from(&self, from_id: u64, limit: u64) -> Vec<(u64, Row)> {
+        (from_id..self.last_id)
+            .filter_map(|id| self.rows.get(&id).map(|row| (id, row)))
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Returns up to `limit` rows with `field` equal to `value` starting from `from_index`, ordered by id.
+    pub fn find(&self, field: String, value: String, from_index: u64, limit: u64) -> Vec<(u64, Row)> {
+        self.index
+            .get(&(field, value))
+            .unwrap_or_default()
+            .into_iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|id| (id, self.rows.get(&id).unwrap()))
+            .collect()
+    }
+
+    /// Returns up to `limit` rows owned by `owner` starting from `from_index`, ordered by id.
+    pub fn find_by_owner(&self, owner: &AccountId, from_index: u64, limit: u64) -> Vec<(u64, Row)> {
+        self.owner_index
+            .get(owner)
+            .unwrap_or_default()
+            .into_iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|id| (id, self.rows.get(&id).unwrap()))
+            .collect()
+    }
+
+    fn owner_index_add(&mut self, id: u64, owner: &AccountId) {
+        let mut ids = self.owner_index.get(owner).unwrap_or_default();
+        if let Err(position) = ids.binary_search(&id) {
+            ids.insert(position, id);
+        }
+        self.owner_index.insert(owner, &ids);
+    }
+
+    fn owner_index_remove(&mut self, id: u64, owner: &AccountId) {
+        let mut ids = self.owner_index.get(owner).unwrap_or_default();
+        ids.retain(|row_id| *row_id != id);
+        if ids.is_empty() {
+            self.owner_index.remove(owner);
+        } else {
+            self.owner_index.insert(owner, &ids);
+        }
+    }
+
+    fn index_add(&mut self, id: u64, row: &Row) {
+        for (field, value) in row.fields.iter() {
+            let key = (field.clone(), value.clone());
+            let mut ids = self.index.get(&key).unwrap_or_default();
+            if let Err(position) = ids.binary_search(&id) {
+                ids.insert(position, id);
+            }
+            self.index.insert(&key, &ids);
+        }
+    }
+
+    fn index_remove(&mut self, id: u64, row: &Row) {
+        for (field, value) in row.fields.iter() {
+            let key = (field.clone(), value.clone());
+            let mut ids = self.index.get(&key).unwrap_or_default();
+            ids.retain(|row_id| *row_id != id);
+            if ids.is_empty() {
+                self.index.remove(&key);
+            } else {
+                self.index.insert(&key, &ids);
+            }
+        }
     }
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
 enum Vote {
     Null,
     Delete,
     Keep
 }
 
-#[derive(BorshSerialize, BorshDeserialize)]
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
 struct Challenge {
+    /// Challenged row.
+    row_id: u64,
+    /// Version of the row when it was challenged.
+    row_version: u64,
     /// Initiator challenge.
     challenger: AccountId,
+    /// $TCR locked by the challenger, the challenge deposit of the table when the challenge was created.
+    deposit: Balance,
     /// Attached description: either link or short content.
     description: String,
-    /// All the votes for given challenge.
+    /// All the votes for given challenge with $TCR voted by the voter.
     votes: HashMap<AccountId, (Vote, u128)>,
+    /// $TCR still locked by the voters, until they unlock it after the challenge is finalized.
+    locked: HashMap<AccountId, Balance>,
     /// When challenge concludes.
     end_time: Timestamp,
-    /// Total votes for deleting.
+    /// Total $TCR voted for deleting.
     vote_delete: u128,
-    /// Total votes for keeping.
+    /// Total $TCR voted for keeping.
     vote_keep: u128,
+    /// Side that won, set when the challenge is finalized.
+    outcome: Option<Vote>,
+    /// Part of the forfeited deposit left to claim by the voters on the winning side.
+    voter_reward: Balance,
 }
 
 #[near_bindgen]
-#[derive(BorshSerialize, BorshDeserialize)]
+#[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 struct TokenCuratedRegistry {
     upgrade: Upgrade,
     bank: Bank,
-    token: Token,
-    table: Table,
-    challenges: UnorderedMap<u64, Challenge>,
+    token: FungibleToken,
+    /// Metadata of $TCR.
+    metadata: LazyOption<FungibleTokenMetadata>,
+    /// Registries hosted by this contract, by name.
+    tables: UnorderedMap<String, Table>,
+    last_proposal_id: u64,
+    /// Open proposals to change the parameters of the tables.
+    params_proposals: UnorderedMap<u64, ParamsProposal>,
 }
 
+#[near_bindgen]
 impl TokenCuratedRegistry {
     #[init]
-    pub fn new(owner: AccountId) -> Self {
+    pub fn new(owner: ValidAccountId, metadata: FungibleTokenMetadata) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        metadata.assert_valid();
+        let mut token = FungibleToken::new(b"a".to_vec());
+        token.internal_register_account(owner.as_ref());
+        token.internal_deposit(owner.as_ref(), INITIAL_SUPPLY);
         Self {
-            upgrade: Upgrade::new(owner.clone(), UPGRADE_STAGING_DURATION),
+            upgrade: Upgrade::new(owner.into(), UPGRADE_STAGING_DURATION),
+            token,
+            metadata: LazyOption::new(b"m".to_vec(), Some(&metadata)),
+            tables: UnorderedMap::new(b"n".to_vec()),
+            last_proposal_id: 0,
+            params_proposals: UnorderedMap::new(b"p".to_vec()),
             bank: Bank::new(),
-            token: Token::new(owner, INITIAL_SUPPLY),
-            table: Table::new(),
-            challenges: UnorderedMap::new(b"c".to_vec()),
         }
     }
 
-    pub fn get(&mut self, id: u64) -> Row {
-        self.table.get(id).expect("Row is missing")
+    /// Owner creates new table with given `schema` and default parameters.
+    /// Attached deposit must cover the storage of the table.
+    #[payable]
+    pub fn create_table(&mut self, name: String, schema: Schema) {
+        assert_eq!(env::predecessor_account_id(), self.upgrade.get_owner(), "Only owner can create tables");
+        assert!(self.tables.get(&name).is_none(), "Table already exists");
+        schema.assert_consistent();
+        self.bank.start_record();
+        let table = Table::new(format!("t{}:", self.tables.len()), schema, Params::default());
+        self.tables.insert(&name, &table);
+        self.bank.end_record();
+    }
+
+    /// Returns `limit` names of the tables starting from `from_index`.
+    pub fn get_tables(&self, from_index: u64, limit: u64) -> Vec<String> {
+        let keys = self.tables.keys_as_vector();
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .map(|index| keys.get(index).unwrap())
+            .collect()
+    }
+
+    pub fn get_schema(&self, table_name: String) -> Schema {
+        self.get_table(&table_name).schema
+    }
+
+    /// Owner replaces the schema of the table. Existing rows are validated on the next update.
+    #[payable]
+    pub fn set_schema(&mut self, table_name: String, schema: Schema) {
+        assert_eq!(env::predecessor_account_id(), self.upgrade.get_owner(), "Only owner can set schema");
+        schema.assert_consistent();
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        table.schema = schema;
+        self.tables.insert(&table_name, &table);
+        self.bank.end_record();
+    }
+
+    pub fn get(&self, table_name: String, id: u64) -> Row {
+        self.get_table(&table_name).get(id)
+    }
+
+    /// Returns `limit` previous versions of the row starting from `from_index`, oldest first.
+    /// Only `MAX_ROW_HISTORY` latest versions are kept.
+    pub fn get_row_history(&self, table_name: String, id: u64, from_index: u64, limit: u64) -> Vec<RowVersion> {
+        self.get_table(&table_name)
+            .history
+            .get(&id)
+            .unwrap_or_default()
+            .into_iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Returns `limit` rows with `field` equal to `value` starting from `from_index`.
+    /// Expired rows are skipped, so fewer than `limit` rows can be returned.
+    pub fn find_rows(&self, table_name: String, field: String, value: String, from_index: u64, limit: u64) -> Vec<(u64, Row)> {
+        self.get_table(&table_name).find(field, value, from_index, limit).into_iter().filter(|(_, row)| !row.is_expired()).collect()
+    }
+
+    /// Returns `limit` rows owned by `owner_id` starting from `from_index`, including expired ones.
+    pub fn get_rows_by_owner(&self, table_name: String, owner_id: ValidAccountId, from_index: u64, limit: u64) -> Vec<(u64, Row)> {
+        self.get_table(&table_name).find_by_owner(owner_id.as_ref(), from_index, limit)
+    }
+
+    /// Returns number of rows in the table.
+    pub fn get_total_rows(&self, table_name: String) -> u64 {
+        self.get_table(&table_name).len()
     }
 
-    pub fn list(&mut self) -> Vec<(u64, Row)> {
-        self.table.list()
+    /// Returns `limit` rows starting from `from_index`.
+    /// Expired rows are skipped, so fewer than `limit` rows can be returned.
+    pub fn list(&self, table_name: String, from_index: u64, limit: u64) -> Vec<(u64, Row)> {
+        self.get_table(&table_name).list(from_index, limit).into_iter().filter(|(_, row)| !row.is_expired()).collect()
     }
 
-    // #[payable]
-    pub fn insert(&mut self, fields: HashMap<String, String>) -> u64 {
+    /// Inserts new row, locking listing deposit of sender's $TCR.
+    /// The deposit is forfeited if the row gets removed by a challenge.
+    /// If `ttl` is given, the row expires after it unless renewed by the owner.
+    #[payable]
+    pub fn insert(&mut self, table_name: String, fields: HashMap<String, String>, ttl: Option<U64>) -> u64 {
         self.bank.start_record();
-        let result = self.table.insert(Row { owner: env::predecessor_account_id(), fields });
+        let mut table = self.get_table(&table_name);
+        table.schema.assert_valid(&fields);
+        let deposit = table.params.listing_deposit.0;
+        self.lock_stake(&env::predecessor_account_id(), deposit);
+        let expires_at = ttl.map(|ttl| env::block_timestamp() + ttl.0);
+        let result = table.insert(Row { owner: env::predecessor_account_id(), fields, deposit, challenge_id: None, expires_at, version: 0, updated_at: env::block_timestamp() });
+        self.tables.insert(&table_name, &table);
+        Event::Insert { table_name: &table_name, id: result, owner: &env::predecessor_account_id() }.emit();
         self.bank.end_record();
         result
     }
 
-    // #[payable]
-    pub fn update(&mut self, id: u64, fields: HashMap<String, String>) {
+    /// Owner renews the row before it expires, extending it by `ttl` from now.
+    /// Listing deposit is re-staked: the locked $TCR is replaced with the current listing deposit.
+    #[payable]
+    pub fn renew(&mut self, table_name: String, id: u64, ttl: U64) {
         self.bank.start_record();
-        let mut row = self.get(id);
+        let mut table = self.get_table(&table_name);
+        let mut row = table.get(id);
         assert_eq!(row.owner, env::predecessor_account_id());
+        assert!(row.expires_at.is_some(), "Row doesn't expire");
+        assert!(!row.is_expired(), "Row is expired");
+        self.unlock_stake(&row.owner, row.deposit);
+        row.deposit = table.params.listing_deposit.0;
+        self.lock_stake(&row.owner, row.deposit);
+        row.expires_at = Some(env::block_timestamp() + ttl.0);
+        table.update(id, row);
+        self.tables.insert(&table_name, &table);
+        self.bank.end_record();
+    }
+
+    /// Anyone can remove expired row that is not challenged, getting the refund for the released storage.
+    /// Locked $TCR is returned to the owner of the row.
+    #[payable]
+    pub fn remove_expired(&mut self, table_name: String, id: u64) {
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        let row = table.get(id);
+        assert!(row.is_expired(), "Row is not expired");
+        assert!(row.challenge_id.is_none(), "Row is challenged");
+        table.delete(id);
+        self.tables.insert(&table_name, &table);
+        self.unlock_stake(&row.owner, row.deposit);
+        Event::Remove { table_name: &table_name, id, owner: &row.owner }.emit();
+        self.bank.end_record();
+    }
+
+    /// Owner removes the row that is not challenged, getting back locked $TCR and the refund for the released storage.
+    #[payable]
+    pub fn remove(&mut self, table_name: String, id: u64) {
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        let row = table.get(id);
+        assert_eq!(row.owner, env::predecessor_account_id());
+        assert!(row.challenge_id.is_none(), "Row is challenged");
+        table.delete(id);
+        self.tables.insert(&table_name, &table);
+        self.unlock_stake(&row.owner, row.deposit);
+        Event::Remove { table_name: &table_name, id, owner: &row.owner }.emit();
+        self.bank.end_record();
+    }
+
+    #[payable]
+    pub fn update(&mut self, table_name: String, id: u64, fields: HashMap<String, String>) {
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        let mut row = table.get(id);
+        assert_eq!(row.owner, env::predecessor_account_id());
+        table.schema.assert_valid(&fields);
+        table.push_history(id, &row);
         row.fields = fields;
-        self.table.update(id, row);
+        row.version += 1;
+        row.updated_at = env::block_timestamp();
+        Event::Update { table_name: &table_name, id, owner: &row.owner }.emit();
+        table.update(id, row);
+        self.tables.insert(&table_name, &table);
+        self.bank.end_record();
+    }
+
+    /// Owner can import rows in bulk, e.g. when migrating existing registry.
+    /// Attached deposit must cover the storage of imported rows, the rest is refunded.
+    /// Imported rows don't have $TCR locked.
+    /// Returns ids of the imported rows.
+    #[payable]
+    pub fn import_rows(&mut self, table_name: String, rows: Vec<RowInput>) -> Vec<u64> {
+        assert_eq!(env::predecessor_account_id(), self.upgrade.get_owner(), "Only owner can import rows");
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        let ids = rows
+            .into_iter()
+            .map(|row| {
+                table.schema.assert_valid(&row.fields);
+                let id = table.insert(Row { owner: row.owner.clone(), fields: row.fields, deposit: 0, challenge_id: None, expires_at: None, version: 0, updated_at: env::block_timestamp() });
+                Event::Insert { table_name: &table_name, id, owner: &row.owner }.emit();
+                id
+            })
+            .collect();
+        self.tables.insert(&table_name, &table);
         self.bank.end_record();
+        ids
+    }
+
+    /// Exports up to `limit` rows with id starting from `from_id`, ordered by id.
+    /// To get the next page, pass id of the last returned row plus one.
+    pub fn export_rows(&self, table_name: String, from_id: u64, limit: u64) -> Vec<(u64, Row)> {
+        self.get_table(&table_name).list_from(from_id, limit)
     }
 
-    pub fn set_row_owner(&mut self, id: u64, new_owner: AccountId) {
+    #[payable]
+    pub fn set_row_owner(&mut self, table_name: String, id: u64, new_owner: ValidAccountId) {
         self.bank.start_record();
-        let mut row = self.get(id);
+        let mut table = self.get_table(&table_name);
+        let mut row = table.get(id);
         assert_eq!(row.owner, env::predecessor_account_id());
-        row.owner = new_owner;
-        self.table.update(id, row);
+        Event::SetRowOwner { table_name: &table_name, id, old_owner: &row.owner, new_owner: new_owner.as_ref() }.emit();
+        row.owner = new_owner.into();
+        table.update(id, row);
+        self.tables.insert(&table_name, &table);
         self.bank.end_record();
     }
 
-    pub fn get_challenge(&self, id: u64) -> Challenge {
-        self.challenges.get(&id).expect("No challenge for given id")
+    pub fn get_challenge(&self, table_name: String, id: u64) -> Challenge {
+        let table = self.get_table(&table_name);
+        table.challenges.get(&id)
+            .or_else(|| table.finalized_challenges.get(&id))
+            .expect("No challenge for given id")
     }
 
-    pub fn get_challenge_list(&self) -> Vec<(u64, Challenge)> {
-        self.challenges.to_vec()
+    /// Returns number of open challenges in the table.
+    pub fn get_open_challenge_count(&self, table_name: String) -> u64 {
+        self.get_table(&table_name).challenges.len()
     }
 
-    /// Create new challenge. Must have CHALLENGE_DEPOSIT amount of $TCR to proceed.
-    /// If the challenge is successful - $TCR is returned,
-    /// if the challenge is unsuccessful - $TCR is burned.
-//    #[payable]
-    pub fn challenge(&mut self, id: u64, description: String) {
-        assert!(self.challenges.get(&id).is_none(), "Given id already challenged");
+    /// Returns `limit` open challenges starting from `from_index`.
+    pub fn get_challenges(&self, table_name: String, from_index: u64, limit: u64) -> Vec<(u64, Challenge)> {
+        let table = self.get_table(&table_name);
+        let keys = table.challenges.keys_as_vector();
+        let values = table.challenges.values_as_vector();
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    /// Create new challenge of row `id`. Must have challenge deposit amount of $TCR to proceed.
+    /// If the challenge is successful - $TCR is returned together with a part of the listing deposit,
+    /// if the challenge is unsuccessful - $TCR is given to the row owner and voters to keep the row.
+    /// Returns id of the challenge, unique within the table.
+    #[payable]
+    pub fn challenge(&mut self, table_name: String, id: u64, description: String) -> u64 {
+        let mut table = self.get_table(&table_name);
+        let mut row = table.get(id);
+        assert!(row.challenge_id.is_none(), "Given id already challenged");
+        assert!(!row.is_expired(), "Row is expired");
         self.bank.start_record();
+        let deposit = table.params.challenge_deposit.0;
+        self.lock_stake(&env::predecessor_account_id(), deposit);
+        let challenge_id = table.last_challenge_id;
+        table.last_challenge_id += 1;
+        row.challenge_id = Some(challenge_id);
+        let row_version = row.version;
+        table.update(id, row);
         let challenge = Challenge {
+            row_id: id,
+            row_version,
             challenger: env::predecessor_account_id(),
+            deposit,
             description,
             votes: HashMap::default(),
-            end_time: env::block_timestamp() + CHALLENGE_DURATION,
+            locked: HashMap::default(),
+            end_time: env::block_timestamp() + table.params.challenge_duration,
             vote_delete: 0,
             vote_keep: 0,
+            outcome: None,
+            voter_reward: 0,
         };
-        self.challenges.insert(&id, &challenge);
+        table.challenges.insert(&challenge_id, &challenge);
+        self.tables.insert(&table_name, &table);
+        Event::Challenge { table_name: &table_name, challenge_id, row_id: id, challenger: &challenge.challenger }.emit();
         self.bank.end_record();
+        challenge_id
     }
 
-//    #[payable]
-    pub fn challenge_vote(&mut self, id: u64, vote: Vote) {
+    /// Vote on the challenge with `amount` of $TCR, which is locked until the challenge is finalized
+    /// and then unlocked by the voter with `unlock_tokens`.
+    #[payable]
+    pub fn challenge_vote(&mut self, table_name: String, id: u64, vote: Vote, amount: U128) {
+        let amount: Balance = amount.into();
+        assert!(amount > 0, "Vote must lock some $TCR");
         self.bank.start_record();
-        let mut challenge = self.challenges.get(&id).expect("No challenge for given id");
+        let mut table = self.get_table(&table_name);
+        let mut challenge = table.challenges.get(&id).expect("No challenge for given id");
+        assert!(challenge.end_time > env::block_timestamp(), "Voting period has passed");
         if challenge.votes.contains_key(&env::predecessor_account_id()) {
             env::panic(b"Already voted");
         }
-        challenge.votes.insert(env::predecessor_account_id(), (vote.clone(), 1));
+        self.lock_stake(&env::predecessor_account_id(), amount);
+        challenge.votes.insert(env::predecessor_account_id(), (vote.clone(), amount));
+        challenge.locked.insert(env::predecessor_account_id(), amount);
         match vote {
             Vote::Null => {},
-            Vote::Delete => challenge.vote_delete += 1,
-            Vote::Keep => challenge.vote_keep += 1,
+            Vote::Delete => challenge.vote_delete += amount,
+            Vote::Keep => challenge.vote_keep += amount,
         }
-        self.challenges.insert(&id, &challenge);
+        table.challenges.insert(&id, &challenge);
+        self.tables.insert(&table_name, &table);
+        Event::ChallengeVote {
+            table_name: &table_name,
+            challenge_id: id,
+            account_id: &env::predecessor_account_id(),
+            vote: &vote,
+            amount: amount.into(),
+        }.emit();
         self.bank.end_record();
     }
 
     /// Anyone can call to finalize open challenge.
-    pub fn finalize_challenge(&mut self, id: u64) {
+    /// The deposit of the losing side is split between the winner and the voters on the winning side,
+    /// who claim their part with `claim_reward`.
+    #[payable]
+    pub fn finalize_challenge(&mut self, table_name: String, id: u64) {
         self.bank.start_record();
-        let challenge = self.challenges.get(&id).expect("No challenge for given id");
+        let mut table = self.get_table(&table_name);
+        let challenge = table.challenges.get(&id).expect("No challenge for given id");
         if challenge.end_time > env::block_timestamp() {
             env::panic(b"Challenge period didn't pass yet");
         }
-        self.challenges.remove(&id);
-        if challenge.vote_delete > challenge.vote_keep {
-            self.table.delete(id);
-            env::log(b"Challenge successful");
-        } else {
-            env::log(b"Challenge unsuccessful");
+        self.internal_finalize_challenge(&table_name, &mut table, id, challenge);
+        self.tables.insert(&table_name, &table);
+        self.bank.end_record();
+    }
+
+    /// Anyone can call to finalize up to `limit` open challenges of the table which voting period has passed.
+    /// Returns number of finalized challenges.
+    #[payable]
+    pub fn finalize_expired_challenges(&mut self, table_name: String, limit: u64) -> u64 {
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        let expired: Vec<(u64, Challenge)> = table
+            .challenges
+            .iter()
+            .filter(|(_, challenge)| challenge.end_time <= env::block_timestamp())
+            .take(limit as usize)
+            .collect();
+        let count = expired.len() as u64;
+        for (id, challenge) in expired {
+            self.internal_finalize_challenge(&table_name, &mut table, id, challenge);
+        }
+        self.tables.insert(&table_name, &table);
+        self.bank.end_record();
+        count
+    }
+
+    /// Voter on the winning side of finalized challenge claims its part of the forfeited deposit,
+    /// pro-rata to the $TCR it voted with.
+    #[payable]
+    pub fn claim_reward(&mut self, table_name: String, challenge_id: u64) -> U128 {
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        let mut challenge = table.finalized_challenges.get(&challenge_id).expect("Challenge is not finalized");
+        let (vote, amount) = challenge.votes.remove(&env::predecessor_account_id()).expect("No reward to claim");
+        let outcome = challenge.outcome.clone().unwrap();
+        assert!(vote == outcome, "No reward to claim");
+        let winning_votes = if outcome == Vote::Delete { challenge.vote_delete } else { challenge.vote_keep };
+        let reward = mul_div(challenge.voter_reward, amount, winning_votes);
+        table.finalized_challenges.insert(&challenge_id, &challenge);
+        self.unlock_stake(&env::predecessor_account_id(), reward);
+        self.bank.end_record();
+        reward.into()
+    }
+
+    /// Voter unlocks $TCR it voted with on finalized challenge. Returns unlocked amount.
+    #[payable]
+    pub fn unlock_tokens(&mut self, table_name: String, challenge_id: u64) -> U128 {
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        let mut challenge = table.finalized_challenges.get(&challenge_id).expect("Challenge is not finalized");
+        let amount = challenge.locked.remove(&env::predecessor_account_id()).expect("No tokens to unlock");
+        table.finalized_challenges.insert(&challenge_id, &challenge);
+        self.unlock_stake(&env::predecessor_account_id(), amount);
+        self.bank.end_record();
+        amount.into()
+    }
+
+    /// Voter unlocks $TCR it voted with on the challenge that wasn't finalized after the voting period,
+    /// e.g. if finalization fails. The vote is still counted if the challenge is finalized later.
+    /// Returns unlocked amount.
+    #[payable]
+    pub fn rescue_tokens(&mut self, table_name: String, challenge_id: u64) -> U128 {
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        let mut challenge = table.challenges.get(&challenge_id).expect("No challenge for given id");
+        if challenge.end_time > env::block_timestamp() {
+            env::panic(b"Challenge period didn't pass yet");
         }
-        self.challenges.remove(&id);
+        let amount = challenge.locked.remove(&env::predecessor_account_id()).expect("No tokens to unlock");
+        table.challenges.insert(&challenge_id, &challenge);
+        self.tables.insert(&table_name, &table);
+        self.unlock_stake(&env::predecessor_account_id(), amount);
         self.bank.end_record();
+        amount.into()
     }
 }
 
-#[near_bindgen]
-impl Upgradable for TokenCuratedRegistry {
-    fn get_owner(&self) -> AccountId {
-        self.upgrade.get_owner()
+impl TokenCuratedRegistry {
+    fn get_table(&self, name: &str) -> Table {
+        self.tables.get(&name.to_string()).expect("Table is missing")
+    }
+
+    /// Finalizes the challenge, moving it from open to finalized challenges of the table.
+    fn internal_finalize_challenge(&mut self, table_name: &str, table: &mut Table, id: u64, mut challenge: Challenge) {
+        table.challenges.remove(&id);
+        let mut row = table.get(challenge.row_id);
+        let (outcome, winner, forfeited, winning_votes) = if table.params.is_passed(challenge.vote_delete, challenge.vote_keep) {
+            table.delete(challenge.row_id);
+            self.unlock_stake(&challenge.challenger, challenge.deposit);
+            (Vote::Delete, challenge.challenger.clone(), row.deposit, challenge.vote_delete)
+        } else {
+            row.challenge_id = None;
+            let owner = row.owner.clone();
+            table.update(challenge.row_id, row);
+            (Vote::Keep, owner, challenge.deposit, challenge.vote_keep)
+        };
+        let dispensation = if winning_votes > 0 {
+            forfeited * table.params.dispensation_pct.0 / 100
+        } else {
+            forfeited
+        };
+        self.unlock_stake(&winner, dispensation);
+        Event::FinalizeChallenge { table_name, challenge_id: id, row_id: challenge.row_id, outcome: &outcome }.emit();
+        challenge.outcome = Some(outcome);
+        challenge.voter_reward = forfeited - dispensation;
+        table.finalized_challenges.insert(&id, &challenge);
+    }
+
+    /// Moves `amount` of $TCR from `account_id` to this contract.
+    fn lock_stake(&mut self, account_id: &AccountId, amount: Balance) {
+        self.internal_transfer(account_id, &env::current_account_id(), amount);
     }
 
-    fn set_owner(&mut self, owner: AccountId) {
-        self.upgrade.set_owner(owner);
+    /// Returns `amount` of $TCR locked in this contract to `account_id`.
+    fn unlock_stake(&mut self, account_id: &AccountId, amount: Balance) {
+        self.internal_transfer(&env::current_account_id(), account_id, amount);
     }
 
-    fn get_staging_duration(&self) -> WrappedDuration {
-        self.upgrade.get_staging_duration()
+    /// Burns `amount` of $TCR locked in this contract.
+    fn burn_stake(&mut self, amount: Balance) {
+        if amount > 0 {
+            self.token.internal_withdraw(&env::current_account_id(), amount);
+        }
+    }
+
+    /// Transfers $TCR, registering the receiver if needed. Storage is paid through the Bank.
+    fn internal_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        assert!(self.token.accounts.get(sender_id).unwrap_or(0) >= amount, "Not enough $TCR");
+        self.internal_register_if_missing(receiver_id);
+        self.token.internal_transfer(sender_id, receiver_id, amount, None);
+    }
+
+    fn internal_register_if_missing(&mut self, account_id: &AccountId) {
+        if !self.token.accounts.contains_key(account_id) {
+            self.token.internal_register_account(account_id);
+        }
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenCore for TokenCuratedRegistry {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        self.token.ft_transfer(receiver_id, amount, memo)
     }
 
-    fn stage_code(&mut self, code: Base64VecU8, timestamp: Timestamp) {
-        self.upgrade.stage_code(code, timestamp);
+    #[payable]
+    fn ft_transfer_call(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>, msg: String) -> PromiseOrValue<U128> {
+        self.token.ft_transfer_call(receiver_id, amount, memo, msg)
     }
 
-    fn deploy_code(&mut self) -> Promise {
-        self.upgrade.deploy_code()
+    fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
     }
 
-    fn migrate(&mut self) {
+    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.token.ft_balance_of(account_id)
     }
 }
 
 #[near_bindgen]
-impl FungibleToken for TokenCuratedRegistry {
-    fn inc_allowance(&mut self, escrow_account_id: String, amount: U128) {
-        unimplemented!()
+impl FungibleTokenResolver for TokenCuratedRegistry {
+    #[private]
+    fn ft_resolve_transfer(&mut self, sender_id: ValidAccountId, receiver_id: ValidAccountId, amount: U128) -> U128 {
+        self.token.ft_resolve_transfer(sender_id, receiver_id, amount)
     }
+}
 
-    fn dec_allowance(&mut self, escrow_account_id: String, amount: U128) {
-        unimplemented!()
+/// Registration with $TCR pays for its own storage, so it's not recorded by the Bank.
+#[near_bindgen]
+impl StorageManagement for TokenCuratedRegistry {
+    #[payable]
+    fn storage_deposit(&mut self, account_id: Option<ValidAccountId>, registration_only: Option<bool>) -> StorageBalance {
+        self.token.storage_deposit(account_id, registration_only)
     }
 
-    fn transfer_from(&mut self, owner_id: String, new_owner_id: String, amount: U128) {
-        unimplemented!()
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        self.token.storage_withdraw(amount)
     }
 
-    fn transfer(&mut self, new_owner_id: String, amount: U128) {
-        unimplemented!()
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        self.token.storage_unregister(force)
     }
 
-    fn get_total_supply(&self) -> U128 {
-        self.token.get_total_supply().into()
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        self.token.storage_balance_bounds()
     }
 
-    fn get_balance(&self, owner_id: String) -> U128 {
-        self.token.get_balance(owner_id).into()
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.token.storage_balance_of(account_id)
     }
+}
 
-    fn get_allowance(&self, owner_id: String, escrow_account_id: String) -> U128 {
-        self.token.get_allowance(owner_id, escrow_account_id).into()
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for TokenCuratedRegistry {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        self.metadata.get().unwrap()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use near_lib::context::{accounts, VMContextBuilder};
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_lib::upgrade::Upgradable;
     use near_sdk::{MockedBlockchain, testing_env};
 
     use super::*;
 
+    fn context(predecessor: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder.current_account_id(accounts(5)).predecessor_account_id(predecessor).attached_deposit(10u128.pow(24));
+        builder
+    }
+
+    const TABLE: &str = "tokens";
+
+    fn metadata() -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: "ft-1.0.0".to_string(),
+            name: "Token Curated Registry".to_string(),
+            symbol: "TCR".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
+    }
+
+    fn table() -> String {
+        TABLE.to_string()
+    }
+
+    /// Creates the registry owned by `accounts(0)` with empty `TABLE`.
+    fn setup() -> TokenCuratedRegistry {
+        let mut registry = TokenCuratedRegistry::new(accounts(0), metadata());
+        registry.create_table(table(), Schema::default());
+        registry
+    }
+
+    /// Deletes the row outside of the contract calls.
+    fn delete_row(registry: &mut TokenCuratedRegistry, id: u64) {
+        let mut rows = registry.get_table(TABLE);
+        rows.delete(id);
+        registry.tables.insert(&table(), &rows);
+    }
+
+    /// Mints $TCR outside of the contract calls, paying for its storage through the Bank.
+    fn mint(registry: &mut TokenCuratedRegistry, account_id: ValidAccountId, amount: Balance) {
+        registry.bank.start_record();
+        registry.internal_register_if_missing(account_id.as_ref());
+        registry.token.internal_deposit(account_id.as_ref(), amount);
+        registry.bank.end_record();
+    }
+
     #[test]
     fn test_edit_registry() {
-        testing_env!(VMContextBuilder::new().finish());
-        let mut registry = TokenCuratedRegistry::new(accounts(0));
-        let id1 = registry.insert(vec![("name".to_string(), "123".to_string())].into_iter().collect());
-        assert_eq!(registry.list().len(), 1);
-        registry.challenge(id1, "test".to_string());
-        assert_eq!(registry.get_challenge_list().len(), 1);
-        assert_eq!(registry.get_challenge(id1).votes.len(), 0);
-        registry.challenge_vote(id1, Vote::Delete);
-        assert_eq!(registry.get_challenge(id1).votes.len(), 1);
-        testing_env!(VMContextBuilder::new().block_timestamp(CHALLENGE_DURATION + 1).finish());
-        registry.finalize_challenge(id1);
-        assert_eq!(registry.get_challenge_list().len(), 0);
-        assert_eq!(registry.list().len(), 0);
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id1 = registry.insert(table(), vec![("name".to_string(), "123".to_string())].into_iter().collect(), None);
+        assert_eq!(registry.get_total_rows(table()), 1);
+        assert_eq!(registry.list(table(), 0, 10).len(), 1);
+        let challenge_id = registry.challenge(table(), id1, "test".to_string());
+        assert_eq!(registry.get_open_challenge_count(table()), 1);
+        assert_eq!(registry.get_challenges(table(), 0, 10)[0].0, challenge_id);
+        assert_eq!(registry.get_challenge(table(), challenge_id).votes.len(), 0);
+        registry.challenge_vote(table(), challenge_id, Vote::Delete, 10.into());
+        assert_eq!(registry.get_challenge(table(), challenge_id).votes.len(), 1);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY - LISTING_DEPOSIT - CHALLENGE_DEPOSIT - 10);
+        testing_env!(context(accounts(0)).block_timestamp(CHALLENGE_DURATION + 1).build());
+        registry.finalize_challenge(table(), challenge_id);
+        assert_eq!(registry.get_challenges(table(), 0, 10).len(), 0);
+        assert_eq!(registry.list(table(), 0, 10).len(), 0);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY - LISTING_DEPOSIT / 2 - 10);
+        assert_eq!(registry.claim_reward(table(), challenge_id).0, LISTING_DEPOSIT / 2);
+        assert_eq!(registry.unlock_tokens(table(), challenge_id).0, 10);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY);
+        assert_eq!(registry.ft_total_supply().0, INITIAL_SUPPLY);
+    }
+
+    #[test]
+    fn test_unsuccessful_challenge() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        mint(&mut registry, accounts(1), CHALLENGE_DEPOSIT + 10);
+        let id = registry.insert(table(), vec![("name".to_string(), "123".to_string())].into_iter().collect(), None);
+        testing_env!(context(accounts(1)).build());
+        let challenge_id = registry.challenge(table(), id, "test".to_string());
+        registry.challenge_vote(table(), challenge_id, Vote::Delete, 10.into());
+        assert_eq!(registry.ft_balance_of(accounts(1)).0, 0);
+        testing_env!(context(accounts(2)).build());
+        mint(&mut registry, accounts(2), 10);
+        registry.challenge_vote(table(), challenge_id, Vote::Keep, 10.into());
+        assert_eq!(registry.get_challenge(table(), challenge_id).vote_keep, 10);
+        testing_env!(context(accounts(0)).build());
+        registry.challenge_vote(table(), challenge_id, Vote::Keep, 30.into());
+        testing_env!(context(accounts(0)).block_timestamp(CHALLENGE_DURATION + 1).build());
+        registry.finalize_challenge(table(), challenge_id);
+        assert_eq!(registry.list(table(), 0, 10).len(), 1);
+        assert_eq!(registry.ft_balance_of(accounts(1)).0, 0);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY - LISTING_DEPOSIT + CHALLENGE_DEPOSIT / 2 - 30);
+        assert_eq!(registry.claim_reward(table(), challenge_id).0, CHALLENGE_DEPOSIT * 3 / 8);
+        assert_eq!(registry.unlock_tokens(table(), challenge_id).0, 30);
+        testing_env!(context(accounts(2)).build());
+        assert_eq!(registry.claim_reward(table(), challenge_id).0, CHALLENGE_DEPOSIT / 8);
+        assert_eq!(registry.unlock_tokens(table(), challenge_id).0, 10);
+        testing_env!(context(accounts(1)).build());
+        assert_eq!(registry.unlock_tokens(table(), challenge_id).0, 10);
+        assert_eq!(registry.ft_balance_of(accounts(1)).0, 10);
+        assert_eq!(registry.ft_balance_of(accounts(5)).0, LISTING_DEPOSIT);
+        assert_eq!(registry.ft_total_supply().0, INITIAL_SUPPLY + CHALLENGE_DEPOSIT + 20);
+        // Row can be challenged again.
+        testing_env!(context(accounts(0)).build());
+        assert_eq!(registry.challenge(table(), id, "test".to_string()), challenge_id + 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "No reward to claim")]
+    fn test_claim_reward_twice() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.insert(table(), HashMap::default(), None);
+        let challenge_id = registry.challenge(table(), id, "test".to_string());
+        registry.challenge_vote(table(), challenge_id, Vote::Keep, 10.into());
+        testing_env!(context(accounts(0)).block_timestamp(CHALLENGE_DURATION + 1).build());
+        registry.finalize_challenge(table(), challenge_id);
+        registry.claim_reward(table(), challenge_id);
+        registry.claim_reward(table(), challenge_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Voting period has passed")]
+    fn test_challenge_vote_after_end() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.insert(table(), HashMap::default(), None);
+        let challenge_id = registry.challenge(table(), id, "test".to_string());
+        testing_env!(context(accounts(0)).block_timestamp(CHALLENGE_DURATION).build());
+        registry.challenge_vote(table(), challenge_id, Vote::Keep, 10.into());
+    }
+
+    #[test]
+    fn test_rescue_tokens() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.insert(table(), HashMap::default(), None);
+        let challenge_id = registry.challenge(table(), id, "test".to_string());
+        registry.challenge_vote(table(), challenge_id, Vote::Keep, 10.into());
+        testing_env!(context(accounts(0)).block_timestamp(CHALLENGE_DURATION + 1).build());
+        assert_eq!(registry.rescue_tokens(table(), challenge_id).0, 10);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY - LISTING_DEPOSIT - CHALLENGE_DEPOSIT);
+        // The vote still counts.
+        registry.finalize_challenge(table(), challenge_id);
+        assert_eq!(registry.list(table(), 0, 10).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough deposit to cover storage")]
+    fn test_insert_without_deposit() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        testing_env!(context(accounts(0)).attached_deposit(0).build());
+        registry.insert(table(), HashMap::default(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough $TCR")]
+    fn test_insert_without_stake() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        testing_env!(context(accounts(1)).build());
+        registry.insert(table(), HashMap::default(), None);
+    }
+
+    #[test]
+    fn test_import_export() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).attached_deposit(10u128.pow(24)).build());
+        let mut registry = setup();
+        let rows = (0..3).map(|i| RowInput {
+            owner: accounts(1).into(),
+            fields: vec![("name".to_string(), i.to_string())].into_iter().collect(),
+        }).collect();
+        assert_eq!(registry.import_rows(table(), rows), vec![0, 1, 2]);
+        delete_row(&mut registry, 1);
+        let page = registry.export_rows(table(), 0, 1);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].1.owner, AccountId::from(accounts(1)));
+        let page = registry.export_rows(table(), page[0].0 + 1, 10);
+        assert_eq!(page.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(page[0].1.fields["name"], "2");
+        assert_eq!(registry.get_total_rows(table()), 2);
+        assert_eq!(registry.list(table(), 1, 10).len(), 1);
+        assert_eq!(registry.list(table(), 2, 10).len(), 0);
+    }
+
+    #[test]
+    fn test_find_rows() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let fields = |symbol: &str| vec![("symbol".to_string(), symbol.to_string())].into_iter().collect();
+        let id1 = registry.insert(table(), fields("ABC"), None);
+        let id2 = registry.insert(table(), fields("XYZ"), None);
+        let id3 = registry.insert(table(), fields("ABC"), None);
+        let find = |registry: &TokenCuratedRegistry, symbol: &str, from_index| {
+            registry.find_rows(table(), "symbol".to_string(), symbol.to_string(), from_index, 10)
+                .into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        assert_eq!(find(&registry, "ABC", 0), vec![id1, id3]);
+        assert_eq!(find(&registry, "ABC", 1), vec![id3]);
+        registry.update(table(), id2, fields("ABC"));
+        assert_eq!(find(&registry, "ABC", 0), vec![id1, id2, id3]);
+        assert_eq!(find(&registry, "XYZ", 0), Vec::<u64>::new());
+        delete_row(&mut registry, id1);
+        assert_eq!(find(&registry, "ABC", 0), vec![id2, id3]);
+    }
+
+    #[test]
+    fn test_row_history() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let fields = |version: u64| vec![("version".to_string(), version.to_string())].into_iter().collect();
+        let id = registry.insert(table(), fields(0), None);
+        for version in 1..=MAX_ROW_HISTORY as u64 + 1 {
+            testing_env!(context(accounts(0)).block_timestamp(version).build());
+            registry.update(table(), id, fields(version));
+        }
+        let history = registry.get_row_history(table(), id, 0, 100);
+        assert_eq!(history.len(), MAX_ROW_HISTORY);
+        assert_eq!(history[0].version, 1);
+        assert_eq!(history[0].fields["version"], "1");
+        assert_eq!(history[0].timestamp, 1);
+        assert_eq!(history[0].editor, AccountId::from(accounts(0)));
+        assert_eq!(registry.get_row_history(table(), id, 9, 10).len(), 1);
+        let challenge_id = registry.challenge(table(), id, "test".to_string());
+        assert_eq!(registry.get_challenge(table(), challenge_id).row_version, MAX_ROW_HISTORY as u64 + 1);
+    }
+
+    #[test]
+    fn test_remove() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.insert(table(), HashMap::default(), None);
+        registry.remove(table(), id);
+        assert_eq!(registry.get_total_rows(table()), 0);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY);
+    }
+
+    #[test]
+    #[should_panic(expected = "Row is challenged")]
+    fn test_remove_challenged() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.insert(table(), HashMap::default(), None);
+        registry.challenge(table(), id, "test".to_string());
+        registry.remove(table(), id);
+    }
+
+    #[test]
+    fn test_challenge_deposit() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.insert(table(), HashMap::default(), None);
+        let challenge_id = registry.challenge(table(), id, "test".to_string());
+        registry.set_challenge_deposit(table(), (2 * CHALLENGE_DEPOSIT).into());
+        assert_eq!(registry.get_challenge_deposit(table()).0, 2 * CHALLENGE_DEPOSIT);
+        assert_eq!(registry.get_challenge(table(), challenge_id).deposit, CHALLENGE_DEPOSIT);
+        testing_env!(context(accounts(0)).block_timestamp(CHALLENGE_DURATION + 1).build());
+        registry.finalize_challenge(table(), challenge_id);
+        // Unsuccessful challenge pays out the deposit in force when it was created.
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY - LISTING_DEPOSIT);
+        assert_eq!(registry.ft_balance_of(accounts(5)).0, LISTING_DEPOSIT);
+    }
+
+    #[test]
+    fn test_finalize_expired_challenges() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        for _ in 0..3 {
+            let id = registry.insert(table(), HashMap::default(), None);
+            registry.challenge(table(), id, "test".to_string());
+        }
+        testing_env!(context(accounts(0)).block_timestamp(1).build());
+        let id = registry.insert(table(), HashMap::default(), None);
+        registry.challenge(table(), id, "test".to_string());
+        testing_env!(context(accounts(1)).block_timestamp(CHALLENGE_DURATION).build());
+        assert_eq!(registry.finalize_expired_challenges(table(), 2), 2);
+        assert_eq!(registry.finalize_expired_challenges(table(), 10), 1);
+        assert_eq!(registry.get_open_challenge_count(table()), 1);
+        assert_eq!(registry.get_total_rows(table()), 4);
+    }
+
+    #[test]
+    fn test_rows_by_owner() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id1 = registry.insert(table(), HashMap::default(), None);
+        let id2 = registry.insert(table(), HashMap::default(), None);
+        let id3 = registry.insert(table(), HashMap::default(), None);
+        registry.set_row_owner(table(), id2, accounts(1));
+        registry.remove(table(), id3);
+        let owned = |registry: &TokenCuratedRegistry, account_id| {
+            registry.get_rows_by_owner(table(), account_id, 0, 10).into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        assert_eq!(owned(&registry, accounts(0)), vec![id1]);
+        assert_eq!(owned(&registry, accounts(1)), vec![id2]);
+        assert_eq!(registry.get_total_rows(table()), 2);
+    }
+
+    #[test]
+    fn test_tables() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        registry.create_table("names".to_string(), Schema { fields: vec!["name".to_string()], ..Schema::default() });
+        assert_eq!(registry.get_tables(0, 10), vec![table(), "names".to_string()]);
+        let fields: HashMap<_, _> = vec![("name".to_string(), "abc".to_string())].into_iter().collect();
+        assert_eq!(registry.insert(table(), fields.clone(), None), 0);
+        assert_eq!(registry.insert("names".to_string(), fields, None), 0);
+        assert_eq!(registry.challenge("names".to_string(), 0, "test".to_string()), 0);
+        assert_eq!(registry.get_open_challenge_count(table()), 0);
+        assert_eq!(registry.get_total_rows("names".to_string()), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown field symbol")]
+    fn test_schema_unknown_field() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        registry.create_table("names".to_string(), Schema { fields: vec!["name".to_string()], ..Schema::default() });
+        registry.insert("names".to_string(), vec![("symbol".to_string(), "ABC".to_string())].into_iter().collect(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing field symbol")]
+    fn test_schema_missing_field() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        registry.set_schema(table(), Schema { required: vec!["symbol".to_string()], ..Schema::default() });
+        registry.insert(table(), HashMap::default(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Value of field icon is too long")]
+    fn test_schema_value_too_long() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        registry.set_schema(table(), Schema { max_value_len: Some(4), ..Schema::default() });
+        registry.insert(table(), vec![("icon".to_string(), "x".repeat(5))].into_iter().collect(), None);
+    }
+
+    #[test]
+    fn test_expiry() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.insert(table(), HashMap::default(), Some(100.into()));
+        testing_env!(context(accounts(0)).block_timestamp(50).build());
+        registry.renew(table(), id, 100.into());
+        testing_env!(context(accounts(0)).block_timestamp(100).build());
+        assert_eq!(registry.list(table(), 0, 10).len(), 1);
+        testing_env!(context(accounts(1)).block_timestamp(150).build());
+        assert_eq!(registry.list(table(), 0, 10).len(), 0);
+        registry.remove_expired(table(), id);
+        assert_eq!(registry.get_total_rows(table()), 0);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY);
+    }
+
+    #[test]
+    #[should_panic(expected = "Row is not expired")]
+    fn test_remove_not_expired() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.insert(table(), HashMap::default(), Some(100.into()));
+        testing_env!(context(accounts(1)).block_timestamp(99).build());
+        registry.remove_expired(table(), id);
+    }
+
+    #[test]
+    fn test_params_proposal() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let params = Params { challenge_duration: 10, listing_deposit: U128(0), ..Params::default() };
+        let id = registry.propose_params(table(), params.clone());
+        registry.params_vote(id, true, 10.into());
+        let rejected_id = registry.propose_params(table(), Params { dispensation_pct: U128(0), ..Params::default() });
+        registry.params_vote(rejected_id, false, 10.into());
+        testing_env!(context(accounts(0)).block_timestamp(CHALLENGE_DURATION + 1).build());
+        registry.finalize_params(id);
+        registry.finalize_params(rejected_id);
+        assert_eq!(registry.get_params(table()), params);
+        assert_eq!(registry.params_unlock_tokens(id).0, 10);
+        assert_eq!(registry.params_unlock_tokens(rejected_id).0, 10);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY - CHALLENGE_DEPOSIT);
+        assert_eq!(registry.ft_total_supply().0, INITIAL_SUPPLY - CHALLENGE_DEPOSIT);
+        // New parameters apply to the next rows and challenges.
+        let row_id = registry.insert(table(), HashMap::default(), None);
+        let challenge_id = registry.challenge(table(), row_id, "test".to_string());
+        assert_eq!(registry.get_challenge(table(), challenge_id).end_time, CHALLENGE_DURATION + 11);
+        assert_eq!(registry.ft_balance_of(accounts(0)).0, INITIAL_SUPPLY - 2 * CHALLENGE_DEPOSIT);
+    }
+
+    #[test]
+    #[should_panic(expected = "Proposal is not finalized")]
+    fn test_params_unlock_not_finalized() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        let id = registry.propose_params(table(), Params::default());
+        registry.params_vote(id, true, 10.into());
+        testing_env!(context(accounts(0)).block_timestamp(CHALLENGE_DURATION + 1).build());
+        registry.params_unlock_tokens(id);
+    }
+
+    #[test]
+    fn test_ft_metadata() {
+        testing_env!(context(accounts(0)).build());
+        let registry = setup();
+        assert_eq!(registry.ft_metadata().symbol, metadata().symbol);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only owner can import rows")]
+    fn test_import_not_owner() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(1)).build());
+        let mut registry = TokenCuratedRegistry::new(accounts(0), metadata());
+        registry.import_rows(table(), vec![]);
+    }
+
+    #[test]
+    fn test_deploy_with_migration() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        registry.stage_code_with_migration(vec![1, 2, 3], UPGRADE_STAGING_DURATION);
+        assert!(registry.is_staging_migration());
+        testing_env!(context(accounts(0)).block_timestamp(UPGRADE_STAGING_DURATION).build());
+        registry.deploy_code();
+        assert!(!registry.is_staging_migration());
+    }
+
+    #[test]
+    #[should_panic(expected = "Deploy code too early")]
+    fn test_deploy_too_early() {
+        testing_env!(context(accounts(0)).build());
+        let mut registry = setup();
+        registry.stage_code(vec![1, 2, 3], UPGRADE_STAGING_DURATION);
+        registry.deploy_code();
     }
 }
@@ -0,0 +1,217 @@
+//! Parameterizer: $TCR holders propose and vote on changes to the parameters of the tables.
+//! Proposal locks the challenge deposit of the proposer and is voted on with $TCR the same way as
+//! challenges of the rows: voted $TCR is locked until the proposal is finalized, and then each voter
+//! unlocks it with `params_unlock_tokens`.
+
+use crate::*;
+
+/// Parameters of a table, changed via proposals.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Params {
+    /// How long challenges and proposals are voted on.
+    pub challenge_duration: Duration,
+    /// $TCR locked by the owner of the row while it's listed.
+    pub listing_deposit: U128,
+    /// $TCR locked by the challenger until the challenge is finalized.
+    pub challenge_deposit: U128,
+    /// Percentage of the voted $TCR that must be for the change (deleting the row or
+    /// accepting the proposal) for it to pass.
+    pub vote_quorum_pct: U128,
+    /// Percentage of the forfeited deposit that goes to the winner of the challenge,
+    /// the rest is distributed to the voters on the winning side.
+    pub dispensation_pct: U128,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            challenge_duration: CHALLENGE_DURATION,
+            listing_deposit: LISTING_DEPOSIT.into(),
+            challenge_deposit: CHALLENGE_DEPOSIT.into(),
+            vote_quorum_pct: VOTE_QUORUM_PCT.into(),
+            dispensation_pct: DISPENSATION_PCT.into(),
+        }
+    }
+}
+
+impl Params {
+    pub fn assert_valid(&self) {
+        assert!(
+            self.challenge_duration > 0,
+            "Challenge duration must be positive"
+        );
+        assert!(
+            self.vote_quorum_pct.0 <= 100,
+            "Vote quorum must be a percentage"
+        );
+        assert!(
+            self.dispensation_pct.0 <= 100,
+            "Dispensation must be a percentage"
+        );
+    }
+
+    /// Returns true if `vote_for` passes the quorum.
+    pub fn is_passed(&self, vote_for: Balance, vote_against: Balance) -> bool {
+        vote_for > 0 && vote_for * 100 > self.vote_quorum_pct.0 * (vote_for + vote_against)
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParamsProposal {
+    proposer: AccountId,
+    /// Name of the table to change the parameters of.
+    table_name: String,
+    params: Params,
+    /// $TCR locked by the proposer, returned if the proposal is accepted and burned otherwise.
+    deposit: U128,
+    /// All the votes for given proposal with $TCR locked by the voter, removed when the voter unlocks it.
+    votes: HashMap<AccountId, (bool, U128)>,
+    /// When voting concludes.
+    end_time: Timestamp,
+    /// Total $TCR voted for accepting.
+    vote_for: U128,
+    /// Total $TCR voted against.
+    vote_against: U128,
+    /// Set by `finalize_params`. Finalized proposal is kept until all the voters unlock their $TCR.
+    finalized: bool,
+}
+
+#[near_bindgen]
+impl TokenCuratedRegistry {
+    pub fn get_params(&self, table_name: String) -> Params {
+        self.get_table(&table_name).params
+    }
+
+    pub fn get_challenge_deposit(&self, table_name: String) -> U128 {
+        self.get_table(&table_name).params.challenge_deposit
+    }
+
+    /// Owner sets the challenge deposit of the table, which applies to the next challenges and proposals.
+    #[payable]
+    pub fn set_challenge_deposit(&mut self, table_name: String, challenge_deposit: U128) {
+        self.upgrade.assert_owner();
+        self.bank.start_record();
+        let mut table = self.get_table(&table_name);
+        table.params.challenge_deposit = challenge_deposit;
+        self.tables.insert(&table_name, &table);
+        self.bank.end_record();
+    }
+
+    pub fn get_params_proposal(&self, id: u64) -> ParamsProposal {
+        self.params_proposals
+            .get(&id)
+            .expect("No proposal for given id")
+    }
+
+    /// Proposes to replace the parameters of the table with `params`.
+    /// Must have challenge deposit amount of $TCR to proceed.
+    /// Returns id of the proposal.
+    #[payable]
+    pub fn propose_params(&mut self, table_name: String, params: Params) -> u64 {
+        params.assert_valid();
+        self.bank.start_record();
+        let current_params = self.get_table(&table_name).params;
+        let deposit = current_params.challenge_deposit.0;
+        self.lock_stake(&env::predecessor_account_id(), deposit);
+        let id = self.last_proposal_id;
+        self.last_proposal_id += 1;
+        self.params_proposals.insert(
+            &id,
+            &ParamsProposal {
+                proposer: env::predecessor_account_id(),
+                table_name,
+                params,
+                deposit: deposit.into(),
+                votes: HashMap::default(),
+                end_time: env::block_timestamp() + current_params.challenge_duration,
+                vote_for: U128(0),
+                vote_against: U128(0),
+                finalized: false,
+            },
+        );
+        self.bank.end_record();
+        id
+    }
+
+    /// Vote on the proposal with `amount` of $TCR, which is locked until the proposal is finalized.
+    #[payable]
+    pub fn params_vote(&mut self, id: u64, approve: bool, amount: U128) {
+        let amount: Balance = amount.into();
+        assert!(amount > 0, "Vote must lock some $TCR");
+        self.bank.start_record();
+        let mut proposal = self.get_params_proposal(id);
+        assert!(
+            proposal.end_time > env::block_timestamp(),
+            "Voting period has passed"
+        );
+        if proposal.votes.contains_key(&env::predecessor_account_id()) {
+            env::panic(b"Already voted");
+        }
+        self.lock_stake(&env::predecessor_account_id(), amount);
+        proposal
+            .votes
+            .insert(env::predecessor_account_id(), (approve, amount.into()));
+        if approve {
+            proposal.vote_for.0 += amount;
+        } else {
+            proposal.vote_against.0 += amount;
+        }
+        self.params_proposals.insert(&id, &proposal);
+        self.bank.end_record();
+    }
+
+    /// Anyone can call to finalize the proposal after the voting period.
+    /// Applies the parameters if the proposal passed the quorum and returns the deposit if it did.
+    /// Voters then unlock their $TCR with `params_unlock_tokens`.
+    #[payable]
+    pub fn finalize_params(&mut self, id: u64) {
+        self.bank.start_record();
+        let mut proposal = self.get_params_proposal(id);
+        assert!(!proposal.finalized, "Proposal is already finalized");
+        if proposal.end_time > env::block_timestamp() {
+            env::panic(b"Voting period didn't pass yet");
+        }
+        let mut table = self.get_table(&proposal.table_name);
+        if table
+            .params
+            .is_passed(proposal.vote_for.0, proposal.vote_against.0)
+        {
+            table.params = proposal.params.clone();
+            self.tables.insert(&proposal.table_name, &table);
+            self.unlock_stake(&proposal.proposer, proposal.deposit.0);
+            env::log(b"Parameters changed");
+        } else {
+            self.burn_stake(proposal.deposit.0);
+            env::log(b"Parameters proposal rejected");
+        }
+        proposal.finalized = true;
+        if proposal.votes.is_empty() {
+            self.params_proposals.remove(&id);
+        } else {
+            self.params_proposals.insert(&id, &proposal);
+        }
+        self.bank.end_record();
+    }
+
+    /// Voter unlocks $TCR it voted with on finalized proposal. Returns unlocked amount.
+    #[payable]
+    pub fn params_unlock_tokens(&mut self, id: u64) -> U128 {
+        self.bank.start_record();
+        let mut proposal = self.get_params_proposal(id);
+        assert!(proposal.finalized, "Proposal is not finalized");
+        let (_, amount) = proposal
+            .votes
+            .remove(&env::predecessor_account_id())
+            .expect("No tokens to unlock");
+        if proposal.votes.is_empty() {
+            self.params_proposals.remove(&id);
+        } else {
+            self.params_proposals.insert(&id, &proposal);
+        }
+        self.unlock_stake(&env::predecessor_account_id(), amount.0);
+        self.bank.end_record();
+        amount
+    }
+}
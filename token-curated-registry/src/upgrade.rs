@@ -0,0 +1,132 @@
+//! Staged code upgrades by the owner of the registry, via `near_lib::upgrade::Upgradable`.
+//! Code that changes the layout of the registry is staged with `stage_code_with_migration`,
+//! so that `deploy_code` calls its `migrate`.
+
+use near_lib::types::WrappedDuration;
+use near_lib::upgrade::Upgradable;
+use near_sdk::json_types::{ValidAccountId, U64};
+
+use crate::*;
+
+const UPGRADE_CODE_KEY: &[u8] = b"upgrade";
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Upgrade {
+    pub owner: AccountId,
+    pub staging_duration: Duration,
+    pub staging_timestamp: Timestamp,
+    /// Whether `deploy_code` calls `migrate` on the staged code.
+    pub staging_migration: bool,
+}
+
+impl Upgrade {
+    pub fn new(owner: AccountId, staging_duration: Duration) -> Self {
+        Self {
+            owner,
+            staging_duration,
+            staging_timestamp: 0,
+            staging_migration: false,
+        }
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner.clone()
+    }
+
+    pub fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner,
+            "Only owner can call this method"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Upgradable for TokenCuratedRegistry {
+    fn get_staging_duration(&self) -> WrappedDuration {
+        self.upgrade.staging_duration.into()
+    }
+
+    /// Stages code that keeps the layout of the registry, to be deployed not earlier than `timestamp`.
+    /// Attached deposit must cover the storage of the code.
+    #[payable]
+    fn stage_code(
+        &mut self,
+        #[serializer(borsh)] code: Vec<u8>,
+        #[serializer(borsh)] timestamp: Timestamp,
+    ) {
+        self.internal_stage_code(code, timestamp, false);
+    }
+
+    /// Deploys staged code, calling its `migrate` if it was staged with `stage_code_with_migration`.
+    /// Storage of the code is refunded to the owner.
+    fn deploy_code(&mut self) -> Promise {
+        self.upgrade.assert_owner();
+        self.bank.start_record();
+        assert!(
+            env::block_timestamp() >= self.upgrade.staging_timestamp,
+            "Deploy code too early: staging ends on {}",
+            self.upgrade.staging_timestamp
+        );
+        let code = env::storage_read(UPGRADE_CODE_KEY).expect("No upgrade code available");
+        env::storage_remove(UPGRADE_CODE_KEY);
+        let migration = std::mem::take(&mut self.upgrade.staging_migration);
+        self.bank.end_record();
+        let promise = Promise::new(env::current_account_id()).deploy_contract(code);
+        if migration {
+            promise.function_call(b"migrate".to_vec(), vec![], NO_DEPOSIT, MIGRATE_GAS)
+        } else {
+            promise
+        }
+    }
+}
+
+#[near_bindgen]
+impl TokenCuratedRegistry {
+    pub fn get_owner(&self) -> AccountId {
+        self.upgrade.get_owner()
+    }
+
+    pub fn set_owner(&mut self, owner: ValidAccountId) {
+        self.upgrade.assert_owner();
+        self.upgrade.owner = owner.into();
+    }
+
+    /// Returns time when the staged code can be deployed.
+    pub fn get_staging_timestamp(&self) -> U64 {
+        self.upgrade.staging_timestamp.into()
+    }
+
+    /// Whether the staged code is deployed together with a call to its `migrate`.
+    pub fn is_staging_migration(&self) -> bool {
+        self.upgrade.staging_migration
+    }
+
+    /// Same as `stage_code`, for code that changes the layout of the registry.
+    /// Such code must have `#[init(ignore_state)] migrate`, which `deploy_code` calls right after deploying it.
+    #[payable]
+    pub fn stage_code_with_migration(
+        &mut self,
+        #[serializer(borsh)] code: Vec<u8>,
+        #[serializer(borsh)] timestamp: Timestamp,
+    ) {
+        self.internal_stage_code(code, timestamp, true);
+    }
+}
+
+impl TokenCuratedRegistry {
+    fn internal_stage_code(&mut self, code: Vec<u8>, timestamp: Timestamp, migration: bool) {
+        self.upgrade.assert_owner();
+        self.bank.start_record();
+        assert!(
+            env::block_timestamp() + self.upgrade.staging_duration <= timestamp,
+            "Timestamp must be later than staging duration"
+        );
+        // Writes directly into storage to avoid reading the code with the rest of the state.
+        env::storage_write(UPGRADE_CODE_KEY, &code);
+        self.upgrade.staging_timestamp = timestamp;
+        self.upgrade.staging_migration = migration;
+        self.bank.end_record();
+    }
+}
@@ -0,0 +1,54 @@
+//! Simulation of `res/token_curated_registry.wasm`, built with `build.sh`.
+//! Unlike unit tests, the state is written to storage after every call.
+
+use near_sdk::serde_json::json;
+use near_sdk_sim::{init_simulator, to_yocto, DEFAULT_GAS, STORAGE_AMOUNT};
+
+near_sdk_sim::lazy_static_include::lazy_static_include_bytes! {
+    TCR_WASM_BYTES => "res/token_curated_registry.wasm",
+}
+
+/// First state changing call after `new` goes through `Bank::start_record`.
+#[test]
+fn test_new_and_create_table() {
+    let root = init_simulator(None);
+    let registry = root.deploy(&TCR_WASM_BYTES, "tcr".to_string(), STORAGE_AMOUNT);
+    root.call(
+        registry.account_id(),
+        "new",
+        &json!({
+            "owner": root.account_id(),
+            "metadata": {
+                "spec": "ft-1.0.0",
+                "name": "Token Curated Registry",
+                "symbol": "TCR",
+                "decimals": 24,
+            },
+        })
+        .to_string()
+        .into_bytes(),
+        DEFAULT_GAS,
+        0,
+    )
+    .assert_success();
+    root.call(
+        registry.account_id(),
+        "create_table",
+        &json!({ "name": "tokens", "schema": {} })
+            .to_string()
+            .into_bytes(),
+        DEFAULT_GAS,
+        to_yocto("1"),
+    )
+    .assert_success();
+    root.call(
+        registry.account_id(),
+        "create_table",
+        &json!({ "name": "pools", "schema": {} })
+            .to_string()
+            .into_bytes(),
+        DEFAULT_GAS,
+        to_yocto("1"),
+    )
+    .assert_success();
+}
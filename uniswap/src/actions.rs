@@ -0,0 +1,223 @@
+//! Batched execution of swaps, liquidity operations and withdrawals over deposited balances.
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::PromiseResult;
+
+use crate::*;
+
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_WITHDRAW_TOKEN: Gas = 10_000_000_000_000;
+
+/// Single action in the `execute` batch.
+/// All amounts are taken from and returned into the sender's deposited balances.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Action {
+    /// Swaps `near_amount` of deposited NEAR into token.
+    SwapNearToToken {
+        near_amount: U128,
+        min_token_amount: U128,
+    },
+    /// Swaps `token_amount` of deposited token into NEAR.
+    SwapTokenToNear {
+        token_amount: U128,
+        min_near_amount: U128,
+    },
+    /// Adds `near_amount` of deposited NEAR and matching amount of deposited token to the pool.
+    AddLiquidity {
+        near_amount: U128,
+        max_token_amount: U128,
+    },
+    /// Burns `shares` and returns NEAR and token into deposits.
+    RemoveLiquidity {
+        shares: U128,
+        min_near_amount: U128,
+        min_token_amount: U128,
+    },
+    /// Sends `amount` of deposited NEAR to the sender after all actions are done.
+    WithdrawNear { amount: U128 },
+    /// Sends `amount` of deposited token to the sender after all actions are done.
+    /// If the transfer fails, the amount is credited back to the deposits.
+    WithdrawToken { amount: U128 },
+}
+
+#[ext_contract(ext_withdraw_self)]
+trait WithdrawCallbacks {
+    fn on_withdraw_token(&mut self, account_id: AccountId, amount: U128);
+}
+
+/// Balances of the sender, loaded once before the batch and written back once after it.
+struct AccountBalances {
+    near: Balance,
+    token: Balance,
+    shares: Balance,
+    near_withdraw: Balance,
+    token_withdraw: Balance,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Executes given list of actions sequentially on behalf of the sender.
+    /// Attached NEAR is added to the sender's deposit before the first action.
+    /// If any action fails, the whole batch fails and nothing is changed.
+    #[payable]
+    pub fn execute(&mut self, actions: Vec<Action>) {
+        let sender_id = env::predecessor_account_id();
+        let mut balances = AccountBalances {
+            near: self.near_balances.get(&sender_id).unwrap_or(0) + env::attached_deposit(),
+            token: self.token_balances.get(&sender_id).unwrap_or(0),
+            shares: self.shares.get(&sender_id).unwrap_or(0),
+            near_withdraw: 0,
+            token_withdraw: 0,
+        };
+        for action in actions {
+            self.internal_execute_action(&mut balances, action);
+        }
+        set_in_collection(&mut self.near_balances, &sender_id, balances.near);
+        set_in_collection(&mut self.token_balances, &sender_id, balances.token);
+        set_in_collection(&mut self.shares, &sender_id, balances.shares);
+        if balances.near_withdraw > 0 {
+            Promise::new(sender_id.clone()).transfer(balances.near_withdraw);
+        }
+        if balances.token_withdraw > 0 {
+            ext_fungible_token::ft_transfer(
+                sender_id.clone().try_into().unwrap(),
+                U128(balances.token_withdraw),
+                None,
+                &self.token_account_id,
+                1,
+                GAS_FOR_FT_TRANSFER,
+            )
+            .then(ext_withdraw_self::on_withdraw_token(
+                sender_id,
+                U128(balances.token_withdraw),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_ON_WITHDRAW_TOKEN,
+            ));
+        }
+    }
+
+    /// Callback after sending withdrawn token to the account.
+    /// Credits the amount back to the account's deposits if the transfer failed (e.g. not registered).
+    pub fn on_withdraw_token(&mut self, account_id: AccountId, amount: U128) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            env::log(format!("Failed to withdraw {} token to {}", amount.0, account_id).as_bytes());
+            add_to_collection(&mut self.token_balances, &account_id, amount.0);
+        }
+    }
+}
+
+impl Contract {
+    /// Applies single action to the pool and given in-memory balances.
+    fn internal_execute_action(&mut self, balances: &mut AccountBalances, action: Action) {
+        match action {
+            Action::SwapNearToToken {
+                near_amount,
+                min_token_amount,
+            } => {
+                let near_amount: Balance = near_amount.into();
+                assert!(near_amount <= balances.near, "ERR_NOT_ENOUGH_NEAR");
+                let (tokens_bought, _) = self.get_swap_price(near_amount, true);
+                assert!(tokens_bought >= min_token_amount.into(), "ERR_MIN_AMOUNT");
+                self.record_swap(
+                    near_amount,
+                    self.near_amount,
+                    self.token_amount,
+                    tokens_bought,
+                );
+                self.near_amount += near_amount;
+                self.token_amount -= tokens_bought;
+                balances.near -= near_amount;
+                balances.token += tokens_bought;
+            }
+            Action::SwapTokenToNear {
+                token_amount,
+                min_near_amount,
+            } => {
+                let token_amount: Balance = token_amount.into();
+                assert!(token_amount <= balances.token, "ERR_NOT_ENOUGH_TOKEN");
+                let (near_bought, _) = self.get_swap_price(token_amount, false);
+                assert!(near_bought >= min_near_amount.into(), "ERR_MIN_AMOUNT");
+                self.record_swap(
+                    token_amount,
+                    self.token_amount,
+                    self.near_amount,
+                    near_bought,
+                );
+                self.token_amount += token_amount;
+                self.near_amount -= near_bought;
+                balances.token -= token_amount;
+                balances.near += near_bought;
+            }
+            Action::AddLiquidity {
+                near_amount,
+                max_token_amount,
+            } => {
+                let near_amount: Balance = near_amount.into();
+                let max_token_amount: Balance = max_token_amount.into();
+                assert!(near_amount > 0, "ERR_ZERO_AMOUNT");
+                assert!(near_amount <= balances.near, "ERR_NOT_ENOUGH_NEAR");
+                let (token_amount, liquidity_minted) = if self.shares_total_supply > 0 {
+                    (
+                        mul_div(near_amount, self.token_amount, self.near_amount),
+                        mul_div(near_amount, self.shares_total_supply, self.near_amount),
+                    )
+                } else {
+                    (max_token_amount, near_amount)
+                };
+                assert!(token_amount <= max_token_amount, "ERR_MAX_TOKEN_AMOUNT");
+                assert!(token_amount <= balances.token, "ERR_NOT_ENOUGH_TOKEN");
+                self.near_amount += near_amount;
+                self.token_amount += token_amount;
+                self.shares_total_supply += liquidity_minted;
+                balances.near -= near_amount;
+                balances.token -= token_amount;
+                balances.shares += liquidity_minted;
+            }
+            Action::RemoveLiquidity {
+                shares,
+                min_near_amount,
+                min_token_amount,
+            } => {
+                let shares: Balance = shares.into();
+                assert!(shares <= balances.shares, "ERR_NOT_ENOUGH_SHARES");
+                let (near_amount, token_amount) =
+                    self.internal_burn_shares(shares, min_near_amount, min_token_amount);
+                balances.shares -= shares;
+                balances.near += near_amount;
+                balances.token += token_amount;
+            }
+            Action::WithdrawNear { amount } => {
+                let amount: Balance = amount.into();
+                assert!(amount <= balances.near, "ERR_NOT_ENOUGH_NEAR");
+                balances.near -= amount;
+                balances.near_withdraw += amount;
+            }
+            Action::WithdrawToken { amount } => {
+                let amount: Balance = amount.into();
+                assert!(amount <= balances.token, "ERR_NOT_ENOUGH_TOKEN");
+                balances.token -= amount;
+                balances.token_withdraw += amount;
+            }
+        }
+    }
+}
+
+/// Sets given amount for the account in the collection, removing the record if amount is zero.
+fn set_in_collection(
+    c: &mut LookupMap<AccountId, Balance>,
+    account_id: &AccountId,
+    amount: Balance,
+) {
+    if amount > 0 {
+        c.insert(account_id, &amount);
+    } else {
+        c.remove(account_id);
+    }
+}
@@ -1,31 +1,41 @@
 use std::convert::TryInto;
 
+use near_lib::math::{mul_div, U256};
+use near_lib::simulation::SimulationResult;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::LookupMap;
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{
     env, ext_contract, near_bindgen, serde_json, AccountId, Balance, Gas, PanicOnDefault, Promise,
 };
-use uint::construct_uint;
+
+pub use crate::actions::Action;
+pub use crate::sister::SisterGap;
+use crate::sister::SisterQuote;
+pub use crate::stats::SlippageStats;
+
+mod actions;
+mod migrate;
+mod sister;
+mod stats;
 
 const FEE_DIVISOR: u32 = 1_000;
 const NO_DEPOSIT: Balance = 0;
 const GAS_FOR_SWAP: Gas = 10_000_000_000_000;
 
-construct_uint! {
-    /// 256-bit unsigned integer.
-    pub struct U256(4);
-}
-
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 struct Contract {
+    /// Account that can configure the exchange, usually the factory that created it.
+    owner_id: AccountId,
     /// Account for the token.
     token_account_id: AccountId,
     /// Fee.
     fee: u32,
     /// Balances of NEAR that were deposited but not consumed yet.
     near_balances: LookupMap<AccountId, Balance>,
+    /// Balances of token that were deposited but not consumed yet.
+    token_balances: LookupMap<AccountId, Balance>,
     /// Shares of the pool by liquidity providers.
     shares: LookupMap<AccountId, Balance>,
     shares_total_supply: Balance,
@@ -33,6 +43,14 @@ struct Contract {
     near_amount: Balance,
     /// How much token this contract has.
     token_amount: Balance,
+    /// Slippage of the executed swaps.
+    slippage_stats: SlippageStats,
+    /// Exchange of the same token to keep the price coherent with.
+    sister_account_id: Option<AccountId>,
+    /// Last synced reserves of the sister exchange.
+    sister_quote: Option<SisterQuote>,
+    /// Fee for swaps that reduce the price gap with the sister exchange.
+    arb_fee: u32,
 }
 
 #[near_bindgen]
@@ -42,13 +60,19 @@ impl Contract {
         assert!(!env::state_exists(), "ERR_CONTRACT_IS_INITIALIZED");
         assert!(fee < FEE_DIVISOR, "ERR_FEE_TOO_LARGE");
         Self {
+            owner_id: env::predecessor_account_id(),
             token_account_id: token_account_id.into(),
             fee,
             near_balances: LookupMap::new(b"t".to_vec()),
+            token_balances: LookupMap::new(b"b".to_vec()),
             shares: LookupMap::new(b"s".to_vec()),
             shares_total_supply: 0,
             near_amount: 0,
             token_amount: 0,
+            slippage_stats: SlippageStats::default(),
+            sister_account_id: None,
+            sister_quote: None,
+            arb_fee: fee,
         }
     }
 
@@ -70,17 +94,11 @@ impl Contract {
         min_token_amount: U128,
     ) -> Promise {
         let shares_amount: u128 = shares.into();
-        assert!(shares_amount > 0 && self.shares_total_supply > 0);
-        let near_amount = (U256::from(shares_amount) * U256::from(self.near_amount)
-            / U256::from(self.shares_total_supply))
-        .as_u128();
-        let token_amount = (U256::from(shares_amount) * U256::from(self.token_amount)
-            / U256::from(self.shares_total_supply))
-        .as_u128();
-        assert!(near_amount >= min_near_amount.into() && token_amount >= min_token_amount.into());
         let account_id = env::predecessor_account_id();
         let prev_amount = self.shares.get(&account_id).unwrap_or(0);
         assert!(prev_amount >= shares_amount, "ERR_NOT_ENOUGH_SHARES");
+        let (near_amount, token_amount) =
+            self.internal_burn_shares(shares_amount, min_near_amount, min_token_amount);
         // TODO: don't allow to withdraw and leave less than required for storage.
         if prev_amount == shares_amount {
             self.shares.remove(&account_id);
@@ -88,9 +106,6 @@ impl Contract {
             self.shares
                 .insert(&account_id, &(prev_amount - shares_amount));
         }
-        self.shares_total_supply -= shares_amount;
-        self.near_amount -= near_amount;
-        self.token_amount -= token_amount;
         Promise::new(account_id.clone()).transfer(near_amount);
         // TODO: handle error on transfer.
         ext_fungible_token::ft_transfer(
@@ -103,15 +118,44 @@ impl Contract {
         )
     }
 
+    /// Burns given number of shares and returns how much NEAR and token they were worth.
+    /// Doesn't touch the balance of shares of any account, this is up to the caller.
+    fn internal_burn_shares(
+        &mut self,
+        shares_amount: Balance,
+        min_near_amount: U128,
+        min_token_amount: U128,
+    ) -> (Balance, Balance) {
+        assert!(shares_amount > 0 && self.shares_total_supply > 0);
+        let near_amount = mul_div(shares_amount, self.near_amount, self.shares_total_supply);
+        let token_amount = mul_div(shares_amount, self.token_amount, self.shares_total_supply);
+        assert!(near_amount >= min_near_amount.into() && token_amount >= min_token_amount.into());
+        self.shares_total_supply -= shares_amount;
+        self.near_amount -= near_amount;
+        self.token_amount -= token_amount;
+        (near_amount, token_amount)
+    }
+
     /// Pricing between two reserves given input amount.
     fn get_input_price(
         &self,
         input_amount: Balance,
         input_reserve: Balance,
         output_reserve: Balance,
+    ) -> Balance {
+        self.get_input_price_with_fee(input_amount, input_reserve, output_reserve, self.fee)
+    }
+
+    /// Pricing between two reserves given input amount and fee.
+    fn get_input_price_with_fee(
+        &self,
+        input_amount: Balance,
+        input_reserve: Balance,
+        output_reserve: Balance,
+        fee: u32,
     ) -> Balance {
         assert!(input_reserve > 0 && output_reserve > 0, "ERR_NO_LIQUIDITY");
-        let input_amount_with_fee = U256::from(input_amount) * U256::from(FEE_DIVISOR - self.fee);
+        let input_amount_with_fee = U256::from(input_amount) * U256::from(FEE_DIVISOR - fee);
         ((input_amount_with_fee * U256::from(output_reserve))
             / (U256::from(input_reserve) * U256::from(FEE_DIVISOR) + input_amount_with_fee))
             .as_u128()
@@ -143,11 +187,48 @@ impl Contract {
         self.get_output_price(amount, self.token_amount, self.near_amount)
     }
 
+    /// Returns fee charged when swapping given amount with given fee.
+    fn get_fee_amount(&self, amount: Balance, fee: u32) -> Balance {
+        mul_div(amount, fee as u128, FEE_DIVISOR as u128)
+    }
+
+    /// Simulates `swap_near_to_token` with given attached NEAR without changing state.
+    pub fn simulate_swap_near_to_token(&self, amount: U128, min_amount: U128) -> SimulationResult {
+        near_lib::simulate!({
+            let amount: Balance = amount.into();
+            let (tokens_bought, fee) = self.get_swap_price(amount, true);
+            assert!(tokens_bought >= min_amount.into(), "ERR_MIN_AMOUNT");
+            SimulationResult {
+                amount_out: tokens_bought.into(),
+                fees: vec![self.get_fee_amount(amount, fee).into()],
+            }
+        })
+    }
+
+    /// Simulates swapping given amount of token to NEAR via `ft_transfer_call` without changing state.
+    pub fn simulate_swap_token_to_near(&self, amount: U128, min_amount: U128) -> SimulationResult {
+        near_lib::simulate!({
+            let amount: Balance = amount.into();
+            let (near_bought, fee) = self.get_swap_price(amount, false);
+            assert!(near_bought >= min_amount.into(), "ERR_MIN_AMOUNT");
+            SimulationResult {
+                amount_out: near_bought.into(),
+                fees: vec![self.get_fee_amount(amount, fee).into()],
+            }
+        })
+    }
+
     #[payable]
     pub fn swap_near_to_token(&mut self, min_amount: Balance) -> Balance {
         let payed_amount = env::attached_deposit();
-        let tokens_bought = self.get_input_price(payed_amount, self.near_amount, self.token_amount);
+        let (tokens_bought, _) = self.get_swap_price(payed_amount, true);
         assert!(tokens_bought >= min_amount, "ERR_MIN_AMOUNT");
+        self.record_swap(
+            payed_amount,
+            self.near_amount,
+            self.token_amount,
+            tokens_bought,
+        );
         self.near_amount += payed_amount;
         self.token_amount -= tokens_bought;
         ext_fungible_token::ft_transfer(
@@ -168,8 +249,14 @@ impl Contract {
         token_amount: Balance,
         min_near_amount: Balance,
     ) -> Promise {
-        let near_bought = self.get_input_price(token_amount, self.token_amount, self.near_amount);
+        let (near_bought, _) = self.get_swap_price(token_amount, false);
         assert!(near_bought >= min_near_amount, "ERR_MIN_AMOUNT");
+        self.record_swap(
+            token_amount,
+            self.token_amount,
+            self.near_amount,
+            near_bought,
+        );
         self.near_amount -= near_bought;
         self.token_amount -= token_amount;
         Promise::new(sender_id.clone()).transfer(near_bought)
@@ -212,6 +299,14 @@ impl Contract {
 #[ext_contract(ext_fungible_token)]
 trait FungibleToken {
     fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>);
+
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> U128;
 }
 
 trait FungibleTokenReceiver {
@@ -246,6 +341,9 @@ impl FungibleTokenReceiver for Contract {
         );
         if msg == "liquidity" {
             self.finish_add_liquidity(sender_id.as_ref(), amount)
+        } else if msg == "deposit" {
+            add_to_collection(&mut self.token_balances, sender_id.as_ref(), amount.into());
+            U128(0)
         } else {
             self.swap_token_to_near(
                 sender_id.as_ref(),
@@ -295,8 +393,16 @@ mod tests {
         assert_eq!(price, 2507522567703109327983951);
 
         // Swap 1N for tokens, check that pool has 1N more and result tokens less.
+        let simulation = contract.simulate_swap_near_to_token(one_near.into(), 1.into());
         testing_env!(context.attached_deposit(one_near).build());
         let result = contract.swap_near_to_token(1);
+        assert_eq!(simulation.amount_out, result.into());
+        assert_eq!(simulation.fees, vec![(3 * one_near / 1_000).into()]);
+        let stats = contract.get_slippage_stats();
+        assert_eq!(stats.num_swaps, 1);
+        assert_eq!(stats.average_bps, stats.last_bps);
+        // Slippage includes the 0.3% fee.
+        assert!(stats.last_bps > 30);
 
         assert_eq!(contract.near_amount, 6 * one_near);
         assert_eq!(contract.token_amount, 10 * one_near - result);
@@ -307,4 +413,163 @@ mod tests {
         assert_eq!(contract.near_amount, 0);
         assert_eq!(contract.token_amount, 0);
     }
+
+    #[test]
+    fn test_execute() {
+        let one_near = 10u128.pow(24);
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(1), 3);
+        contract.ft_on_transfer(
+            accounts(0).into(),
+            (20 * one_near).into(),
+            "deposit".to_string(),
+        );
+
+        // Add liquidity, swap part of NEAR for tokens and withdraw it all in one call.
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(6 * one_near)
+            .build());
+        contract.execute(vec![
+            Action::AddLiquidity {
+                near_amount: U128(5 * one_near),
+                max_token_amount: U128(10 * one_near),
+            },
+            Action::SwapNearToToken {
+                near_amount: U128(one_near),
+                min_token_amount: U128(1),
+            },
+            Action::WithdrawToken {
+                amount: U128(10 * one_near),
+            },
+        ]);
+        assert_eq!(contract.near_amount, 6 * one_near);
+        assert_eq!(contract.shares_balance(accounts(0)), U128(5 * one_near));
+        assert_eq!(contract.near_balances.get(&accounts(0).into()), None);
+        assert!(contract.token_balances.get(&accounts(0).into()).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_migrate_to_multiswap() {
+        let one_near = 10u128.pow(24);
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(1), 3);
+        testing_env!(context.attached_deposit(5 * one_near).build());
+        contract.add_liquidity();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_on_transfer(
+            accounts(0).into(),
+            (10 * one_near).into(),
+            "liquidity".to_string(),
+        );
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(0)
+            .build());
+        contract.migrate_to_multiswap(accounts(2), 0);
+        assert_eq!(contract.shares_balance(accounts(0)), U128(0));
+        assert_eq!(contract.near_amount, 0);
+        assert_eq!(contract.token_amount, 0);
+
+        // Whatever fails to migrate is credited to the deposits.
+        contract.internal_refund_migration(&accounts(0).into(), one_near, 0);
+        assert_eq!(
+            contract.near_balances.get(&accounts(0).into()),
+            Some(one_near)
+        );
+        assert_eq!(contract.token_balances.get(&accounts(0).into()), None);
+    }
+
+    #[test]
+    fn test_sister_arb_fee() {
+        let one_near = 10u128.pow(24);
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(1), 3);
+        testing_env!(context.attached_deposit(5 * one_near).build());
+        contract.add_liquidity();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_on_transfer(
+            accounts(0).into(),
+            (10 * one_near).into(),
+            "liquidity".to_string(),
+        );
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_sister_exchange(Some(accounts(2)), 0);
+        assert!(contract.get_sister_gap().is_none());
+        contract.internal_verify_sister_token(Some(accounts(1).into()));
+        contract.internal_set_sister(Some(accounts(2).into()), 0);
+        // Sister gives 1 token per NEAR, while this exchange gives 2.
+        contract.sister_quote = Some(SisterQuote {
+            near_amount: one_near,
+            token_amount: one_near,
+            timestamp: 0,
+        });
+        assert_eq!(contract.get_sister_gap().unwrap().gap_bps, 10_000);
+
+        // Buying token reduces the gap, so no fee is charged.
+        assert_eq!(
+            contract.get_swap_price(one_near, true),
+            (
+                contract.get_input_price_with_fee(one_near, 5 * one_near, 10 * one_near, 0),
+                0
+            )
+        );
+        assert_eq!(
+            contract
+                .simulate_swap_near_to_token(one_near.into(), 1.into())
+                .fees,
+            vec![U128(0)]
+        );
+        // Selling token increases the gap and pays the full fee.
+        assert_eq!(
+            contract.get_swap_price(one_near, false),
+            (
+                contract.get_input_price(one_near, 10 * one_near, 5 * one_near),
+                3
+            )
+        );
+
+        // Stale quote doesn't give the reduced fee.
+        testing_env!(context.block_timestamp(61 * 1_000_000_000).build());
+        assert!(!contract.get_sister_gap().unwrap().is_fresh);
+        assert_eq!(
+            contract.get_swap_price(one_near, true),
+            (
+                contract.get_input_price(one_near, 5 * one_near, 10 * one_near),
+                3
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_SISTER_WRONG_TOKEN")]
+    fn test_sister_wrong_token() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let contract = Contract::new(accounts(1), 3);
+        contract.internal_verify_sister_token(Some(accounts(3).into()));
+    }
+
+    #[test]
+    fn test_sister_gap_saturates() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let contract = Contract::new(accounts(1), 3);
+        let quote = SisterQuote {
+            near_amount: u128::MAX,
+            token_amount: 1,
+            timestamp: 0,
+        };
+        assert_eq!(contract.gap_bps(&quote, 1, 1 << 100), Some(i64::MAX));
+    }
 }
@@ -0,0 +1,402 @@
+//! Migration of liquidity into a pool of the multiswap exchange.
+//! Shares of the LP are burnt here, NEAR is wrapped into the other token of the multiswap pool and both
+//! tokens are deposited into this contract's account on the multiswap exchange via `ft_transfer_call`,
+//! from where they are added to the pool on behalf of the LP via `add_liquidity_for`.
+//! Whatever doesn't arrive is credited to the LP's deposits here, see `execute`. If adding liquidity fails,
+//! the deposited tokens are moved to the LP's deposits on the multiswap exchange instead.
+//! The LP chooses the exchange and the pool, so it's up to the LP to check that they are genuine.
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::PromiseResult;
+
+use crate::*;
+
+const GAS_FOR_GET_POOL: Gas = 5_000_000_000_000;
+const GAS_FOR_STORAGE_BALANCE_OF: Gas = 5_000_000_000_000;
+const GAS_FOR_NEAR_DEPOSIT: Gas = 5_000_000_000_000;
+const GAS_FOR_NEAR_WITHDRAW: Gas = 5_000_000_000_000;
+const GAS_FOR_FT_TRANSFER_CALL: Gas = 30_000_000_000_000;
+const GAS_FOR_ADD_LIQUIDITY_FOR: Gas = 20_000_000_000_000;
+const GAS_FOR_DEPOSIT_BATCH: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_MIGRATE_NEAR_WITHDRAWN: Gas = 5_000_000_000_000;
+const GAS_FOR_ON_MIGRATE_ADDED: Gas = 10_000_000_000_000 + GAS_FOR_DEPOSIT_BATCH;
+const GAS_FOR_ON_MIGRATE_DEPOSITED: Gas = 10_000_000_000_000
+    + GAS_FOR_NEAR_WITHDRAW
+    + GAS_FOR_ON_MIGRATE_NEAR_WITHDRAWN
+    + GAS_FOR_ADD_LIQUIDITY_FOR
+    + GAS_FOR_ON_MIGRATE_ADDED;
+const GAS_FOR_ON_MIGRATE_WRAPPED: Gas =
+    10_000_000_000_000 + 2 * GAS_FOR_FT_TRANSFER_CALL + GAS_FOR_ON_MIGRATE_DEPOSITED;
+const GAS_FOR_ON_MULTISWAP_POOL: Gas =
+    10_000_000_000_000 + GAS_FOR_NEAR_DEPOSIT + GAS_FOR_ON_MIGRATE_WRAPPED;
+
+/// Part of the multiswap pool info used for the migration.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MultiswapPoolInfo {
+    pub token_account_ids: Vec<AccountId>,
+}
+
+/// Transfer between deposits on the multiswap exchange, see its `deposit_batch`.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MultiswapTransfer {
+    pub token_id: AccountId,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+}
+
+/// Migration of the LP into the multiswap pool, passed between the callbacks.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Migration {
+    pub account_id: AccountId,
+    pub exchange_id: AccountId,
+    pub pool_id: u64,
+    /// Wrapped NEAR, the other token of the pool.
+    pub wrap_near_id: AccountId,
+    /// Whether wrapped NEAR is the first token of the pool.
+    pub near_first: bool,
+}
+
+#[ext_contract(ext_multiswap)]
+trait Multiswap {
+    fn get_pool(&self, pool_id: u64) -> Option<MultiswapPoolInfo>;
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<serde_json::Value>;
+
+    fn add_liquidity_for(&mut self, pool_id: u64, amounts: Vec<U128>, account_id: AccountId);
+
+    fn deposit_batch(&mut self, transfers: Vec<MultiswapTransfer>);
+}
+
+#[ext_contract(ext_wrap_near)]
+trait WrapNear {
+    fn near_deposit(&mut self);
+
+    fn near_withdraw(&mut self, amount: U128);
+}
+
+#[ext_contract(ext_migrate_self)]
+trait SelfCallbacks {
+    fn on_multiswap_pool(
+        &mut self,
+        account_id: AccountId,
+        exchange_id: AccountId,
+        pool_id: u64,
+        near_amount: U128,
+        token_amount: U128,
+    );
+
+    fn on_migrate_wrapped(&mut self, migration: Migration, near_amount: U128, token_amount: U128);
+
+    fn on_migrate_deposited(&mut self, migration: Migration, near_amount: U128, token_amount: U128);
+
+    fn on_migrate_near_withdrawn(&mut self, account_id: AccountId, amount: U128);
+
+    fn on_migrate_added(&mut self, migration: Migration, near_amount: U128, token_amount: U128);
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Burns all shares of the sender and adds the NEAR and token they are worth to `pool_id` on the multiswap
+    /// `exchange_id` on behalf of the sender.
+    /// The pool must have exactly two tokens: this exchange's token and wrapped NEAR.
+    /// This exchange must be registered with the wrapped NEAR token and on the multiswap exchange,
+    /// and the sender must be registered on the multiswap exchange.
+    pub fn migrate_to_multiswap(&mut self, exchange_id: ValidAccountId, pool_id: u64) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let shares = self.shares.remove(&account_id).expect("ERR_NO_SHARES");
+        let (near_amount, token_amount) = self.internal_burn_shares(shares, U128(0), U128(0));
+        ext_multiswap::get_pool(pool_id, exchange_id.as_ref(), NO_DEPOSIT, GAS_FOR_GET_POOL)
+            .and(ext_multiswap::storage_balance_of(
+                account_id.clone(),
+                exchange_id.as_ref(),
+                NO_DEPOSIT,
+                GAS_FOR_STORAGE_BALANCE_OF,
+            ))
+            .then(ext_migrate_self::on_multiswap_pool(
+                account_id,
+                exchange_id.into(),
+                pool_id,
+                near_amount.into(),
+                token_amount.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_ON_MULTISWAP_POOL,
+            ))
+    }
+
+    /// Callback with the multiswap pool and the LP's registration there. Wraps NEAR into the other token of the pool.
+    pub fn on_multiswap_pool(
+        &mut self,
+        account_id: AccountId,
+        exchange_id: AccountId,
+        pool_id: u64,
+        near_amount: U128,
+        token_amount: U128,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        let is_registered = match env::promise_result(1) {
+            PromiseResult::Successful(value) => {
+                matches!(
+                    serde_json::from_slice::<Option<serde_json::Value>>(&value),
+                    Ok(Some(_))
+                )
+            }
+            _ => false,
+        };
+        if !is_registered {
+            env::log(b"ERR_NOT_REGISTERED");
+            self.internal_refund_migration(&account_id, near_amount.0, token_amount.0);
+            return;
+        }
+        let pool = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                serde_json::from_slice::<Option<MultiswapPoolInfo>>(&value)
+                    .ok()
+                    .flatten()
+            }
+            _ => None,
+        };
+        let wrap_near = pool.and_then(|pool| match pool.token_account_ids.as_slice() {
+            [a, b] if a == &self.token_account_id => Some((b.clone(), false)),
+            [a, b] if b == &self.token_account_id => Some((a.clone(), true)),
+            _ => None,
+        });
+        let (wrap_near_id, near_first) = match wrap_near {
+            Some(wrap_near) => wrap_near,
+            None => {
+                env::log(b"ERR_WRONG_POOL");
+                self.internal_refund_migration(&account_id, near_amount.0, token_amount.0);
+                return;
+            }
+        };
+        ext_wrap_near::near_deposit(&wrap_near_id, near_amount.0, GAS_FOR_NEAR_DEPOSIT).then(
+            ext_migrate_self::on_migrate_wrapped(
+                Migration {
+                    account_id,
+                    exchange_id,
+                    pool_id,
+                    wrap_near_id: wrap_near_id.clone(),
+                    near_first,
+                },
+                near_amount,
+                token_amount,
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_ON_MIGRATE_WRAPPED,
+            ),
+        );
+    }
+
+    /// Callback after wrapping NEAR. Deposits both tokens into this contract's account on the multiswap exchange.
+    pub fn on_migrate_wrapped(
+        &mut self,
+        migration: Migration,
+        near_amount: U128,
+        token_amount: U128,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            env::log(b"ERR_WRAP_NEAR");
+            self.internal_refund_migration(&migration.account_id, near_amount.0, token_amount.0);
+            return;
+        }
+        ext_fungible_token::ft_transfer_call(
+            migration.exchange_id.clone().try_into().unwrap(),
+            near_amount,
+            None,
+            String::new(),
+            &migration.wrap_near_id,
+            1,
+            GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .and(ext_fungible_token::ft_transfer_call(
+            migration.exchange_id.clone().try_into().unwrap(),
+            token_amount,
+            None,
+            String::new(),
+            &self.token_account_id,
+            1,
+            GAS_FOR_FT_TRANSFER_CALL,
+        ))
+        .then(ext_migrate_self::on_migrate_deposited(
+            migration,
+            near_amount,
+            token_amount,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_ON_MIGRATE_DEPOSITED,
+        ));
+    }
+
+    /// Callback after depositing into the multiswap exchange. Adds what arrived to the pool for the LP.
+    /// Token that was refunded is credited to the LP's deposits, wrapped NEAR that was refunded is unwrapped first.
+    pub fn on_migrate_deposited(
+        &mut self,
+        migration: Migration,
+        near_amount: U128,
+        token_amount: U128,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        let near_deposited = used_amount(0, near_amount.0);
+        let token_deposited = used_amount(1, token_amount.0);
+        let near_refund = near_amount.0 - near_deposited;
+        if near_refund > 0 {
+            ext_wrap_near::near_withdraw(
+                near_refund.into(),
+                &migration.wrap_near_id,
+                1,
+                GAS_FOR_NEAR_WITHDRAW,
+            )
+            .then(ext_migrate_self::on_migrate_near_withdrawn(
+                migration.account_id.clone(),
+                near_refund.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_ON_MIGRATE_NEAR_WITHDRAWN,
+            ));
+        }
+        self.internal_refund_migration(&migration.account_id, 0, token_amount.0 - token_deposited);
+        if near_deposited == 0 || token_deposited == 0 {
+            self.internal_move_migrated_deposits(&migration, near_deposited, token_deposited);
+            return;
+        }
+        let amounts = if migration.near_first {
+            vec![U128(near_deposited), U128(token_deposited)]
+        } else {
+            vec![U128(token_deposited), U128(near_deposited)]
+        };
+        ext_multiswap::add_liquidity_for(
+            migration.pool_id,
+            amounts,
+            migration.account_id.clone(),
+            &migration.exchange_id,
+            NO_DEPOSIT,
+            GAS_FOR_ADD_LIQUIDITY_FOR,
+        )
+        .then(ext_migrate_self::on_migrate_added(
+            migration,
+            near_deposited.into(),
+            token_deposited.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_ON_MIGRATE_ADDED,
+        ));
+    }
+
+    /// Callback after unwrapping wrapped NEAR that failed to migrate. Credits the NEAR to the LP's deposits
+    /// only if it was unwrapped, otherwise it stays wrapped on this contract.
+    pub fn on_migrate_near_withdrawn(&mut self, account_id: AccountId, amount: U128) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        if matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.internal_refund_migration(&account_id, amount.0, 0);
+        } else {
+            env::log(format!("ERR_UNWRAP_NEAR: {} for {}", amount.0, account_id).as_bytes());
+        }
+    }
+
+    /// Callback after adding liquidity to the multiswap pool for the LP.
+    /// If it failed, moves the deposited tokens to the LP's deposits on the multiswap exchange.
+    pub fn on_migrate_added(
+        &mut self,
+        migration: Migration,
+        near_amount: U128,
+        token_amount: U128,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            env::log(b"ERR_ADD_LIQUIDITY");
+            self.internal_move_migrated_deposits(&migration, near_amount.0, token_amount.0);
+            return;
+        }
+        env::log(
+            format!(
+                "Migrated {} NEAR and {} token of {} to pool {} of {}",
+                near_amount.0,
+                token_amount.0,
+                migration.account_id,
+                migration.pool_id,
+                migration.exchange_id
+            )
+            .as_bytes(),
+        );
+    }
+}
+
+impl Contract {
+    /// Credits NEAR and token that failed to migrate to the deposits of the LP.
+    pub(crate) fn internal_refund_migration(
+        &mut self,
+        account_id: &AccountId,
+        near_amount: Balance,
+        token_amount: Balance,
+    ) {
+        if near_amount > 0 {
+            add_to_collection(&mut self.near_balances, account_id, near_amount);
+        }
+        if token_amount > 0 {
+            add_to_collection(&mut self.token_balances, account_id, token_amount);
+        }
+    }
+
+    /// Moves tokens deposited on the multiswap exchange but not added to the pool to the LP's deposits there.
+    fn internal_move_migrated_deposits(
+        &mut self,
+        migration: &Migration,
+        near_amount: Balance,
+        token_amount: Balance,
+    ) {
+        let transfers: Vec<MultiswapTransfer> = vec![
+            (migration.wrap_near_id.clone(), near_amount),
+            (self.token_account_id.clone(), token_amount),
+        ]
+        .into_iter()
+        .filter(|(_, amount)| *amount > 0)
+        .map(|(token_id, amount)| MultiswapTransfer {
+            token_id,
+            receiver_id: migration.account_id.clone(),
+            amount: amount.into(),
+        })
+        .collect();
+        if !transfers.is_empty() {
+            ext_multiswap::deposit_batch(
+                transfers,
+                &migration.exchange_id,
+                1,
+                GAS_FOR_DEPOSIT_BATCH,
+            );
+        }
+    }
+}
+
+/// Amount used by the receiver of `ft_transfer_call` with given result index, capped by the transferred amount.
+fn used_amount(result_index: u64, amount: Balance) -> Balance {
+    match env::promise_result(result_index) {
+        PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+            .map(|used| std::cmp::min(used.0, amount))
+            .unwrap_or(0),
+        _ => 0,
+    }
+}
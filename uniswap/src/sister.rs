@@ -0,0 +1,261 @@
+//! Arbitrage assist against a sister exchange of the same token.
+//! Keepers sync reserves of the sister exchange, and while the quote is fresh, swaps that reduce
+//! the price gap between the two exchanges are charged the reduced `arb_fee`.
+
+use near_sdk::json_types::U64;
+use near_sdk::serde::Serialize;
+use near_sdk::{PromiseOrValue, PromiseResult};
+
+use crate::*;
+
+/// For how long synced reserves of the sister exchange are used for fee discounts: 1 minute.
+const SISTER_QUOTE_MAX_AGE: u64 = 60 * 1_000_000_000;
+const GAS_FOR_GET_RESERVES: Gas = 5_000_000_000_000;
+const GAS_FOR_ON_SISTER_RESERVES: Gas = 5_000_000_000_000;
+const GAS_FOR_GET_TOKEN_ACCOUNT_ID: Gas = 5_000_000_000_000;
+const GAS_FOR_ON_SISTER_TOKEN: Gas = 5_000_000_000_000;
+const GAP_BPS_DIVISOR: u32 = 10_000;
+
+/// Reserves of the sister exchange as of `timestamp`.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct SisterQuote {
+    pub(crate) near_amount: Balance,
+    pub(crate) token_amount: Balance,
+    pub(crate) timestamp: u64,
+}
+
+/// Price gap against the sister exchange.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SisterGap {
+    pub sister_account_id: AccountId,
+    pub sister_near_amount: U128,
+    pub sister_token_amount: U128,
+    /// How much more token per NEAR this exchange gives compared to the sister, in basis points.
+    /// Negative if this exchange gives less.
+    pub gap_bps: i64,
+    /// Time of the sister quote the gap is computed against.
+    pub timestamp: U64,
+    /// Whether the quote is fresh enough for the reduced fee to apply.
+    pub is_fresh: bool,
+}
+
+#[ext_contract(ext_exchange)]
+trait Exchange {
+    fn get_reserves(&self) -> (U128, U128);
+    fn get_token_account_id(&self) -> AccountId;
+}
+
+#[ext_contract(ext_self)]
+trait SelfCallbacks {
+    fn on_sister_reserves(&mut self, sister_account_id: AccountId);
+    fn on_sister_token(&mut self, sister_account_id: AccountId, arb_fee: u32);
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns NEAR and token reserves of this exchange.
+    pub fn get_reserves(&self) -> (U128, U128) {
+        (self.near_amount.into(), self.token_amount.into())
+    }
+
+    /// Returns account of the token this exchange trades against NEAR.
+    pub fn get_token_account_id(&self) -> AccountId {
+        self.token_account_id.clone()
+    }
+
+    /// Sets sister exchange of the same token and the fee for swaps that reduce the price gap with it.
+    /// The sister is set in the callback, once it confirmed that it trades the same token.
+    /// Only owner can call.
+    pub fn set_sister_exchange(
+        &mut self,
+        sister_account_id: Option<ValidAccountId>,
+        arb_fee: u32,
+    ) -> PromiseOrValue<()> {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "ERR_NOT_OWNER"
+        );
+        assert!(arb_fee <= self.fee, "ERR_ARB_FEE_TOO_LARGE");
+        match sister_account_id {
+            Some(sister_account_id) => ext_exchange::get_token_account_id(
+                sister_account_id.as_ref(),
+                NO_DEPOSIT,
+                GAS_FOR_GET_TOKEN_ACCOUNT_ID,
+            )
+            .then(ext_self::on_sister_token(
+                sister_account_id.into(),
+                arb_fee,
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_ON_SISTER_TOKEN,
+            ))
+            .into(),
+            None => {
+                self.internal_set_sister(None, arb_fee);
+                PromiseOrValue::Value(())
+            }
+        }
+    }
+
+    /// Callback with the token of the sister exchange, sets the sister if it's the same token.
+    pub fn on_sister_token(&mut self, sister_account_id: AccountId, arb_fee: u32) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        let token_account_id = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<AccountId>(&value).ok(),
+            _ => None,
+        };
+        self.internal_verify_sister_token(token_account_id);
+        self.internal_set_sister(Some(sister_account_id), arb_fee);
+    }
+
+    /// Fetches current reserves of the sister exchange. Can be called by anyone.
+    pub fn sync_sister(&mut self) -> Promise {
+        let sister_account_id = self
+            .sister_account_id
+            .clone()
+            .expect("ERR_NO_SISTER_EXCHANGE");
+        ext_exchange::get_reserves(&sister_account_id, NO_DEPOSIT, GAS_FOR_GET_RESERVES).then(
+            ext_self::on_sister_reserves(
+                sister_account_id,
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_ON_SISTER_RESERVES,
+            ),
+        )
+    }
+
+    /// Callback with reserves of the sister exchange.
+    pub fn on_sister_reserves(&mut self, sister_account_id: AccountId) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "ERR_NOT_SELF"
+        );
+        // Sister could have been changed while the request was in flight.
+        if self.sister_account_id.as_ref() != Some(&sister_account_id) {
+            return;
+        }
+        if let PromiseResult::Successful(value) = env::promise_result(0) {
+            if let Ok((near_amount, token_amount)) = serde_json::from_slice::<(U128, U128)>(&value)
+            {
+                self.sister_quote = Some(SisterQuote {
+                    near_amount: near_amount.into(),
+                    token_amount: token_amount.into(),
+                    timestamp: env::block_timestamp(),
+                });
+            }
+        }
+    }
+
+    /// Returns price gap against the last synced quote of the sister exchange.
+    pub fn get_sister_gap(&self) -> Option<SisterGap> {
+        let quote = self.sister_quote.as_ref()?;
+        Some(SisterGap {
+            sister_account_id: self.sister_account_id.clone().unwrap(),
+            sister_near_amount: quote.near_amount.into(),
+            sister_token_amount: quote.token_amount.into(),
+            gap_bps: self.gap_bps(quote, self.near_amount, self.token_amount)?,
+            timestamp: quote.timestamp.into(),
+            is_fresh: env::block_timestamp() <= quote.timestamp + SISTER_QUOTE_MAX_AGE,
+        })
+    }
+}
+
+impl Contract {
+    /// Panics unless the sister exchange reported the same token as this exchange trades.
+    pub(crate) fn internal_verify_sister_token(&self, token_account_id: Option<AccountId>) {
+        assert_eq!(
+            token_account_id.as_ref(),
+            Some(&self.token_account_id),
+            "ERR_SISTER_WRONG_TOKEN"
+        );
+    }
+
+    pub(crate) fn internal_set_sister(
+        &mut self,
+        sister_account_id: Option<AccountId>,
+        arb_fee: u32,
+    ) {
+        // Fee could have been changed while the request was in flight.
+        assert!(arb_fee <= self.fee, "ERR_ARB_FEE_TOO_LARGE");
+        self.sister_account_id = sister_account_id;
+        self.sister_quote = None;
+        self.arb_fee = arb_fee;
+    }
+
+    /// Gap between the price of given reserves and the sister quote in basis points.
+    /// `None` if either side has no liquidity.
+    fn gap_bps(
+        &self,
+        quote: &SisterQuote,
+        near_amount: Balance,
+        token_amount: Balance,
+    ) -> Option<i64> {
+        if near_amount == 0 || quote.near_amount == 0 || quote.token_amount == 0 {
+            return None;
+        }
+        let price = U256::from(token_amount) * U256::from(quote.near_amount);
+        let sister_price = U256::from(quote.token_amount) * U256::from(near_amount);
+        let (diff, negative) = if price >= sister_price {
+            (price - sister_price, false)
+        } else {
+            (sister_price - price, true)
+        };
+        let gap = diff * U256::from(GAP_BPS_DIVISOR) / sister_price;
+        // Saturates, as a gap that large means the quote is unusable anyway.
+        let gap = if gap > U256::from(i64::MAX as u64) {
+            i64::MAX
+        } else {
+            gap.as_u64() as i64
+        };
+        Some(if negative { -gap } else { gap })
+    }
+
+    /// Returns amount out for the swap and the fee it's charged, which is the reduced fee
+    /// if the swap reduces the price gap with the sister exchange.
+    pub(crate) fn get_swap_price(&self, amount_in: Balance, near_in: bool) -> (Balance, u32) {
+        let (input_reserve, output_reserve) = if near_in {
+            (self.near_amount, self.token_amount)
+        } else {
+            (self.token_amount, self.near_amount)
+        };
+        let amount_out = self.get_input_price(amount_in, input_reserve, output_reserve);
+        let quote = match self.sister_quote.as_ref() {
+            Some(quote)
+                if self.arb_fee < self.fee
+                    && env::block_timestamp() <= quote.timestamp + SISTER_QUOTE_MAX_AGE =>
+            {
+                quote
+            }
+            _ => return (amount_out, self.fee),
+        };
+        let arb_amount_out =
+            self.get_input_price_with_fee(amount_in, input_reserve, output_reserve, self.arb_fee);
+        let (near_after, token_after) = if near_in {
+            (
+                self.near_amount + amount_in,
+                self.token_amount - arb_amount_out,
+            )
+        } else {
+            (
+                self.near_amount - arb_amount_out,
+                self.token_amount + amount_in,
+            )
+        };
+        match (
+            self.gap_bps(quote, self.near_amount, self.token_amount),
+            self.gap_bps(quote, near_after, token_after),
+        ) {
+            (Some(gap_before), Some(gap_after)) if gap_after.abs() < gap_before.abs() => {
+                (arb_amount_out, self.arb_fee)
+            }
+            _ => (amount_out, self.fee),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Statistics of realized swap prices relative to the pre-trade spot price.
+
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+/// Number of the most recent swaps the rolling average is taken over.
+const SLIPPAGE_WINDOW: u64 = 100;
+const BPS_DIVISOR: u32 = 10_000;
+
+/// Slippage of swaps in basis points, including the fee.
+/// Slippage is how much less the swap returned compared to trading the whole amount at the spot price.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SlippageStats {
+    pub num_swaps: u64,
+    /// Rolling average over the last `SLIPPAGE_WINDOW` swaps.
+    pub average_bps: u32,
+    pub max_bps: u32,
+    pub last_bps: u32,
+}
+
+impl SlippageStats {
+    fn add(&mut self, slippage_bps: u32) {
+        self.num_swaps += 1;
+        let window = std::cmp::min(self.num_swaps, SLIPPAGE_WINDOW);
+        self.average_bps =
+            ((self.average_bps as u64 * (window - 1) + slippage_bps as u64) / window) as u32;
+        self.max_bps = std::cmp::max(self.max_bps, slippage_bps);
+        self.last_bps = slippage_bps;
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns slippage statistics over swaps in both directions.
+    pub fn get_slippage_stats(&self) -> SlippageStats {
+        self.slippage_stats.clone()
+    }
+}
+
+impl Contract {
+    /// Records slippage of the swap given reserves before it and logs realized price.
+    pub(crate) fn record_swap(
+        &mut self,
+        amount_in: Balance,
+        input_reserve: Balance,
+        output_reserve: Balance,
+        amount_out: Balance,
+    ) {
+        let spot_amount_out =
+            U256::from(amount_in) * U256::from(output_reserve) / U256::from(input_reserve);
+        let slippage_bps = if spot_amount_out.is_zero() {
+            0
+        } else {
+            (spot_amount_out.saturating_sub(U256::from(amount_out)) * U256::from(BPS_DIVISOR)
+                / spot_amount_out)
+                .as_u32()
+        };
+        env::log(
+            format!(
+                "Swapped {} for {}, spot price {}/{}, slippage {} bps",
+                amount_in, amount_out, output_reserve, input_reserve, slippage_bps
+            )
+            .as_bytes(),
+        );
+        self.slippage_stats.add(slippage_bps);
+    }
+}